@@ -0,0 +1,56 @@
+#[macro_use]
+extern crate criterion;
+extern crate bulletproofs_amcl;
+
+use criterion::Criterion;
+
+use amcl_wrapper::field_elem::FieldElement;
+use bulletproofs_amcl::r1cs::gadgets::helper_constraints::poseidon::{
+    poseidon_permutation_w3, PoseidonParams, Poseidon_permutation, Poseidon_permutation_owned,
+    SboxType,
+};
+
+/// Benchmark the Poseidon permutation for all widths and sboxes
+fn permutation_benchmark(c: &mut Criterion) {
+    for (width, partial_rounds) in vec![(3, 55), (5, 56), (9, 57)] {
+        let params = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+        let input = (0..width)
+            .map(|_| FieldElement::random())
+            .collect::<Vec<_>>();
+
+        let mut sboxes = vec![SboxType::Cube, SboxType::Quint];
+        #[cfg(feature = "inverse_sbox")]
+        sboxes.push(SboxType::Inverse);
+        for sbox in sboxes {
+            c.bench_function(
+                format!("permutation for width {} with sbox {:?}", width, sbox).as_str(),
+                |b| b.iter(|| Poseidon_permutation(&input, &params, &sbox)),
+            );
+        }
+    }
+}
+
+/// Benchmark the unrolled width 3 permutation against the generic one
+fn permutation_w3_benchmark(c: &mut Criterion) {
+    let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+    let input = [
+        FieldElement::random(),
+        FieldElement::random(),
+        FieldElement::random(),
+    ];
+    let sbox = SboxType::Quint;
+    c.bench_function("generic permutation for width 3", |b| {
+        b.iter(|| Poseidon_permutation_owned(input.to_vec(), &params, &sbox))
+    });
+    c.bench_function("unrolled permutation for width 3", |b| {
+        b.iter(|| poseidon_permutation_w3(input.clone(), &params, &sbox))
+    });
+}
+
+criterion_group!(
+    name = bench_permutation;
+    config = Criterion::default();
+    targets = permutation_benchmark, permutation_w3_benchmark
+);
+
+criterion_main!(bench_permutation);
@@ -53,6 +53,10 @@ pub enum BulletproofErrorKind {
     )]
     ParseErrorForPoseidonConstant { constant: String, error_msg: String },
 
+    /// Occurs when a checked Poseidon hash is given an input not less than the curve order
+    #[fail(display = "Input at index {} is not a canonical field element", index)]
+    NonCanonicalInputForPoseidon { index: usize },
+
     /// Occurs when Merkle tree hash is called with incorrect number of inputs
     #[fail(
         display = "Merkle tree hash is called with incorrect number of inputs. Expected {} inputs but found {}",
@@ -138,6 +142,13 @@ pub enum R1CSErrorKind {
         /// The description of the reasons for the error.
         description: String,
     },
+
+    /// Occurs when adding a gadget would take the circuit over its budget of multiplication gates
+    #[fail(
+        display = "Circuit needs {} multiplication gates but the budget is {}",
+        current, max
+    )]
+    GateBudgetExceeded { current: usize, max: usize },
 }
 
 #[derive(Debug)]
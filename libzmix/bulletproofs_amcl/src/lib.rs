@@ -13,6 +13,8 @@ extern crate serde_json;
 
 extern crate failure;
 
+extern crate zeroize;
+
 #[macro_use]
 pub mod errors;
 
@@ -0,0 +1,191 @@
+use super::helper_constraints::bit::bit_gadget;
+use super::helper_constraints::poseidon::{PoseidonParams, Poseidon_hash_n_constraints, SboxType};
+use crate::errors::{R1CSError, R1CSErrorKind};
+use crate::r1cs::linear_combination::AllocatedQuantity;
+use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
+use amcl_wrapper::field_elem::FieldElement;
+
+/*
+Proves that the Poseidon hash of a hidden preimage is the element at a hidden index of a public
+vector. Uses an indicator vector `toggles` with 1 at the index and 0 elsewhere. The constraints are
+
+  - ensure_bit(for each of toggles)
+  - sum(toggles) == 1
+  - sum(i * toggles[i]) == index, this also ensures index is in [0, vector.len())
+  - sum(vector[i] * toggles[i]) == hash(preimage)
+
+Since the vector is public, the last 3 are linear constraints.
+*/
+
+pub fn hash_at_index_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    preimage: Vec<Variable>,
+    index: AllocatedQuantity,
+    committed_vector: &[FieldElement],
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    if committed_vector.is_empty() {
+        return Err(R1CSErrorKind::GadgetError {
+            description: String::from("Vector should not be empty"),
+        }
+        .into());
+    }
+
+    let hash = Poseidon_hash_n_constraints::<CS>(
+        cs,
+        preimage.into_iter().map(|v| v.into()).collect(),
+        params,
+        sbox_type,
+    )?;
+
+    let mut toggle_sum = vec![(Variable::One(), FieldElement::minus_one())];
+    let mut index_sum = vec![(index.variable, FieldElement::minus_one())];
+    let mut selected = vec![];
+    for (i, item) in committed_vector.iter().enumerate() {
+        let i = FieldElement::from(i as u64);
+        let toggle_val = index.assignment.as_ref().map(|idx| {
+            if *idx == i {
+                FieldElement::one()
+            } else {
+                FieldElement::zero()
+            }
+        });
+        let toggle_var = cs.allocate(toggle_val.clone())?;
+        let toggle = AllocatedQuantity {
+            variable: toggle_var,
+            assignment: toggle_val,
+        };
+        bit_gadget(cs, &toggle)?;
+        toggle_sum.push((toggle_var, FieldElement::one()));
+        index_sum.push((toggle_var, i));
+        selected.push((toggle_var, item.clone()));
+    }
+    cs.constrain(toggle_sum.iter().collect());
+    cs.constrain(index_sum.iter().collect());
+    let selected: LinearCombination = selected.iter().collect();
+    cs.constrain(selected - hash);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::gadgets::helper_constraints::poseidon::Poseidon_hash_n;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    fn check_hash_at_index(
+        preimage: Vec<FieldElement>,
+        index: u64,
+        vector: &[FieldElement],
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 512).into();
+        let H: G1Vector = get_generators("H", 512).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let n = preimage.len();
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"HashAtIndex");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let mut comms = vec![];
+            let mut vars = vec![];
+            for p in preimage {
+                let (c, v) = prover.commit(p, FieldElement::random());
+                comms.push(c);
+                vars.push(v);
+            }
+            let index_val = FieldElement::from(index);
+            let (c, v) = prover.commit(index_val.clone(), FieldElement::random());
+            comms.push(c);
+            let index = AllocatedQuantity {
+                variable: v,
+                assignment: Some(index_val),
+            };
+            hash_at_index_gadget(&mut prover, vars, index, vector, params, sbox_type).unwrap();
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"HashAtIndex");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let vars = (0..n)
+            .map(|i| verifier.commit(commitments[i].clone()))
+            .collect::<Vec<_>>();
+        let index = AllocatedQuantity {
+            variable: verifier.commit(commitments[n].clone()),
+            assignment: None,
+        };
+        hash_at_index_gadget(&mut verifier, vars, index, vector, params, sbox_type).unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_hash_at_index() {
+        let width = 5;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let preimage = vec![
+            FieldElement::random(),
+            FieldElement::random(),
+            FieldElement::random(),
+        ];
+        let image = Poseidon_hash_n(preimage.clone(), &params, sbox_type).unwrap();
+        let mut vector = (0..8).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        vector[5] = image;
+
+        assert!(check_hash_at_index(
+            preimage.clone(),
+            5,
+            &vector,
+            &params,
+            sbox_type
+        ));
+        // Wrong index
+        assert!(!check_hash_at_index(
+            preimage.clone(),
+            4,
+            &vector,
+            &params,
+            sbox_type
+        ));
+        // Index out of range
+        assert!(!check_hash_at_index(
+            preimage.clone(),
+            8,
+            &vector,
+            &params,
+            sbox_type
+        ));
+        // Wrong preimage
+        let mut wrong_preimage = preimage;
+        wrong_preimage[1] = FieldElement::random();
+        assert!(!check_hash_at_index(
+            wrong_preimage,
+            5,
+            &vector,
+            &params,
+            sbox_type
+        ));
+    }
+}
@@ -1,8 +1,11 @@
 use crate::errors::R1CSError;
 use crate::r1cs::linear_combination::AllocatedQuantity;
 use crate::r1cs::{ConstraintSystem, LinearCombination};
+use amcl_wrapper::constants::CurveOrder;
 use amcl_wrapper::field_elem::FieldElement;
 
+use super::constrain_lc_with_scalar;
+
 // Ensure `v` is a bit, hence 0 or 1
 pub fn bit_gadget<CS: ConstraintSystem>(
     cs: &mut CS,
@@ -31,3 +34,60 @@ pub fn bit_gadget<CS: ConstraintSystem>(
 
     Ok(())
 }
+
+/// Decompose `v` into as many bits as the curve order, least significant bit first. Each bit is
+/// constrained with `bit_gadget` and the bits are constrained to represent a number less than the
+/// curve order so that the decomposition is unique, i.e. the bits are of `v` and not of `v + order`.
+pub fn canonical_bits_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v: LinearCombination,
+) -> Result<Vec<AllocatedQuantity>, R1CSError> {
+    let num_bits = CurveOrder.nbits();
+    let val = cs.evaluate_lc(&v);
+
+    let mut bits = vec![];
+    let mut constraint_v = vec![];
+    let mut exp_2 = FieldElement::one();
+    for i in 0..num_bits {
+        let assignment = val.as_ref().map(|v| {
+            if v.shift_right(i).is_odd() {
+                FieldElement::one()
+            } else {
+                FieldElement::zero()
+            }
+        });
+        let variable = cs.allocate(assignment.clone())?;
+        let bit = AllocatedQuantity {
+            variable,
+            assignment,
+        };
+        bit_gadget(cs, &bit)?;
+        constraint_v.push((variable, exp_2.clone()));
+        exp_2 = &exp_2 + &exp_2;
+        bits.push(bit);
+    }
+
+    // Enforce that Sum(b_i * 2^i, i = 0..num_bits-1) = v
+    let sum: LinearCombination = constraint_v.iter().collect();
+    cs.constrain(sum - v);
+
+    // Enforce that the bits are less than the order by comparing with the bits of the order
+    // starting from the most significant bit. `eq` is 1 when all compared bits are same and
+    // `lt` becomes 1 when a bit of the order is 1 and the bit of `v` is 0 while `eq` is 1.
+    let mut eq: LinearCombination = FieldElement::one().into();
+    let mut lt = LinearCombination::default();
+    for i in (0..num_bits).rev() {
+        let (_, _, eq_and_bit) = cs.multiply(eq.clone(), bits[i].variable.into());
+        if CurveOrder.bit(i) == 1 {
+            // eq*(1 - b_i)
+            lt = lt + eq - eq_and_bit;
+            eq = eq_and_bit.into();
+        } else {
+            // When all compared bits are same, bit of `v` can't be 1 when bit of order is 0
+            cs.constrain(eq_and_bit.into());
+        }
+    }
+    constrain_lc_with_scalar::<CS>(cs, lt, &FieldElement::one());
+
+    Ok(bits)
+}
@@ -10,6 +10,8 @@ pub mod bit;
 pub mod mimc;
 pub mod non_zero;
 pub mod poseidon;
+pub mod poseidon_merkle_tree;
+pub mod poseidon_sparse;
 pub mod positive_no;
 pub mod sparse_merkle_tree_4_ary;
 pub mod sparse_merkle_tree_8_ary;
@@ -24,6 +26,21 @@ pub fn constrain_lc_with_scalar<CS: ConstraintSystem>(
     cs.constrain(lc - LinearCombination::from(scalar.clone()));
 }
 
+/// Allocate a variable with the value of the given linear combination and constrain both to be
+/// equal. Used to pass a computed linear combination to gadgets expecting an `AllocatedQuantity`.
+pub fn allocate_lc_as_quantity<CS: ConstraintSystem>(
+    cs: &mut CS,
+    lc: LinearCombination,
+) -> Result<AllocatedQuantity, R1CSError> {
+    let assignment = cs.evaluate_lc(&lc);
+    let variable = cs.allocate(assignment.clone())?;
+    cs.constrain(lc - variable);
+    Ok(AllocatedQuantity {
+        variable,
+        assignment,
+    })
+}
+
 /// Get byte size of number in given `base` with `num_digits` digits in that base
 fn get_byte_size(num_digits: usize, base: u8) -> usize {
     let num_bits = get_bit_count(num_digits, base);
@@ -2,6 +2,8 @@ use crate::errors::R1CSError;
 use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
 use amcl_wrapper::field_elem::FieldElement;
 
+use super::allocate_lc_as_quantity;
+
 /// Enforces that x is not 0.
 /// Takes x and x_inv as input.
 /// The idea is described in the Pinocchio paper in section 3.2, "Zero-Equality Gate". Quoting the paper,
@@ -30,3 +32,87 @@ pub fn is_nonzero_gadget<CS: ConstraintSystem>(
 
     Ok(())
 }
+
+/// Enforces that the output of a hash is not 0. Allocates the output and its inverse as variables
+/// and applies `is_nonzero_gadget` to them.
+pub fn constrain_hash_nonzero_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    hash_output: LinearCombination,
+) -> Result<(), R1CSError> {
+    let output = allocate_lc_as_quantity(cs, hash_output)?;
+    // Prover assigns 0 as inverse of 0 which fails the constraints
+    let output_inv = cs.allocate(output.assignment.as_ref().map(|o| {
+        if o.is_zero() {
+            FieldElement::zero()
+        } else {
+            o.inverse()
+        }
+    }))?;
+    is_nonzero_gadget(cs, output.variable, output_inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::gadgets::helper_constraints::poseidon::{
+        PoseidonParams, Poseidon_hash_2, Poseidon_hash_2_constraints, SboxType, CAP_CONST_W_3,
+    };
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    #[test]
+    fn test_constrain_hash_nonzero() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 512).into();
+        let H: G1Vector = get_generators("H", 512).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let inputs = vec![FieldElement::random(), FieldElement::random()];
+        let hash = Poseidon_hash_2(inputs.clone(), &params, sbox_type).unwrap();
+
+        // Subtracting the hash from the output of the hash constraints forces the output to 0
+        for (offset, expected) in vec![(FieldElement::zero(), true), (hash, false)] {
+            let (proof, commitments) = {
+                let mut prover_transcript = Transcript::new(b"HashNonZero");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let (comms, vars): (Vec<_>, Vec<_>) = inputs
+                    .iter()
+                    .map(|i| prover.commit(i.clone(), FieldElement::random()))
+                    .unzip();
+                let output = Poseidon_hash_2_constraints(
+                    &mut prover,
+                    vars.into_iter().map(|v| v.into()).collect(),
+                    FieldElement::from(CAP_CONST_W_3).into(),
+                    &params,
+                    sbox_type,
+                )
+                .unwrap();
+                constrain_hash_nonzero_gadget(&mut prover, output - offset.clone()).unwrap();
+                (prover.prove(&G, &H).unwrap(), comms)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"HashNonZero");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let vars = commitments
+                .into_iter()
+                .map(|c| verifier.commit(c).into())
+                .collect();
+            let output = Poseidon_hash_2_constraints(
+                &mut verifier,
+                vars,
+                FieldElement::from(CAP_CONST_W_3).into(),
+                &params,
+                sbox_type,
+            )
+            .unwrap();
+            constrain_hash_nonzero_gadget(&mut verifier, output - offset).unwrap();
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+}
@@ -1,16 +1,146 @@
-use crate::errors::{BulletproofError, BulletproofErrorKind, R1CSError};
+use crate::errors::{BulletproofError, BulletproofErrorKind, R1CSError, R1CSErrorKind};
 use crate::r1cs::linear_combination::AllocatedQuantity;
 use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
+use amcl_wrapper::constants::{CurveOrder, MODBYTES};
 use amcl_wrapper::field_elem::FieldElement;
+use amcl_wrapper::group_elem::GroupElement;
+use amcl_wrapper::group_elem_g1::G1;
+use amcl_wrapper::types::BigNum;
+use zeroize::Zeroize;
 
-use super::super::helper_constraints::constrain_lc_with_scalar;
+#[cfg(feature = "inverse_sbox")]
 use super::super::helper_constraints::non_zero::is_nonzero_gadget;
+use super::super::helper_constraints::{allocate_lc_as_quantity, constrain_lc_with_scalar};
 use crate::r1cs::gadgets::poseidon_constants::*;
 
+/// Field operations used by the native permutation so that it can be used with field
+/// implementations other than `FieldElement` of `amcl_wrapper`. The constraints are only over
+/// `FieldElement`.
+pub trait PoseidonField: Clone + PartialEq + std::fmt::Debug {
+    fn zero() -> Self;
+    fn one() -> Self;
+    fn add(&self, b: &Self) -> Self;
+    fn mul(&self, b: &Self) -> Self;
+    fn square(&self) -> Self;
+    /// Inverse of the element, the inverse of 0 is 0
+    fn inverse(&self) -> Self;
+    fn is_zero(&self) -> bool;
+    /// Parse a hex string without the "0x" prefix. Should reject values not less than the
+    /// field's modulus.
+    fn from_hex(hex_str: &str) -> Result<Self, BulletproofError>;
+}
+
+impl PoseidonField for FieldElement {
+    fn zero() -> Self {
+        FieldElement::zero()
+    }
+
+    fn one() -> Self {
+        FieldElement::one()
+    }
+
+    fn add(&self, b: &Self) -> Self {
+        self + b
+    }
+
+    fn mul(&self, b: &Self) -> Self {
+        #[cfg(feature = "poseidon_instrumentation")]
+        instrumentation::count_mul();
+        self * b
+    }
+
+    fn square(&self) -> Self {
+        #[cfg(feature = "poseidon_instrumentation")]
+        instrumentation::count_mul();
+        FieldElement::square(self)
+    }
+
+    fn inverse(&self) -> Self {
+        #[cfg(feature = "poseidon_instrumentation")]
+        instrumentation::count_inv();
+        FieldElement::inverse(self)
+    }
+
+    fn is_zero(&self) -> bool {
+        FieldElement::is_zero(self)
+    }
+
+    fn from_hex(hex_str: &str) -> Result<Self, BulletproofError> {
+        // `FieldElement::from_hex` reduces values not less than the curve order so a mistyped
+        // constant would be silently accepted.
+        if !is_less_than_curve_order(hex_str) {
+            return Err(BulletproofErrorKind::ParseErrorForPoseidonConstant {
+                constant: hex_str.to_string(),
+                error_msg: String::from("Constant is not less than the curve order"),
+            }
+            .into());
+        }
+        FieldElement::from_hex(hex_str.to_string()).map_err(|_| {
+            BulletproofError::from(BulletproofErrorKind::ParseErrorForPoseidonConstant {
+                constant: hex_str.to_string(),
+                error_msg: String::from("Cannot convert to hex"),
+            })
+        })
+    }
+}
+
+/// Number of field operations done by the native permutation on `FieldElement`s
+#[cfg(feature = "poseidon_instrumentation")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FieldOpCounts {
+    /// Multiplications including squarings
+    pub multiplications: usize,
+    pub inversions: usize,
+}
+
+// Per thread counters incremented by `impl PoseidonField for FieldElement`
+#[cfg(feature = "poseidon_instrumentation")]
+mod instrumentation {
+    use super::FieldOpCounts;
+    use std::cell::Cell;
+
+    thread_local! {
+        static COUNTS: Cell<FieldOpCounts> = Cell::new(FieldOpCounts::default());
+    }
+
+    pub(super) fn count_mul() {
+        COUNTS.with(|c| {
+            let mut counts = c.get();
+            counts.multiplications += 1;
+            c.set(counts)
+        })
+    }
+
+    pub(super) fn count_inv() {
+        COUNTS.with(|c| {
+            let mut counts = c.get();
+            counts.inversions += 1;
+            c.set(counts)
+        })
+    }
+
+    /// Returns the counts so far and resets them
+    pub(super) fn take() -> FieldOpCounts {
+        COUNTS.with(|c| c.replace(FieldOpCounts::default()))
+    }
+}
+
+/// Compare hex string (without "0x") with the curve order. Leading zeros and case are ignored.
+fn is_less_than_curve_order(hex_str: &str) -> bool {
+    let normalize = |s: &str| s.trim_start_matches('0').to_lowercase();
+    let c = normalize(hex_str);
+    let order = normalize(&CurveOrder.tostring());
+    if c.len() != order.len() {
+        return c.len() < order.len();
+    }
+    // Same length hex strings compare as the numbers they encode
+    c < order
+}
+
 // Poseidon is described here https://eprint.iacr.org/2019/458
 // TODO: Give a overview of Poseidon construction relative of the code.
 #[derive(Clone, Debug)]
-pub struct PoseidonParams {
+pub struct PoseidonParams<F: PoseidonField = FieldElement> {
     pub width: usize,
     // Number of full SBox rounds in beginning
     pub full_rounds_beginning: usize,
@@ -18,19 +148,548 @@ pub struct PoseidonParams {
     pub full_rounds_end: usize,
     // Number of partial SBox rounds in beginning
     pub partial_rounds: usize,
-    pub round_keys: Vec<FieldElement>,
-    pub MDS_matrix: Vec<Vec<FieldElement>>,
+    // Round keys of all rounds, `width` keys per round. Changing them has to go through
+    // `set_round_keys` to update `round_keys_by_round`.
+    pub round_keys: Vec<F>,
+    pub MDS_matrix: Vec<Vec<F>>,
+    // Positions of the state the Sbox is applied to in partial rounds, `[width - 1]` by default
+    pub partial_sbox_positions: Vec<usize>,
+    // `round_keys` split per round, computed once at construction so that the permutation
+    // indexes the keys of a round instead of offsetting into `round_keys`
+    round_keys_by_round: Vec<Vec<F>>,
 }
 
-/// Read the defined round keys and MDS matrix for the corresponding curve from file `poseidon_constants.rs`.
-/// Reads constants `ROUND_CONSTS_W_<width>` and `MDS_ENTRIES_W_<width>`
 impl PoseidonParams {
+    /// Read the defined round keys and MDS matrix for the corresponding curve from file `poseidon_constants.rs`.
+    /// Reads constants `ROUND_CONSTS_W_<width>` and `MDS_ENTRIES_W_<width>`
     pub fn new(
         width: usize,
         full_rounds_beginning: usize,
         full_rounds_end: usize,
         partial_rounds: usize,
     ) -> Result<PoseidonParams, BulletproofError> {
+        Self::new_for_field(
+            width,
+            full_rounds_beginning,
+            full_rounds_end,
+            partial_rounds,
+        )
+    }
+
+    /// Same as `new` but first checks that the rounds are enough for `POSEIDON_SECURITY_BITS` bits
+    /// of security with the given width and Sbox as per `min_secure_rounds`. Errors with the
+    /// minimum rounds otherwise. `new` does not check this so that reduced round params can be
+    /// created.
+    pub fn new_validated(
+        width: usize,
+        full_rounds_beginning: usize,
+        full_rounds_end: usize,
+        partial_rounds: usize,
+        sbox: &SboxType,
+    ) -> Result<PoseidonParams, R1CSError> {
+        let full_rounds = full_rounds_beginning + full_rounds_end;
+        let (min_full, min_partial) = Self::min_secure_rounds(width, full_rounds, sbox);
+        if full_rounds < min_full || partial_rounds < min_partial {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Rounds ({}, {}, {}) are insecure for width {} and sbox {:?}, need at least {} full rounds and with {} full rounds at least {} partial rounds",
+                    full_rounds_beginning,
+                    full_rounds_end,
+                    partial_rounds,
+                    width,
+                    sbox,
+                    min_full,
+                    full_rounds,
+                    min_partial
+                ),
+            }
+            .into());
+        }
+        Self::new(
+            width,
+            full_rounds_beginning,
+            full_rounds_end,
+            partial_rounds,
+        )
+        .map_err(|e| {
+            R1CSErrorKind::GadgetError {
+                description: e.to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// Minimum number of full rounds and, given `full_rounds` full rounds, minimum number of
+    /// partial rounds for `POSEIDON_SECURITY_BITS` bits of security, including the security margin.
+    /// The bounds are those of `calc_round_numbers.py` in `r1cs/gadgets/sage`: rounds `(R_F, R_P)`
+    /// are secure if they satisfy the bounds of `rounds_satisfy_bounds`, and the margin adds 2 full
+    /// rounds and 7.5% partial rounds, rounded up. So for `full_rounds` full rounds the partial
+    /// rounds are the least ones satisfying the bounds with `full_rounds - 2` full rounds, with the
+    /// margin.
+    pub fn min_secure_rounds(width: usize, full_rounds: usize, sbox: &SboxType) -> (usize, usize) {
+        let min_full = Self::statistical_full_rounds(width, sbox) + 2;
+        let min_partial = Self::least_partial_rounds(width, full_rounds.saturating_sub(2), sbox);
+        (min_full, Self::partial_rounds_with_margin(min_partial))
+    }
+
+    /// Rounds `(R_F, R_P)`, including the security margin, with the least number of Sboxes
+    /// `t * R_F + R_P` for the given width and Sbox. This is the recommendation of
+    /// `calc_round_numbers.py` which searches even `R_F` below 200 and prefers fewer full rounds
+    /// on a tie.
+    pub fn recommended_rounds(width: usize, sbox: &SboxType) -> (usize, usize) {
+        let mut best: Option<(usize, usize)> = None;
+        for full_rounds in (Self::statistical_full_rounds(width, sbox)..200).step_by(2) {
+            let partial_rounds = Self::least_partial_rounds(width, full_rounds, sbox);
+            let rounds = (
+                full_rounds + 2,
+                Self::partial_rounds_with_margin(partial_rounds),
+            );
+            let cost = |(f, p): (usize, usize)| width * f + p;
+            if best.map_or(true, |b| cost(rounds) < cost(b)) {
+                best = Some(rounds);
+            }
+        }
+        best.unwrap()
+    }
+
+    /// Full rounds needed against statistical attacks, without the margin. With `M` the security
+    /// level, `n` the bits of the field, `t` the width and `N = n * t`, this is 6 if
+    /// `t + 1 <= N + n - M` for the cube Sbox or `2 * (t + 1) <= N + n - M` for others, else 10.
+    fn statistical_full_rounds(width: usize, sbox: &SboxType) -> usize {
+        let n = CurveOrder.nbits();
+        let big_n = n * width;
+        let lhs = match sbox {
+            SboxType::Cube => width + 1,
+            _ => 2 * (width + 1),
+        };
+        if lhs + POSEIDON_SECURITY_BITS <= big_n + n {
+            6
+        } else {
+            10
+        }
+    }
+
+    /// Least number of partial rounds, at least 1, satisfying `rounds_satisfy_bounds` with
+    /// `full_rounds` full rounds. Does not include the margin.
+    fn least_partial_rounds(width: usize, full_rounds: usize, sbox: &SboxType) -> usize {
+        // All bounds get weaker with more partial rounds so this terminates
+        (1..)
+            .find(|p| Self::rounds_satisfy_bounds(width, full_rounds, *p, sbox))
+            .unwrap()
+    }
+
+    /// Whether `full_rounds` and `partial_rounds` satisfy the interpolation and Groebner basis
+    /// bounds of `calc_round_numbers.py`, without the margin. With `M` the security level, `n`
+    /// the bits of the field and `t` the width, each `R_F` must be at least the ceiling of:
+    /// - cube Sbox: `0.63 * min(n, M) + log_2(t) - R_P`, `0.32 * min(n, M) - R_P` and
+    ///   `(0.18 * min(n, M) - 1 - R_P) / (t - 1)`
+    /// - quint Sbox: `0.43 * min(n, M) + log_2(t) - R_P`, `0.21 * min(n, M) - R_P` and
+    ///   `(0.14 * min(n, M) - 1 - R_P) / (t - 1)`
+    /// - inverse Sbox: `(log_2(t) + 0.5 * min(n, M) - R_P) / log_2(t)` and
+    ///   `(0.25 * min(n, M) - 1 - R_P) / (t - 1)`, and `R_P` must be at least the ceiling of
+    ///   `2 + log_2(t) + min(n, M) - log_2(t) * R_F`
+    /// The script's bound for binary fields does not apply.
+    fn rounds_satisfy_bounds(
+        width: usize,
+        full_rounds: usize,
+        partial_rounds: usize,
+        sbox: &SboxType,
+    ) -> bool {
+        let min_n_m = (CurveOrder.nbits().min(POSEIDON_SECURITY_BITS)) as f64;
+        let t = width as f64;
+        let log_t = t.log2();
+        let rf = full_rounds as f64;
+        let rp = partial_rounds as f64;
+        let bounds = match sbox {
+            SboxType::Cube => vec![
+                0.63 * min_n_m + log_t - rp,
+                0.32 * min_n_m - rp,
+                (0.18 * min_n_m - 1.0 - rp) / (t - 1.0),
+            ],
+            SboxType::Quint => vec![
+                0.43 * min_n_m + log_t - rp,
+                0.21 * min_n_m - rp,
+                (0.14 * min_n_m - 1.0 - rp) / (t - 1.0),
+            ],
+            #[cfg(feature = "inverse_sbox")]
+            SboxType::Inverse => {
+                if rp < (2.0 + log_t + min_n_m - log_t * rf).ceil() {
+                    return false;
+                }
+                vec![
+                    (log_t + 0.5 * min_n_m - rp) / log_t,
+                    (0.25 * min_n_m - 1.0 - rp) / (t - 1.0),
+                ]
+            }
+        };
+        bounds.iter().all(|b| rf >= b.ceil())
+    }
+
+    /// Add the margin of 7.5% to `partial_rounds`, rounded up
+    fn partial_rounds_with_margin(partial_rounds: usize) -> usize {
+        (partial_rounds * 1075 + 999) / 1000
+    }
+
+    /// Check that the round keys and MDS matrix are the canonical ones by comparing their digest
+    /// with the digest `PARAMS_DIGEST_W_<width>`. The digest is the output of `PoseidonSponge`
+    /// using these params and quint sbox after absorbing the round keys and then the rows of the
+    /// MDS matrix. Only canonical params, i.e. ones using all the round constants of the table,
+    /// can pass this check.
+    pub fn verify_integrity(&self) -> bool {
+        let expected = match self.width {
+            3 => PARAMS_DIGEST_W_3,
+            5 => PARAMS_DIGEST_W_5,
+            9 => PARAMS_DIGEST_W_9,
+            _ => return false,
+        };
+        let expected = match Self::get_field_element_from_hex_str(expected) {
+            Ok(e) => e,
+            Err(_) => return false,
+        };
+        let sbox = SboxType::Quint;
+        let mut sponge = match PoseidonSponge::new(self, &sbox, 1) {
+            Ok(s) => s,
+            Err(_) => return false,
+        };
+        sponge.absorb(&self.round_keys);
+        for row in &self.MDS_matrix {
+            sponge.absorb(row);
+        }
+        sponge.squeeze(1)[0] == expected
+    }
+
+    /// Fingerprint of the params as a single field element. The width, number of rounds, round
+    /// keys and rows of the MDS matrix are absorbed, in that order, in a `PoseidonSponge` using the
+    /// canonical width 3 params with rounds (4, 4, 55) and quint sbox, and the first squeezed
+    /// element is the fingerprint. Non default partial Sbox positions are absorbed last, preceded
+    /// by their count. Not available for secp256k1 which lacks width 3 constants.
+    #[cfg(any(feature = "bls381", feature = "bn254", feature = "ed25519"))]
+    pub fn fingerprint(&self) -> FieldElement {
+        // The constant tables of width 3 have enough constants for these rounds
+        let hasher = Self::new(3, 4, 4, 55).unwrap();
+        let sbox = SboxType::Quint;
+        let mut sponge = PoseidonSponge::new(&hasher, &sbox, 1).unwrap();
+        sponge.absorb(&[
+            FieldElement::from(self.width as u64),
+            FieldElement::from(self.full_rounds_beginning as u64),
+            FieldElement::from(self.full_rounds_end as u64),
+            FieldElement::from(self.partial_rounds as u64),
+        ]);
+        sponge.absorb(&self.round_keys);
+        for row in &self.MDS_matrix {
+            sponge.absorb(row);
+        }
+        if !self.has_default_partial_sbox_positions() {
+            sponge.absorb(&[FieldElement::from(self.partial_sbox_positions.len() as u64)]);
+            for p in &self.partial_sbox_positions {
+                sponge.absorb(&[FieldElement::from(*p as u64)]);
+            }
+        }
+        sponge.squeeze(1).remove(0)
+    }
+
+    /// Version of the serialization format, written as the first byte by `to_bytes`
+    pub const SERIALIZATION_VERSION: u8 = 1;
+
+    /// Version of the serialization format for params with non default partial Sbox positions
+    pub const SERIALIZATION_VERSION_PARTIAL_SBOX_POSITIONS: u8 = 2;
+
+    /// Serialize as the version byte, the width and the number of full rounds in beginning, full
+    /// rounds in end and partial rounds each as 8 bytes in big endian, then the round keys and
+    /// then the rows of the MDS matrix, each element being of `MODBYTES` bytes. Params with non
+    /// default partial Sbox positions use version `SERIALIZATION_VERSION_PARTIAL_SBOX_POSITIONS`
+    /// and have the number of positions and then the positions appended, each as 8 bytes in big
+    /// endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let default_positions = self.has_default_partial_sbox_positions();
+        let mut bytes = vec![if default_positions {
+            Self::SERIALIZATION_VERSION
+        } else {
+            Self::SERIALIZATION_VERSION_PARTIAL_SBOX_POSITIONS
+        }];
+        for n in &[
+            self.width,
+            self.full_rounds_beginning,
+            self.full_rounds_end,
+            self.partial_rounds,
+        ] {
+            bytes.extend_from_slice(&(*n as u64).to_be_bytes());
+        }
+        for k in &self.round_keys {
+            bytes.extend_from_slice(&k.to_bytes());
+        }
+        for row in &self.MDS_matrix {
+            for e in row {
+                bytes.extend_from_slice(&e.to_bytes());
+            }
+        }
+        if !default_positions {
+            bytes.extend_from_slice(&(self.partial_sbox_positions.len() as u64).to_be_bytes());
+            for p in &self.partial_sbox_positions {
+                bytes.extend_from_slice(&(*p as u64).to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Deserialize bytes returned by `to_bytes`. Only checks that the bytes are well formed, use
+    /// `validate` to check the params or `from_trusted_blob` for params from an untrusted source.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, R1CSError> {
+        let malformed = |description: String| -> R1CSError {
+            R1CSErrorKind::GadgetError { description }.into()
+        };
+        if bytes.is_empty()
+            || (bytes[0] != Self::SERIALIZATION_VERSION
+                && bytes[0] != Self::SERIALIZATION_VERSION_PARTIAL_SBOX_POSITIONS)
+        {
+            return Err(malformed(String::from(
+                "Unknown version of serialized Poseidon params",
+            )));
+        }
+        if bytes.len() < 33 {
+            return Err(malformed(format!(
+                "Invalid length {} of serialized Poseidon params",
+                bytes.len()
+            )));
+        }
+        let mut header = [0usize; 4];
+        for (i, h) in header.iter_mut().enumerate() {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&bytes[1 + 8 * i..9 + 8 * i]);
+            *h = u64::from_be_bytes(b) as usize;
+        }
+        let [width, full_rounds_beginning, full_rounds_end, partial_rounds] = header;
+
+        // Number of round keys and MDS entries, checked since the header is untrusted
+        let counts = full_rounds_beginning
+            .checked_add(full_rounds_end)
+            .and_then(|r| r.checked_add(partial_rounds))
+            .and_then(|r| r.checked_mul(width))
+            .and_then(|k| width.checked_mul(width).map(|m| (k, m)));
+        let elems_end = counts
+            .and_then(|(k, m)| k.checked_add(m))
+            .and_then(|n| n.checked_mul(MODBYTES))
+            .and_then(|n| n.checked_add(33));
+        let (num_keys, elems_end) = match (counts, elems_end) {
+            (Some((k, _)), Some(l)) if l <= bytes.len() && width > 0 => (k, l),
+            _ => {
+                return Err(malformed(format!(
+                    "Invalid length {} of serialized Poseidon params",
+                    bytes.len()
+                )))
+            }
+        };
+
+        // Trailing partial Sbox positions, only present in version 2
+        let partial_sbox_positions = if bytes[0] == Self::SERIALIZATION_VERSION {
+            if elems_end != bytes.len() {
+                return Err(malformed(format!(
+                    "Invalid length {} of serialized Poseidon params",
+                    bytes.len()
+                )));
+            }
+            vec![width - 1]
+        } else {
+            let trailer = &bytes[elems_end..];
+            let read_u64 = |b: &[u8]| {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(b);
+                u64::from_be_bytes(buf) as usize
+            };
+            if trailer.len() < 8 {
+                return Err(malformed(String::from(
+                    "Missing partial Sbox positions of serialized Poseidon params",
+                )));
+            }
+            let num_positions = read_u64(&trailer[..8]);
+            if num_positions.checked_mul(8) != Some(trailer.len() - 8) {
+                return Err(malformed(format!(
+                    "Invalid length {} of serialized Poseidon params",
+                    bytes.len()
+                )));
+            }
+            trailer[8..].chunks(8).map(read_u64).collect()
+        };
+
+        let mut elems = vec![];
+        for chunk in bytes[33..elems_end].chunks(MODBYTES) {
+            elems.push(FieldElement::from_bytes(chunk).map_err(|_| {
+                malformed(String::from(
+                    "Cannot parse element of serialized Poseidon params",
+                ))
+            })?);
+        }
+        let MDS_matrix = elems[num_keys..]
+            .chunks(width)
+            .map(|row| row.to_vec())
+            .collect();
+        elems.truncate(num_keys);
+        let round_keys_by_round = split_round_keys(&elems, width);
+        Ok(Self {
+            width,
+            full_rounds_beginning,
+            full_rounds_end,
+            partial_rounds,
+            round_keys: elems,
+            MDS_matrix,
+            partial_sbox_positions,
+            round_keys_by_round,
+        })
+    }
+
+    /// Check that the params are usable, i.e. the width is at least 2, there is a full round,
+    /// there is a partial round when the width is more than 3, there are `width` round keys for
+    /// each round, the MDS matrix is a `width x width` invertible matrix and the partial Sbox
+    /// positions are distinct positions of the state. Does not check that the params are
+    /// canonical, see `verify_integrity`.
+    /// With no partial rounds, the security has to come from full rounds alone which needs many
+    /// more Sboxes than the round counts used here account for, so for wide params it is most
+    /// likely a misconfiguration. Params with only full rounds, e.g. reduced round params for
+    /// cryptanalysis, should not be checked with this.
+    pub fn validate(&self) -> Result<(), R1CSError> {
+        let invalid = |description: String| -> Result<(), R1CSError> {
+            Err(R1CSErrorKind::GadgetError { description }.into())
+        };
+        if self.width < 2 {
+            return invalid(format!("Width should be at least 2 but is {}", self.width));
+        }
+        if self.full_rounds_beginning + self.full_rounds_end == 0 {
+            return invalid(String::from("There should be at least 1 full round"));
+        }
+        if self.partial_rounds == 0 && self.width > 3 {
+            return invalid(format!(
+                "There should be at least 1 partial round for width {}",
+                self.width
+            ));
+        }
+        self.check_shape()?;
+        if self.mds_determinant().is_zero() {
+            return invalid(String::from("MDS matrix is not invertible"));
+        }
+        Ok(())
+    }
+
+    /// Deserialize params received from an untrusted source and check them against a fingerprint
+    /// obtained from a trusted source. Errors if the bytes are malformed, the params are invalid
+    /// as per `validate` or the fingerprint of the params is not `expected_fingerprint`.
+    #[cfg(any(feature = "bls381", feature = "bn254", feature = "ed25519"))]
+    pub fn from_trusted_blob(
+        bytes: &[u8],
+        expected_fingerprint: FieldElement,
+    ) -> Result<PoseidonParams, R1CSError> {
+        let params = Self::from_bytes(bytes)?;
+        params.validate()?;
+        if params.fingerprint() != expected_fingerprint {
+            return Err(R1CSErrorKind::GadgetError {
+                description: String::from("Fingerprint of Poseidon params does not match"),
+            }
+            .into());
+        }
+        Ok(params)
+    }
+
+    /// Params with round keys and MDS matrix generated from an RNG seeded with `seed`, so the
+    /// same seed gives the same params. Uses 4 full rounds in beginning and end and 56 partial
+    /// rounds. The MDS matrix is a Cauchy matrix `M[i][j] = 1/(x_i + y_j)` which is invertible for
+    /// distinct `x_i`s, distinct `y_j`s and non-zero `x_i + y_j`, the points are sampled again in
+    /// the unlikely case the matrix is singular. Only for tests, these params are not secure.
+    #[cfg(test)]
+    pub fn random_for_testing(width: usize, seed: u64) -> PoseidonParams {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        assert!(width >= 2, "Width should be at least 2");
+        let (full_rounds_beginning, full_rounds_end, partial_rounds) = (4, 4, 56);
+        let total_rounds = full_rounds_beginning + full_rounds_end + partial_rounds;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let round_keys = (0..total_rounds * width)
+            .map(|_| FieldElement::random_using_rng(&mut rng))
+            .collect::<Vec<_>>();
+        let round_keys_by_round = split_round_keys(&round_keys, width);
+        let mut params = PoseidonParams {
+            width,
+            full_rounds_beginning,
+            full_rounds_end,
+            partial_rounds,
+            round_keys,
+            MDS_matrix: vec![],
+            partial_sbox_positions: vec![width - 1],
+            round_keys_by_round,
+        };
+        loop {
+            let x = (0..width)
+                .map(|_| FieldElement::random_using_rng(&mut rng))
+                .collect::<Vec<_>>();
+            let y = (0..width)
+                .map(|_| FieldElement::random_using_rng(&mut rng))
+                .collect::<Vec<_>>();
+            params.MDS_matrix = x
+                .iter()
+                .map(|x_i| y.iter().map(|y_j| (x_i + y_j).inverse()).collect())
+                .collect();
+            if params.validate().is_ok() {
+                return params;
+            }
+        }
+    }
+
+    /// Determinant of the MDS matrix, computed with gaussian elimination. A non-zero determinant
+    /// means the matrix is invertible which is necessary (not sufficient) for it to be MDS.
+    pub fn mds_determinant(&self) -> FieldElement {
+        let mut m = self.MDS_matrix.clone();
+        let n = m.len();
+        let mut det = FieldElement::one();
+        for col in 0..n {
+            // Find a row with non-zero entry in this column and swap it in place
+            let pivot = match (col..n).find(|&r| !m[r][col].is_zero()) {
+                Some(p) => p,
+                None => return FieldElement::zero(),
+            };
+            if pivot != col {
+                m.swap(pivot, col);
+                det = det.negation();
+            }
+            det = &det * &m[col][col];
+            let inv = m[col][col].inverse();
+            for r in col + 1..n {
+                let factor = &m[r][col] * &inv;
+                for c in col..n {
+                    let t = &factor * &m[col][c];
+                    m[r][c] = &m[r][c] - &t;
+                }
+            }
+        }
+        det
+    }
+
+    /// Round keys and MDS matrix as `0x` prefixed lowercase hex strings zero padded to the byte
+    /// length of the curve order, the format of the constants in `poseidon_constants.rs`, so that
+    /// generated params can be diffed textually against the hardcoded tables.
+    pub fn to_hex_tables(&self) -> (Vec<String>, Vec<Vec<String>>) {
+        let num_hex_chars = (CurveOrder.nbits() + 7) / 8 * 2;
+        let to_hex = |e: &FieldElement| {
+            let hex = e.to_hex().trim_start_matches('0').to_lowercase();
+            format!("0x{:0>width$}", hex, width = num_hex_chars)
+        };
+        let round_keys = self.round_keys.iter().map(to_hex).collect();
+        let mds = self
+            .MDS_matrix
+            .iter()
+            .map(|row| row.iter().map(to_hex).collect())
+            .collect();
+        (round_keys, mds)
+    }
+}
+
+impl<F: PoseidonField> PoseidonParams<F> {
+    /// Same as `new` but for any field implementing `PoseidonField`, the constants are parsed
+    /// with `PoseidonField::from_hex`
+    pub fn new_for_field(
+        width: usize,
+        full_rounds_beginning: usize,
+        full_rounds_end: usize,
+        partial_rounds: usize,
+    ) -> Result<Self, BulletproofError> {
         if width != 3 && width != 5 && width != 9 {
             return Err(BulletproofErrorKind::UnacceptableWidthForPoseidon {
                 width,
@@ -40,22 +699,134 @@ impl PoseidonParams {
         }
         let total_rounds = full_rounds_beginning + partial_rounds + full_rounds_end;
         let round_keys = Self::get_round_keys(width, total_rounds)?;
+        let round_keys_by_round = split_round_keys(&round_keys, width);
         let matrix_2 = Self::get_MDS_matrix(width)?;
-        Ok(PoseidonParams {
+        Ok(Self {
             width,
             full_rounds_beginning,
             full_rounds_end,
             partial_rounds,
             round_keys,
             MDS_matrix: matrix_2,
+            partial_sbox_positions: vec![width - 1],
+            round_keys_by_round,
         })
     }
 
+    /// Check that there are `width` round keys for each round, the MDS matrix is `width x width`
+    /// and the partial Sbox positions are distinct positions of the state, i.e. that the
+    /// permutation will not index out of any table
+    fn check_shape(&self) -> Result<(), R1CSError> {
+        let invalid = |description: String| -> Result<(), R1CSError> {
+            Err(R1CSErrorKind::GadgetError { description }.into())
+        };
+        let total_rounds = self.full_rounds_beginning + self.full_rounds_end + self.partial_rounds;
+        if self.round_keys.len() != total_rounds * self.width {
+            return invalid(format!(
+                "Round keys should be {} for {} rounds but are {}",
+                total_rounds * self.width,
+                total_rounds,
+                self.round_keys.len()
+            ));
+        }
+        if self
+            .round_keys_by_round
+            .iter()
+            .flatten()
+            .ne(self.round_keys.iter())
+        {
+            return invalid(String::from(
+                "Round keys were changed without `set_round_keys`",
+            ));
+        }
+        if self.MDS_matrix.len() != self.width
+            || self.MDS_matrix.iter().any(|row| row.len() != self.width)
+        {
+            return invalid(format!(
+                "MDS matrix should be of {} rows and columns",
+                self.width
+            ));
+        }
+        let positions = &self.partial_sbox_positions;
+        if positions.is_empty()
+            || positions.iter().any(|p| *p >= self.width)
+            || positions
+                .iter()
+                .enumerate()
+                .any(|(i, p)| positions[i + 1..].contains(p))
+        {
+            return invalid(format!(
+                "Partial Sbox positions {:?} should be distinct and less than width {}",
+                positions, self.width
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether the Sbox of partial rounds is applied only to the last position of the state
+    pub fn has_default_partial_sbox_positions(&self) -> bool {
+        self.partial_sbox_positions.len() == 1 && self.partial_sbox_positions[0] + 1 == self.width
+    }
+
+    /// The `width` round keys of round `round`. Panics if there are not enough round keys for
+    /// the round.
+    pub fn round_keys_of(&self, round: usize) -> &[F] {
+        &self.round_keys_by_round[round]
+    }
+
+    /// Round keys split per round, `round_keys_by_round()[r]` is `round_keys_of(r)`. The last
+    /// element has less than `width` keys if the number of round keys is not a multiple of the
+    /// width.
+    pub fn round_keys_by_round(&self) -> &[Vec<F>] {
+        &self.round_keys_by_round
+    }
+
+    /// Replace the round keys, `width` keys per round. Changing `round_keys` directly does not
+    /// change the keys used by the permutation and makes `validate` fail.
+    pub fn set_round_keys(&mut self, round_keys: Vec<F>) {
+        self.round_keys_by_round = split_round_keys(&round_keys, self.width);
+        self.round_keys = round_keys;
+    }
+
+    /// Round key of position `pos` in round `round`, i.e. `round_keys[round * width + pos]`, or
+    /// `None` when the round or position is out of range
+    pub fn round_key(&self, round: usize, pos: usize) -> Option<&F> {
+        if pos >= self.width {
+            return None;
+        }
+        round
+            .checked_mul(self.width)
+            .and_then(|i| i.checked_add(pos))
+            .and_then(|i| self.round_keys.get(i))
+    }
+
+    /// The permutation with these params and `sbox` as a function value, for code generic over
+    /// the permutation. Calling it is same as calling `Poseidon_permutation`.
+    pub fn permutation_fn(&self, sbox: SboxType) -> impl Fn(&[F]) -> Vec<F> + '_ {
+        move |input| Poseidon_permutation(input, self, &sbox)
+    }
+
+    /// Trim the round keys to `total_rounds * width` and the MDS matrix to `width x width`,
+    /// dropping any extra entries of tables built by hand, and release the unused capacity of
+    /// the vectors. This is a memory optimization for params kept around for long, the hashes
+    /// are unchanged as the extra entries are never used. Tables smaller than needed are left
+    /// as they are.
+    pub fn shrink_to_fit(&mut self) {
+        let total_rounds = self.full_rounds_beginning + self.partial_rounds + self.full_rounds_end;
+        self.round_keys.truncate(total_rounds * self.width);
+        self.round_keys.shrink_to_fit();
+        self.round_keys_by_round.truncate(total_rounds);
+        self.round_keys_by_round.shrink_to_fit();
+        self.MDS_matrix.truncate(self.width);
+        for row in self.MDS_matrix.iter_mut() {
+            row.truncate(self.width);
+            row.shrink_to_fit();
+        }
+        self.MDS_matrix.shrink_to_fit();
+    }
+
     /// Get the round keys for the curve and given width
-    fn get_round_keys(
-        width: usize,
-        total_rounds: usize,
-    ) -> Result<Vec<FieldElement>, BulletproofError> {
+    fn get_round_keys(width: usize, total_rounds: usize) -> Result<Vec<F>, BulletproofError> {
         let cap = total_rounds * width;
         //(0..cap).map(|_| FieldElement::random()).collect::<Vec<_>>()
         //vec![FieldElement::one(); cap]
@@ -86,7 +857,7 @@ impl PoseidonParams {
     }
 
     /// Get the MDS matrix for the curve and given width
-    fn get_MDS_matrix(width: usize) -> Result<Vec<Vec<FieldElement>>, BulletproofError> {
+    fn get_MDS_matrix(width: usize) -> Result<Vec<Vec<F>>, BulletproofError> {
         //(0..width).map(|_| (0..width).map(|_| FieldElement::random()).collect::<Vec<_>>()).collect::<Vec<Vec<_>>>()
         //vec![vec![FieldElement::one(); width]; width]
 
@@ -123,7 +894,7 @@ impl PoseidonParams {
             }
             .into());
         }
-        let mut mds: Vec<Vec<FieldElement>> = vec![vec![FieldElement::zero(); width]; width];
+        let mut mds: Vec<Vec<F>> = vec![vec![F::zero(); width]; width];
         for i in 0..width {
             if MDS_ENTRIES[i].len() != width {
                 return Err(BulletproofErrorKind::IncorrectMSDColCountForPoseidon {
@@ -140,7 +911,7 @@ impl PoseidonParams {
     }
 
     /// Convert a hex string of form "0x4d4..." to a field element
-    fn get_field_element_from_hex_str(hex_str: &str) -> Result<FieldElement, BulletproofError> {
+    fn get_field_element_from_hex_str(hex_str: &str) -> Result<F, BulletproofError> {
         let mut c = hex_str.to_string();
         // Remove "0x" from beginning
         let l = c.len();
@@ -152,43 +923,82 @@ impl PoseidonParams {
             .into());
         }
         c.replace_range(..2, "");
-        FieldElement::from_hex(c).map_err(|_| {
-            BulletproofError::from(BulletproofErrorKind::ParseErrorForPoseidonConstant {
-                constant: hex_str[2..].to_string(),
-                error_msg: String::from("Cannot convert to hex"),
-            })
-        })
+        F::from_hex(&c)
+    }
+}
+
+/// Split `round_keys` into chunks of `width` keys, one chunk per round
+fn split_round_keys<F: PoseidonField>(round_keys: &[F], width: usize) -> Vec<Vec<F>> {
+    if width == 0 {
+        return vec![];
     }
+    round_keys.chunks(width).map(|keys| keys.to_vec()).collect()
 }
 
 #[derive(Copy, Clone, Debug)]
 pub enum SboxType {
     Cube,
+    // Can be disabled with feature `inverse_sbox` to avoid compiling its constraints
+    #[cfg(feature = "inverse_sbox")]
     Inverse,
     Quint,
 }
 
 impl SboxType {
     /// Apply the Sbox on the given element
-    fn apply_sbox(&self, elem: &FieldElement) -> FieldElement {
+    pub(crate) fn apply_sbox<F: PoseidonField>(&self, elem: &F) -> F {
         match self {
             SboxType::Cube => {
                 // elem^3. When squaring, don't use `elem * elem` but `elem.square()` since its faster
                 let sqr = elem.square();
-                sqr * elem
+                sqr.mul(elem)
             }
+            #[cfg(feature = "inverse_sbox")]
             SboxType::Inverse => elem.inverse(),
             SboxType::Quint => {
                 // elem^5
                 let sq = elem.square();
                 let f = sq.square();
-                f * elem
+                f.mul(elem)
+            }
+        }
+    }
+
+    /// Same as `apply_sbox` but zeroizes the intermediate powers of `elem`
+    fn apply_sbox_secret<F: PoseidonField + Zeroize>(&self, elem: &F) -> F {
+        match self {
+            SboxType::Cube => {
+                let mut sqr = elem.square();
+                let out = sqr.mul(elem);
+                sqr.zeroize();
+                out
+            }
+            #[cfg(feature = "inverse_sbox")]
+            SboxType::Inverse => elem.inverse(),
+            SboxType::Quint => {
+                let mut sq = elem.square();
+                let mut f = sq.square();
+                let out = f.mul(elem);
+                sq.zeroize();
+                f.zeroize();
+                out
             }
         }
     }
 
+    /// Number of multiplication gates needed to enforce the constraints of this Sbox
+    pub fn gate_count(&self) -> usize {
+        match self {
+            SboxType::Cube => 2,
+            // 1 gate for the input and its inverse and 2 for `is_nonzero_gadget`
+            #[cfg(feature = "inverse_sbox")]
+            SboxType::Inverse => 3,
+            SboxType::Quint => 3,
+        }
+    }
+
     /// Enforce the constraints of this Sbox
-    fn synthesize_sbox<CS: ConstraintSystem>(
+    pub(crate) fn synthesize_sbox<CS: ConstraintSystem>(
         &self,
         cs: &mut CS,
         input_var: LinearCombination,
@@ -196,6 +1006,7 @@ impl SboxType {
     ) -> Result<Variable, R1CSError> {
         match self {
             SboxType::Cube => Self::synthesize_cube_sbox(cs, input_var, round_key),
+            #[cfg(feature = "inverse_sbox")]
             SboxType::Inverse => Self::synthesize_inverse_sbox(cs, input_var, round_key),
             SboxType::Quint => Self::synthesize_quint_sbox(cs, input_var, round_key),
         }
@@ -227,6 +1038,7 @@ impl SboxType {
     }
 
     /// Allocate variables in circuit and enforce constraints when Sbox as inverse, i.e. (input_var + round_key)^-1
+    #[cfg(feature = "inverse_sbox")]
     fn synthesize_inverse_sbox<CS: ConstraintSystem>(
         cs: &mut CS,
         input_var: LinearCombination,
@@ -250,110 +1062,309 @@ impl SboxType {
     }
 }
 
-/// Computes the permutation on the given inputs, parameters and Sbox and outputs the result of the permutation
-pub fn Poseidon_permutation(
-    input: &[FieldElement],
-    params: &PoseidonParams,
-    sbox: &SboxType,
-) -> Vec<FieldElement> {
-    let width = params.width;
-    assert_eq!(input.len(), width);
-
-    let full_rounds_beginning = params.full_rounds_beginning;
-    let partial_rounds = params.partial_rounds;
-    let full_rounds_end = params.full_rounds_end;
-
-    // Each round of the permutation will change current_state.
-    let mut current_state = input.to_owned();
+// Keeping the state in Montgomery form across rounds would avoid a reduction in each multiplication
+// but `FieldElement` of `amcl_wrapper` does not expose a Montgomery representation or Montgomery
+// multiplication, each arithmetic operation returns a reduced element. So there is no such fast
+// path. Throughput of the permutation is measured by the benchmark `poseidon_permutation`.
+// Similarly, computing several outputs of the linear layer at once with SIMD needs vectorized
+// multiplication and addition of field elements which `amcl_wrapper` does not have, its field
+// arithmetic is on one `BIG` at a time and `FieldElementVector::inner_product` is a scalar loop.
+// So there is no `simd` feature, a SIMD linear layer would need a different field backend
+// implementing `PoseidonField`.
 
+/// State of the native permutation between rounds
+struct PermState<F: PoseidonField> {
+    current_state: Vec<F>,
     // Temporary layer to hold the output of the linear layer
-    let mut current_state_temp = vec![FieldElement::zero(); width];
-
-    let mut round_keys_offset = 0;
-
-    /// Apply given number of full rounds.
-    // Using closure makes me wrap the mutables, i.e. current_state, current_state_temp, round_keys_offset
-    // in `RefCell`s which results in using borrow and borrow_mut at other places in code.
-    fn apply_full_rounds(
-        num_rounds: usize,
-        current_state: &mut Vec<FieldElement>,
-        current_state_temp: &mut Vec<FieldElement>,
-        round_keys_offset: &mut usize,
-        params: &PoseidonParams,
-        sbox: &SboxType,
-    ) {
-        for _ in 0..num_rounds {
-            // Sbox layer
-            for i in 0..params.width {
-                current_state[i] += &params.round_keys[*round_keys_offset];
-                current_state[i] = sbox.apply_sbox(&current_state[i]);
-                *round_keys_offset += 1;
-            }
-
-            // linear layer
-            for i in 0..params.width {
-                for j in 0..params.width {
-                    current_state_temp[i] += &current_state[j] * &params.MDS_matrix[j][i];
-                }
-            }
+    current_state_temp: Vec<F>,
+}
 
-            // Output of this round becomes input to next round
-            for i in 0..params.width {
-                current_state[i] = current_state_temp.remove(0);
-                current_state_temp.push(FieldElement::zero());
-            }
+impl<F: PoseidonField> PermState<F> {
+    fn new(input: Vec<F>) -> Self {
+        let width = input.len();
+        Self {
+            current_state: input,
+            current_state_temp: vec![F::zero(); width],
         }
     }
 
-    // full Sbox rounds
-    apply_full_rounds(
-        full_rounds_beginning,
-        &mut current_state,
-        &mut current_state_temp,
-        &mut round_keys_offset,
-        params,
-        sbox,
-    );
+    /// Round with Sbox applied to all elements of the state
+    fn full_round(&mut self, keys: &[F], params: &PoseidonParams<F>, sbox: &SboxType) {
+        for i in 0..params.width {
+            self.current_state[i] = self.current_state[i].add(&keys[i]);
+            self.current_state[i] = sbox.apply_sbox(&self.current_state[i]);
+        }
+        self.linear_layer(params);
+    }
 
-    // middle partial Sbox rounds
-    for _ in full_rounds_beginning..(full_rounds_beginning + partial_rounds) {
+    /// Round with Sbox applied to only the elements of the state at `partial_sbox_positions`. By
+    /// default only the last one is chosen but the choice is arbitrary.
+    // TODO: This should be written in the paper not just in a diagram.
+    fn partial_round(&mut self, keys: &[F], params: &PoseidonParams<F>, sbox: &SboxType) {
+        let width = params.width;
         for i in 0..width {
-            current_state[i] += &params.round_keys[round_keys_offset];
-            round_keys_offset += 1;
+            self.current_state[i] = self.current_state[i].add(&keys[i]);
         }
+        for &p in &params.partial_sbox_positions {
+            self.current_state[p] = sbox.apply_sbox(&self.current_state[p]);
+        }
+        self.linear_layer(params);
+    }
 
-        // partial Sbox layer, apply Sbox to only 1 element of the state.
-        // Here the last one is chosen but the choice is arbitrary.
-        // TODO: This should be written in the paper not just in a diagram.
-        current_state[width - 1] = sbox.apply_sbox(&current_state[width - 1]);
-
-        // linear layer
-        for i in 0..width {
-            for j in 0..width {
-                current_state_temp[i] += &current_state[j] * &params.MDS_matrix[j][i];
+    /// Multiply the state with the MDS matrix, the output becomes input to the next round
+    fn linear_layer(&mut self, params: &PoseidonParams<F>) {
+        for i in 0..params.width {
+            for j in 0..params.width {
+                let t = self.current_state[j].mul(&params.MDS_matrix[j][i]);
+                self.current_state_temp[i] = self.current_state_temp[i].add(&t);
             }
         }
-
-        // Output of this round becomes input to next round
-        for i in 0..width {
-            current_state[i] = current_state_temp.remove(0);
-            current_state_temp.push(FieldElement::zero());
+        std::mem::swap(&mut self.current_state, &mut self.current_state_temp);
+        for t in self.current_state_temp.iter_mut() {
+            *t = F::zero();
         }
     }
+}
 
-    // TODO: Remove duplicate code below.
-    // last full Sbox rounds
-    apply_full_rounds(
-        full_rounds_end,
-        &mut current_state,
-        &mut current_state_temp,
-        &mut round_keys_offset,
-        params,
-        sbox,
-    );
+// The rounds of `Poseidon_permutation_secret`. Same as the ones above but every value derived
+// from the input is zeroized before being dropped. Temporaries internal to the field arithmetic
+// are out of reach.
+impl<F: PoseidonField + Zeroize> PermState<F> {
+    /// Replace the state element at `i` with `new`, zeroizing the replaced element
+    fn replace_secret(&mut self, i: usize, new: F) {
+        let mut old = std::mem::replace(&mut self.current_state[i], new);
+        old.zeroize();
+    }
 
-    current_state
-}
+    fn full_round_secret(&mut self, keys: &[F], params: &PoseidonParams<F>, sbox: &SboxType) {
+        for i in 0..params.width {
+            let mut keyed = self.current_state[i].add(&keys[i]);
+            let out = sbox.apply_sbox_secret(&keyed);
+            keyed.zeroize();
+            self.replace_secret(i, out);
+        }
+        self.linear_layer_secret(params);
+    }
+
+    fn partial_round_secret(&mut self, keys: &[F], params: &PoseidonParams<F>, sbox: &SboxType) {
+        for i in 0..params.width {
+            let keyed = self.current_state[i].add(&keys[i]);
+            self.replace_secret(i, keyed);
+        }
+        for &p in &params.partial_sbox_positions {
+            let out = sbox.apply_sbox_secret(&self.current_state[p]);
+            self.replace_secret(p, out);
+        }
+        self.linear_layer_secret(params);
+    }
+
+    fn linear_layer_secret(&mut self, params: &PoseidonParams<F>) {
+        for i in 0..params.width {
+            for j in 0..params.width {
+                let mut t = self.current_state[j].mul(&params.MDS_matrix[j][i]);
+                let mut old = std::mem::replace(&mut self.current_state_temp[i], F::zero());
+                self.current_state_temp[i] = old.add(&t);
+                old.zeroize();
+                t.zeroize();
+            }
+        }
+        std::mem::swap(&mut self.current_state, &mut self.current_state_temp);
+        for t in self.current_state_temp.iter_mut() {
+            t.zeroize();
+        }
+    }
+}
+
+/// Computes the permutation on the given inputs, parameters and Sbox and outputs the result of the permutation.
+/// Width 3 uses the unrolled `poseidon_permutation_w3`.
+pub fn Poseidon_permutation<F: PoseidonField>(
+    input: &[F],
+    params: &PoseidonParams<F>,
+    sbox: &SboxType,
+) -> Vec<F> {
+    if params.width == 3 && input.len() == 3 {
+        let state = [input[0].clone(), input[1].clone(), input[2].clone()];
+        return poseidon_permutation_w3(state, params, sbox).to_vec();
+    }
+    Poseidon_permutation_owned(input.to_vec(), params, sbox)
+}
+
+/// Same as `Poseidon_permutation` but also returns the number of field multiplications and
+/// inversions it did. Meant for comparing the native cost of Sbox types. Only operations of this
+/// call on the current thread are counted.
+#[cfg(feature = "poseidon_instrumentation")]
+pub fn Poseidon_permutation_instrumented(
+    input: &[FieldElement],
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> (Vec<FieldElement>, FieldOpCounts) {
+    instrumentation::take();
+    let output = Poseidon_permutation(input, params, sbox);
+    (output, instrumentation::take())
+}
+
+/// Permutation specialized for width 3 with the rounds and the linear layer unrolled over the 3
+/// elements of the state instead of looping over the width. Gives the same output as the generic
+/// permutation, `Poseidon_permutation_owned`. Panics if the params are not of width 3.
+pub fn poseidon_permutation_w3<F: PoseidonField>(
+    state: [F; 3],
+    params: &PoseidonParams<F>,
+    sbox: &SboxType,
+) -> [F; 3] {
+    assert_eq!(params.width, 3);
+    let m = &params.MDS_matrix;
+    let keys = params.round_keys_by_round();
+    let (full_b, partial_end, total_rounds) = round_ranges(params);
+
+    // Multiply the state with the MDS matrix
+    let mds = |s0: F, s1: F, s2: F| -> [F; 3] {
+        [
+            s0.mul(&m[0][0])
+                .add(&s1.mul(&m[1][0]))
+                .add(&s2.mul(&m[2][0])),
+            s0.mul(&m[0][1])
+                .add(&s1.mul(&m[1][1]))
+                .add(&s2.mul(&m[2][1])),
+            s0.mul(&m[0][2])
+                .add(&s1.mul(&m[1][2]))
+                .add(&s2.mul(&m[2][2])),
+        ]
+    };
+    let full_round = |[s0, s1, s2]: [F; 3], k: &[F]| -> [F; 3] {
+        mds(
+            sbox.apply_sbox(&s0.add(&k[0])),
+            sbox.apply_sbox(&s1.add(&k[1])),
+            sbox.apply_sbox(&s2.add(&k[2])),
+        )
+    };
+    // Sbox only on the partial Sbox positions as in `PermState::partial_round`
+    let positions = &params.partial_sbox_positions;
+    let active = [
+        positions.contains(&0),
+        positions.contains(&1),
+        positions.contains(&2),
+    ];
+    let partial_sbox = |i: usize, x: F| if active[i] { sbox.apply_sbox(&x) } else { x };
+    let partial_round = |[s0, s1, s2]: [F; 3], k: &[F]| -> [F; 3] {
+        mds(
+            partial_sbox(0, s0.add(&k[0])),
+            partial_sbox(1, s1.add(&k[1])),
+            partial_sbox(2, s2.add(&k[2])),
+        )
+    };
+
+    let mut state = state;
+    for k in &keys[..full_b] {
+        state = full_round(state, k);
+    }
+    for k in &keys[full_b..partial_end] {
+        state = partial_round(state, k);
+    }
+    for k in &keys[partial_end..total_rounds] {
+        state = full_round(state, k);
+    }
+    state
+}
+
+/// Same as `Poseidon_permutation` but for secret inputs like preimages of commitments or MAC keys.
+/// Every intermediate value the permutation derives from the input (Sbox powers, products of the
+/// linear layer, state of previous rounds) is zeroized before being dropped instead of lingering
+/// in memory. Temporaries created inside the field arithmetic of `F` are not covered. The returned
+/// output is not zeroized as the caller owns it and should zeroize it, same for the given input.
+pub fn Poseidon_permutation_secret<F: PoseidonField + Zeroize>(
+    input: &[F],
+    params: &PoseidonParams<F>,
+    sbox: &SboxType,
+) -> Vec<F> {
+    assert_eq!(input.len(), params.width);
+    let (full_b, partial_end, total_rounds) = round_ranges(params);
+    let keys = params.round_keys_by_round();
+    let mut state = PermState::new(input.to_vec());
+    for k in &keys[..full_b] {
+        state.full_round_secret(k, params, sbox);
+    }
+    for k in &keys[full_b..partial_end] {
+        state.partial_round_secret(k, params, sbox);
+    }
+    for k in &keys[partial_end..total_rounds] {
+        state.full_round_secret(k, params, sbox);
+    }
+    std::mem::replace(&mut state.current_state, vec![])
+}
+
+/// Returns the capacity part of the permutation output, i.e. its first `capacity_size` elements,
+/// which the hash functions discard. Meant for validating custom sponge modes. Panics if
+/// `capacity_size` is more than the width.
+pub fn Poseidon_permutation_capacity<F: PoseidonField>(
+    input: &[F],
+    params: &PoseidonParams<F>,
+    sbox: &SboxType,
+    capacity_size: usize,
+) -> Vec<F> {
+    assert!(capacity_size <= params.width);
+    let mut output = Poseidon_permutation(input, params, sbox);
+    output.truncate(capacity_size);
+    output
+}
+
+/// Same as `Poseidon_permutation` but consumes the input and uses it as the initial state thus
+/// avoiding a copy of the input
+pub fn Poseidon_permutation_owned<F: PoseidonField>(
+    input: Vec<F>,
+    params: &PoseidonParams<F>,
+    sbox: &SboxType,
+) -> Vec<F> {
+    assert_eq!(input.len(), params.width);
+    run_rounds(input, params, sbox).current_state
+}
+
+/// Same as `Poseidon_permutation` but checks the params before running the rounds. Errors
+/// instead of panicking if the input is not of `width` elements, the params do not have exactly
+/// `width` round keys for each of the `full_rounds_beginning + partial_rounds + full_rounds_end`
+/// rounds, the MDS matrix is not `width x width` or the partial Sbox positions are not distinct
+/// positions of the state. Meant for debugging custom params.
+pub fn Poseidon_permutation_checked<F: PoseidonField>(
+    input: &[F],
+    params: &PoseidonParams<F>,
+    sbox: &SboxType,
+) -> Result<Vec<F>, R1CSError> {
+    if input.len() != params.width {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!("Expected {} inputs but found {}", params.width, input.len()),
+        }
+        .into());
+    }
+    params.check_shape()?;
+    Ok(run_rounds(input.to_vec(), params, sbox).current_state)
+}
+
+fn run_rounds<F: PoseidonField>(
+    input: Vec<F>,
+    params: &PoseidonParams<F>,
+    sbox: &SboxType,
+) -> PermState<F> {
+    let (full_b, partial_end, total_rounds) = round_ranges(params);
+    let keys = params.round_keys_by_round();
+    let mut state = PermState::new(input);
+    for k in &keys[..full_b] {
+        state.full_round(k, params, sbox);
+    }
+    for k in &keys[full_b..partial_end] {
+        state.partial_round(k, params, sbox);
+    }
+    for k in &keys[partial_end..total_rounds] {
+        state.full_round(k, params, sbox);
+    }
+    state
+}
+
+/// Index of the first partial round, of the first full round after the partial rounds and the
+/// number of rounds
+fn round_ranges<F: PoseidonField>(params: &PoseidonParams<F>) -> (usize, usize, usize) {
+    let full_b = params.full_rounds_beginning;
+    let partial_end = full_b + params.partial_rounds;
+    (full_b, partial_end, partial_end + params.full_rounds_end)
+}
 
 /// Enforces the constraints of the Poseidon permutation with the given constraint system on the
 /// given inputs, parameters and Sbox. Output is a vector where each element of it is a linear
@@ -367,54 +1378,65 @@ pub fn Poseidon_permutation_constraints<'a, CS: ConstraintSystem>(
     let width = params.width;
     assert_eq!(input.len(), width);
 
+    /// Errors instead of panicking if `matrix_2` is not `width x width` as the params might
+    /// not have been checked with `PoseidonParams::validate`
     fn apply_linear_layer(
         sbox_outs: Vec<LinearCombination>,
         next_inputs: &mut Vec<LinearCombination>,
         matrix_2: &Vec<Vec<FieldElement>>,
-    ) {
+    ) -> Result<(), R1CSError> {
         let width = sbox_outs.len();
+        if matrix_2.len() != width
+            || next_inputs.len() != width
+            || matrix_2.iter().any(|row| row.len() != width)
+        {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "MDS matrix should be of {} rows and columns but has rows of lengths {:?}",
+                    width,
+                    matrix_2.iter().map(|row| row.len()).collect::<Vec<_>>()
+                ),
+            }
+            .into());
+        }
         for i in 0..width {
             for j in 0..width {
                 next_inputs[i] += &matrix_2[j][i] * sbox_outs[j].clone();
             }
         }
+        Ok(())
     }
 
     let mut current_state_vars: Vec<LinearCombination> = input;
 
-    let mut round_keys_offset = 0;
-
     let full_rounds_beginning = params.full_rounds_beginning;
     let partial_rounds = params.partial_rounds;
     let full_rounds_end = params.full_rounds_end;
 
-    /// Apply full rounds
+    /// Apply full rounds using the given round keys, one vector of keys per round.
     fn apply_full_rounds<CS: ConstraintSystem>(
-        num_rounds: usize,
+        round_keys: &[Vec<FieldElement>],
         cs: &mut CS,
         input_vars: &mut Vec<LinearCombination>,
-        round_keys_offset: &mut usize,
         params: &PoseidonParams,
         sbox_type: &SboxType,
     ) -> Result<(), R1CSError> {
-        for _ in 0..num_rounds {
+        for keys in round_keys {
             let mut sbox_outputs: Vec<LinearCombination> =
                 vec![LinearCombination::default(); params.width];
 
             // Substitution (S-box) layer
             for i in 0..params.width {
-                let round_key = params.round_keys[*round_keys_offset].clone();
+                let round_key = keys[i].clone();
                 sbox_outputs[i] = sbox_type
                     .synthesize_sbox(cs, input_vars[i].clone(), round_key)?
                     .into();
-
-                *round_keys_offset += 1;
             }
 
             let mut next_input_vars: Vec<LinearCombination> =
                 vec![LinearCombination::default(); params.width];
 
-            apply_linear_layer(sbox_outputs, &mut next_input_vars, &params.MDS_matrix);
+            apply_linear_layer(sbox_outputs, &mut next_input_vars, &params.MDS_matrix)?;
 
             for i in 0..params.width {
                 // replace input_vars with next_input_vars
@@ -424,13 +1446,15 @@ pub fn Poseidon_permutation_constraints<'a, CS: ConstraintSystem>(
         Ok(())
     }
 
+    let partial_rounds_end = full_rounds_beginning + partial_rounds;
+    let round_keys = params.round_keys_by_round();
+
     // ------------ First full rounds begin --------------------
 
     apply_full_rounds(
-        full_rounds_beginning,
+        &round_keys[..full_rounds_beginning],
         cs,
         &mut current_state_vars,
-        &mut round_keys_offset,
         params,
         sbox_type,
     )?;
@@ -439,31 +1463,29 @@ pub fn Poseidon_permutation_constraints<'a, CS: ConstraintSystem>(
 
     // ------------ Middle rounds begin --------------------
 
-    for _ in full_rounds_beginning..(full_rounds_beginning + partial_rounds) {
+    for keys in &round_keys[full_rounds_beginning..partial_rounds_end] {
         let mut sbox_outputs: Vec<LinearCombination> = vec![LinearCombination::default(); width];
 
         // Substitution (S-box) layer
         for i in 0..width {
-            let round_key = params.round_keys[round_keys_offset].clone();
+            let round_key = keys[i].clone();
 
-            // apply Sbox to only 1 element of the state.
-            // Here the last one is chosen but the choice is arbitrary.
-            if i == width - 1 {
+            // apply Sbox to only the elements at `partial_sbox_positions`.
+            // By default the last one is chosen but the choice is arbitrary.
+            if params.partial_sbox_positions.contains(&i) {
                 sbox_outputs[i] = sbox_type
                     .synthesize_sbox(cs, current_state_vars[i].clone(), round_key)?
                     .into();
             } else {
                 sbox_outputs[i] = current_state_vars[i].clone() + round_key;
             }
-
-            round_keys_offset += 1;
         }
 
         // Linear layer
 
         let mut next_input_vars: Vec<LinearCombination> = vec![LinearCombination::default(); width];
 
-        apply_linear_layer(sbox_outputs, &mut next_input_vars, &params.MDS_matrix);
+        apply_linear_layer(sbox_outputs, &mut next_input_vars, &params.MDS_matrix)?;
 
         for i in 0..width {
             // replace input_vars with simplified next_input_vars
@@ -476,10 +1498,9 @@ pub fn Poseidon_permutation_constraints<'a, CS: ConstraintSystem>(
     // ------------ Last full rounds begin --------------------
 
     apply_full_rounds(
-        full_rounds_end,
+        &round_keys[partial_rounds_end..partial_rounds_end + full_rounds_end],
         cs,
         &mut current_state_vars,
-        &mut round_keys_offset,
         params,
         sbox_type,
     )?;
@@ -513,16 +1534,107 @@ pub fn Poseidon_permutation_gadget<'a, CS: ConstraintSystem>(
     Ok(())
 }
 
+/// Enforces the permutation of the same input twice, once with `sbox_type_1` and once with
+/// `sbox_type_2`, and returns both outputs in that order so that the caller can constrain a
+/// relation between them, like when migrating from one Sbox to another. The input is used as it
+/// is by both permutations so it should be a variable or a linear combination of variables and
+/// not re-allocated.
+pub fn Poseidon_permutation_two_sboxes_constraints<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    input: Vec<LinearCombination>,
+    params: &'a PoseidonParams,
+    sbox_type_1: &SboxType,
+    sbox_type_2: &SboxType,
+) -> Result<(Vec<LinearCombination>, Vec<LinearCombination>), R1CSError> {
+    let output_1 = Poseidon_permutation_constraints::<CS>(cs, input.clone(), params, sbox_type_1)?;
+    let output_2 = Poseidon_permutation_constraints::<CS>(cs, input, params, sbox_type_2)?;
+    Ok((output_1, output_2))
+}
+
+/// Security level in bits checked by `PoseidonParams::new_validated`
+pub const POSEIDON_SECURITY_BITS: usize = 128;
+
 // TODO: Say about the 2 types of hash, fixed input vs var input. Explain why capacity constant?
 // Hash from the permutation by passing the first input as capacity constant.
-// Capacity constants should have the least significant width-1 bits set and rest unset
+// Capacity constants should have some least significant bits set and rest unset, checked by
+// `validate_capacity_const`. Note that `CAP_CONST_W_3` has width-1 bits set but `CAP_CONST_W_5`
+// and `CAP_CONST_W_9` have width bits set. Changing them would change all hash outputs.
 // Capacity constant for width 3,   000000000...0011
 pub const CAP_CONST_W_3: u64 = 3;
-// Capacity constant for width 5,   0000000...001111
+// Capacity constant for width 3 when hashing a single input,   000000000...0001
+pub const CAP_CONST_1_W_3: u64 = 1;
+// Capacity constant for width 5,   0000000...011111
 pub const CAP_CONST_W_5: u64 = 31;
-// Capacity constant for width 9,   000...0011111111
+// Capacity constant for width 9,   000...0111111111
 pub const CAP_CONST_W_9: u64 = 511;
 
+/// Check that the capacity constant follows the bit pattern of capacity constants, i.e. it is
+/// non-zero, only its least significant bits are set and it has at most `width` bits.
+pub fn validate_capacity_const(width: usize, cap_const: u64) -> bool {
+    cap_const != 0 && cap_const & (cap_const + 1) == 0 && (width >= 64 || cap_const >> width == 0)
+}
+
+/// Largest reserved domain tag. Tags up to it are reserved for the capacity constants of the
+/// hashes and the IVs of `PoseidonSponge`, `capacity - 1` for capacities less than the widest
+/// params. Custom domains, e.g. tags for `PoseidonSponge::absorb_tagged` or custom capacity
+/// constants, should use tags greater than this.
+pub const MAX_RESERVED_DOMAIN_TAG: u64 = CAP_CONST_W_9;
+
+/// Built-in domain tags used as capacity constants and their names. Domains derived from labels
+/// with `domain_tag` are not listed as they are not `u64` and are registered as `DOMAIN_LABEL_*`.
+pub fn reserved_domain_tags() -> &'static [(u64, &'static str)] {
+    &[
+        (CAP_CONST_1_W_3, "CAP_CONST_1_W_3"),
+        (CAP_CONST_W_3, "CAP_CONST_W_3"),
+        (CAP_CONST_W_5, "CAP_CONST_W_5"),
+        (CAP_CONST_W_9, "CAP_CONST_W_9"),
+    ]
+}
+
+/// Check that `tag` is not in the reserved range, i.e. greater than `MAX_RESERVED_DOMAIN_TAG`,
+/// so it cannot collide with a built-in domain.
+pub fn check_domain_tag_available(tag: u64) -> Result<(), R1CSError> {
+    if tag <= MAX_RESERVED_DOMAIN_TAG {
+        let name = reserved_domain_tags()
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, n)| *n)
+            .unwrap_or("reserved range");
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Domain tag {} is reserved ({}), use a tag greater than {}",
+                tag, name, MAX_RESERVED_DOMAIN_TAG
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+// Registry of labels for domain separation with `domain_tag`. Labels are namespaced as
+// `ursa.poseidon.<construction>[.<part>]` and a label must never be reused for a different
+// construction. Add new labels here rather than inventing tags at the call site. Nodes of merkle
+// trees are plain `Poseidon_hash_2` hashes with `CAP_CONST_W_3` so have no label, leaves that
+// must never equal a node have their own label like `DOMAIN_LABEL_MERKLE_KV_LEAF`.
+pub const DOMAIN_LABEL_COMMITMENT: &str = "ursa.poseidon.commitment";
+pub const DOMAIN_LABEL_PRF: &str = "ursa.poseidon.prf";
+pub const DOMAIN_LABEL_MAC: &str = "ursa.poseidon.mac";
+pub const DOMAIN_LABEL_MERKLE_KV_LEAF: &str = "ursa.poseidon.merkle.kv_leaf";
+pub const DOMAIN_LABEL_HASH_TO_POINT: &str = "ursa.poseidon.hash_to_point";
+pub const DOMAIN_LABEL_SHUFFLE: &str = "ursa.poseidon.shuffle";
+pub const DOMAIN_LABEL_HASH_POINTS: &str = "ursa.poseidon.hash_points";
+pub const DOMAIN_LABEL_DUPLEX_NONCE: &str = "ursa.poseidon.duplex.nonce";
+pub const DOMAIN_LABEL_MERKLE_STRUCTURE: &str = "ursa.poseidon.merkle.structure";
+pub const DOMAIN_LABEL_DERIVE_CHILD: &str = "ursa.poseidon.derive_child";
+
+/// Derive a domain separation tag from a label by hashing it to the field. The tag is meant to be
+/// used as the capacity constant so that hashes of different constructions never collide. The
+/// tags of the labels above are effectively random field elements so they are distinct from the
+/// small capacity constants like `CAP_CONST_W_3`.
+pub fn domain_tag(label: &str) -> FieldElement {
+    FieldElement::from_msg_hash(label.as_bytes())
+}
+
 // Tradeoff: For various `Poseidon_hash_{2/4/8}`, `Poseidon_hash_{2/4/8}_constraints` and `Poseidon_hash_{2/4/8}_gadget`
 // functions below, a better way (since they will always take {2/4/8} elements) is to make inputs an
 // array of {2/4/8} rather than a vector but then below i have to use `mem::replace` and `mem::uninitialized`
@@ -532,29 +1644,95 @@ pub const CAP_CONST_W_9: u64 = 511;
 // needs to work on variable length inputs.
 // Another alternative is to check crate "arrayvec"
 
+/// Hashes a single input to give a single output. Uses width 3 with the last element of the
+/// permutation input set to 0.
+pub fn Poseidon_hash_1(
+    input: FieldElement,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, BulletproofError> {
+    if params.width != 3 {
+        return Err(BulletproofErrorKind::UnacceptableWidthForPoseidon {
+            width: params.width,
+            acceptable: vec![3],
+        }
+        .into());
+    }
+
+    debug_assert!(validate_capacity_const(3, CAP_CONST_1_W_3));
+    let input = vec![
+        FieldElement::from(CAP_CONST_1_W_3),
+        input,
+        FieldElement::zero(),
+    ];
+
+    // Never take the first output
+    let out = Poseidon_permutation_owned(input, params, sbox).remove(1);
+    Ok(out)
+}
+
+/// Enforces constraints for Poseidon_hash_1 for the given constraint system and Poseidon params.
+/// Like `Poseidon_hash_1`, only width 3 is supported and other widths give the error of
+/// `UnacceptableWidthForPoseidon`.
+pub fn Poseidon_hash_1_constraints<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    input: LinearCombination,
+    capacity_const: LinearCombination,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<LinearCombination, R1CSError> {
+    if params.width != 3 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: BulletproofErrorKind::UnacceptableWidthForPoseidon {
+                width: params.width,
+                acceptable: vec![3],
+            }
+            .to_string(),
+        }
+        .into());
+    }
+
+    let input = vec![capacity_const, input, LinearCombination::default()];
+
+    let permutation_output = Poseidon_permutation_constraints::<CS>(cs, input, params, sbox_type)?;
+    Ok(permutation_output[1].to_owned())
+}
+
 /// Hashes 2 inputs to give a single output
 pub fn Poseidon_hash_2(
-    mut inputs: Vec<FieldElement>,
+    inputs: Vec<FieldElement>,
     params: &PoseidonParams,
     sbox: &SboxType,
 ) -> Result<FieldElement, BulletproofError> {
+    Poseidon_hash_2_with_state(inputs, params, sbox).map(|(out, _)| out)
+}
+
+/// Same as `Poseidon_hash_2` but also returns the complete output of the permutation, the hash
+/// being its element at index 1. Useful for continuing from the state, like forking a sponge,
+/// without running the permutation again.
+pub fn Poseidon_hash_2_with_state(
+    mut inputs: Vec<FieldElement>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<(FieldElement, Vec<FieldElement>), BulletproofError> {
     // Only 2 elements to the permutation are set to the input of this hash function,
     // one is set to the capacity constant.
     // Always keep the 1st element of the permutation as the capacity constant.
     if inputs.len() != 2 {
         return Err(BulletproofErrorKind::IncorrectWidthForPoseidon {
-            width: 2,
-            expected: inputs.len(),
+            width: inputs.len(),
+            expected: 2,
         }
         .into());
     }
 
+    debug_assert!(validate_capacity_const(3, CAP_CONST_W_3));
     let mut input = vec![FieldElement::from(CAP_CONST_W_3)];
     input.append(&mut inputs);
 
     // Never take the first output
-    let out = Poseidon_permutation(&input, params, sbox).remove(1);
-    Ok(out)
+    let state = Poseidon_permutation_owned(input, params, sbox);
+    Ok((state[1].clone(), state))
 }
 
 /// Enforces constraints for Poseidon_hash_2 for the given constraint system and Poseidon params
@@ -575,6 +1753,19 @@ pub fn Poseidon_hash_2_constraints<'a, CS: ConstraintSystem>(
     Ok(permutation_output[1].to_owned())
 }
 
+/// Enforces constraints for Poseidon_hash_2 like `Poseidon_hash_2_constraints` but returns the
+/// output as an allocated quantity so it can be passed to gadgets like `positive_no_gadget`.
+pub fn Poseidon_hash_2_as_quantity<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<LinearCombination>,
+    capacity_const: LinearCombination,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<AllocatedQuantity, R1CSError> {
+    let hash = Poseidon_hash_2_constraints::<CS>(cs, inputs, capacity_const, params, sbox_type)?;
+    allocate_lc_as_quantity(cs, hash)
+}
+
 /// Enforces constraints for Poseidon_hash_2 for the given constraint system and Poseidon params
 /// and constraints the output of the hash to given `image`.
 pub fn Poseidon_hash_2_gadget<'a, CS: ConstraintSystem>(
@@ -601,6 +1792,43 @@ pub fn Poseidon_hash_2_gadget<'a, CS: ConstraintSystem>(
     Ok(())
 }
 
+/// Enforces constraints for Poseidon_hash_2 on the given native inputs and returns the digest
+/// along with the output linear combination. The inputs are allocated as variables and the digest
+/// is evaluated from the constraints so the permutation is computed only once and the digest is
+/// always consistent with the constraints. Only for the prover, errors for the verifier since it
+/// cannot evaluate the digest.
+pub fn Poseidon_hash_2_prove<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<FieldElement>,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(FieldElement, LinearCombination), R1CSError> {
+    if inputs.len() != 2 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!("Expected 2 inputs but found {}", inputs.len()),
+        }
+        .into());
+    }
+    let mut vars = vec![];
+    for i in inputs {
+        vars.push(cs.allocate(Some(i))?.into());
+    }
+    let hash = Poseidon_hash_2_constraints::<CS>(
+        cs,
+        vars,
+        FieldElement::from(CAP_CONST_W_3).into(),
+        params,
+        sbox_type,
+    )?;
+    match cs.evaluate_lc(&hash) {
+        Some(digest) => Ok((digest, hash)),
+        None => Err(R1CSErrorKind::GadgetError {
+            description: String::from("Digest can only be computed by the prover"),
+        }
+        .into()),
+    }
+}
+
 /// Hashes 4 inputs to give a single output
 pub fn Poseidon_hash_4(
     mut inputs: Vec<FieldElement>,
@@ -612,16 +1840,17 @@ pub fn Poseidon_hash_4(
     // capacity constant.
     if inputs.len() != 4 {
         return Err(BulletproofErrorKind::IncorrectWidthForPoseidon {
-            width: 4,
-            expected: inputs.len(),
+            width: inputs.len(),
+            expected: 4,
         }
         .into());
     }
 
+    debug_assert!(validate_capacity_const(5, CAP_CONST_W_5));
     let mut input = vec![FieldElement::from(CAP_CONST_W_5)];
     input.append(&mut inputs);
     // Never take the first output
-    let out = Poseidon_permutation(&input, params, sbox).remove(1);
+    let out = Poseidon_permutation_owned(input, params, sbox).remove(1);
     Ok(out)
 }
 
@@ -683,16 +1912,17 @@ pub fn Poseidon_hash_8(
 ) -> Result<FieldElement, BulletproofError> {
     if inputs.len() != 8 {
         return Err(BulletproofErrorKind::IncorrectWidthForPoseidon {
-            width: 8,
-            expected: inputs.len(),
+            width: inputs.len(),
+            expected: 8,
         }
         .into());
     }
+    debug_assert!(validate_capacity_const(9, CAP_CONST_W_9));
     let mut input = vec![FieldElement::from(CAP_CONST_W_9)];
     input.extend(inputs.into_iter());
 
     // Never take the first output
-    let out = Poseidon_permutation(&input, params, sbox).remove(1);
+    let out = Poseidon_permutation_owned(input, params, sbox).remove(1);
     Ok(out)
 }
 
@@ -740,3 +1970,3556 @@ pub fn Poseidon_hash_8_gadget<'a, CS: ConstraintSystem>(
 
     Ok(())
 }
+
+/// Number of multiplication gates needed by the permutation with the given params and Sbox. Only
+/// the Sboxes need multiplication gates, the linear layer and round keys are free.
+pub fn Poseidon_permutation_gate_count(params: &PoseidonParams, sbox_type: &SboxType) -> usize {
+    let full_rounds = params.full_rounds_beginning + params.full_rounds_end;
+    let sboxes =
+        full_rounds * params.width + params.partial_rounds * params.partial_sbox_positions.len();
+    sboxes * sbox_type.gate_count()
+}
+
+// Rounds of the hashes as (full rounds in beginning, full rounds in end, partial rounds) for the
+// width of the hash when using the complete round constant tables.
+const HASH_2_ROUNDS: (usize, usize, usize) = (4, 4, 55);
+const HASH_4_ROUNDS: (usize, usize, usize) = (4, 4, 56);
+const HASH_8_ROUNDS: (usize, usize, usize) = (4, 4, 57);
+
+fn hash_gate_count(width: usize, rounds: (usize, usize, usize), sbox_type: &SboxType) -> usize {
+    let (full_b, full_e, partial) = rounds;
+    ((full_b + full_e) * width + partial) * sbox_type.gate_count()
+}
+
+/// Number of multiplication gates of `Poseidon_hash_2_constraints` with width 3 and rounds (4, 4, 55)
+pub fn Poseidon_hash_2_gate_count(sbox_type: &SboxType) -> usize {
+    hash_gate_count(3, HASH_2_ROUNDS, sbox_type)
+}
+
+/// Number of multiplication gates of `Poseidon_hash_4_constraints` with width 5 and rounds (4, 4, 56)
+pub fn Poseidon_hash_4_gate_count(sbox_type: &SboxType) -> usize {
+    hash_gate_count(5, HASH_4_ROUNDS, sbox_type)
+}
+
+/// Number of multiplication gates of `Poseidon_hash_8_constraints` with width 9 and rounds (4, 4, 57)
+pub fn Poseidon_hash_8_gate_count(sbox_type: &SboxType) -> usize {
+    hash_gate_count(9, HASH_8_ROUNDS, sbox_type)
+}
+
+/// Budget of multiplication gates for a circuit, charged by the budgeted gadgets before adding
+/// their constraints so that building a circuit too big to be proved fails early. The same
+/// budget should be passed to all budgeted gadgets of a circuit.
+#[derive(Clone, Debug)]
+pub struct PoseidonCircuitBudget {
+    max_gates: usize,
+    used_gates: usize,
+}
+
+impl PoseidonCircuitBudget {
+    pub fn new(max_gates: usize) -> Self {
+        Self {
+            max_gates,
+            used_gates: 0,
+        }
+    }
+
+    pub fn max_gates(&self) -> usize {
+        self.max_gates
+    }
+
+    /// Multiplication gates charged so far
+    pub fn used_gates(&self) -> usize {
+        self.used_gates
+    }
+
+    pub fn remaining_gates(&self) -> usize {
+        self.max_gates - self.used_gates
+    }
+
+    /// Charge `gates` multiplication gates. Errors with `GateBudgetExceeded` and leaves the budget
+    /// unchanged if the total would exceed the maximum.
+    pub fn charge(&mut self, gates: usize) -> Result<(), R1CSError> {
+        let current = self.used_gates.saturating_add(gates);
+        if current > self.max_gates {
+            return Err(R1CSErrorKind::GateBudgetExceeded {
+                current,
+                max: self.max_gates,
+            }
+            .into());
+        }
+        self.used_gates = current;
+        Ok(())
+    }
+
+    /// Charge the gates of one permutation with the given params and Sbox
+    pub fn charge_permutation(
+        &mut self,
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> Result<(), R1CSError> {
+        self.charge(Poseidon_permutation_gate_count(params, sbox_type))
+    }
+}
+
+/// Enforces constraints for Poseidon_hash_{2/4/8}, the one chosen by width of the params, and
+/// returns the output of the hash as well as its value. The value is only known to the prover.
+/// Used when the hash computed in one circuit needs to be referenced in another circuit, the
+/// prover publishes the returned value and both prover and verifier of each circuit constrain
+/// the output of the hash to that public value with `constrain_lc_with_scalar`.
+pub fn poseidon_public_output_gadget<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<LinearCombination>,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(LinearCombination, Option<FieldElement>), R1CSError> {
+    if inputs.len() != params.width - 1 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Expected {} inputs for width {} but found {}",
+                params.width - 1,
+                params.width,
+                inputs.len()
+            ),
+        }
+        .into());
+    }
+    let hash = match params.width {
+        3 => Poseidon_hash_2_constraints::<CS>(
+            cs,
+            inputs,
+            FieldElement::from(CAP_CONST_W_3).into(),
+            params,
+            sbox_type,
+        )?,
+        5 => Poseidon_hash_4_constraints::<CS>(
+            cs,
+            inputs,
+            FieldElement::from(CAP_CONST_W_5).into(),
+            params,
+            sbox_type,
+        )?,
+        9 => Poseidon_hash_8_constraints::<CS>(
+            cs,
+            inputs,
+            FieldElement::from(CAP_CONST_W_9).into(),
+            params,
+            sbox_type,
+        )?,
+        _ => {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!("Unacceptable width {} for Poseidon", params.width),
+            }
+            .into())
+        }
+    };
+    let value = cs.evaluate_lc(&hash);
+    Ok((hash, value))
+}
+
+/// Enforces `h1 + h2 == h3` where the arguments are usually outputs of Poseidon hashes. Field
+/// addition is used in place of XOR which is expensive in circuits. Only a linear constraint is
+/// added.
+pub fn constrain_hash_sum_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    h1: LinearCombination,
+    h2: LinearCombination,
+    h3: LinearCombination,
+) {
+    cs.constrain(h1 + h2 - h3);
+}
+
+/// Capacity constant used by `Poseidon_hash_n` for `num_inputs` inputs and given width. When the
+/// inputs fill the rate, it is the same as the constant of `Poseidon_hash_{2/4/8}` so that
+/// `Poseidon_hash_n` agrees with them, otherwise the least significant `num_inputs` bits are set.
+pub fn capacity_const_for_n(num_inputs: usize, width: usize) -> u64 {
+    match (num_inputs, width) {
+        (2, 3) => CAP_CONST_W_3,
+        (4, 5) => CAP_CONST_W_5,
+        (8, 9) => CAP_CONST_W_9,
+        _ => (1u64 << num_inputs) - 1,
+    }
+}
+
+/// Hashes 1 to `width - 1` inputs to give a single output. The inputs are padded with 0s to fill
+/// the rate and the capacity constant depends on the number of inputs so hashes of inputs of
+/// different length do not collide because of the padding.
+pub fn Poseidon_hash_n(
+    mut inputs: Vec<FieldElement>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, BulletproofError> {
+    if inputs.is_empty() || inputs.len() > params.width - 1 {
+        return Err(BulletproofErrorKind::IncorrectWidthForPoseidon {
+            width: inputs.len(),
+            expected: params.width - 1,
+        }
+        .into());
+    }
+
+    let cap_const = capacity_const_for_n(inputs.len(), params.width);
+    debug_assert!(validate_capacity_const(params.width, cap_const));
+    let mut input = vec![FieldElement::from(cap_const)];
+    input.append(&mut inputs);
+    input.resize(params.width, FieldElement::zero());
+
+    // Never take the first output
+    let out = Poseidon_permutation_owned(input, params, sbox).remove(1);
+    Ok(out)
+}
+
+/// Hashes 1 to `width - capacity` inputs to give `num_outputs` outputs. The first `capacity`
+/// elements of the state are the capacity, the first of them being the capacity constant of
+/// `Poseidon_hash_n`, and the inputs padded with 0s fill the rest. The outputs are squeezed from
+/// the rate only, i.e. the last `width - capacity` elements of the permuted state, and the state
+/// is permuted again whenever more outputs are needed, as in `PoseidonSponge::squeeze`. With
+/// capacity 1 and 1 output, this is same as `Poseidon_hash_n`.
+pub fn Poseidon_hash_n_multi(
+    inputs: Vec<FieldElement>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+    capacity: usize,
+    num_outputs: usize,
+) -> Result<Vec<FieldElement>, R1CSError> {
+    if capacity == 0 || capacity >= params.width {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Capacity should be in [1, {}) but is {}",
+                params.width, capacity
+            ),
+        }
+        .into());
+    }
+    let rate = params.width - capacity;
+    if inputs.is_empty() || inputs.len() > rate {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Expected 1 to {} inputs for capacity {} but found {}",
+                rate,
+                capacity,
+                inputs.len()
+            ),
+        }
+        .into());
+    }
+
+    let mut state = vec![FieldElement::zero(); capacity];
+    state[0] = FieldElement::from(capacity_const_for_n(inputs.len(), params.width));
+    state.extend(inputs);
+    state.resize(params.width, FieldElement::zero());
+
+    let mut outputs = Vec::with_capacity(num_outputs);
+    while outputs.len() < num_outputs {
+        state = Poseidon_permutation_owned(state, params, sbox);
+        let needed = std::cmp::min(rate, num_outputs - outputs.len());
+        outputs.extend_from_slice(&state[capacity..capacity + needed]);
+    }
+    Ok(outputs)
+}
+
+/// Enforces constraints for Poseidon_hash_n for the given constraint system and Poseidon params
+pub fn Poseidon_hash_n_constraints<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    mut inputs: Vec<LinearCombination>,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<LinearCombination, R1CSError> {
+    if inputs.is_empty() || inputs.len() > params.width - 1 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Expected 1 to {} inputs for width {} but found {}",
+                params.width - 1,
+                params.width,
+                inputs.len()
+            ),
+        }
+        .into());
+    }
+
+    let mut input =
+        vec![FieldElement::from(capacity_const_for_n(inputs.len(), params.width)).into()];
+    input.append(&mut inputs);
+    input.resize(params.width, LinearCombination::default());
+
+    let permutation_output = Poseidon_permutation_constraints::<CS>(cs, input, params, sbox_type)?;
+    Ok(permutation_output[1].to_owned())
+}
+
+/// Same as `Poseidon_hash_n_constraints` but charges `budget` and errors without adding any
+/// constraints if the budget is exceeded
+pub fn Poseidon_hash_n_constraints_with_budget<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<LinearCombination>,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType,
+    budget: &mut PoseidonCircuitBudget,
+) -> Result<LinearCombination, R1CSError> {
+    budget.charge_permutation(params, sbox_type)?;
+    Poseidon_hash_n_constraints::<CS>(cs, inputs, params, sbox_type)
+}
+
+/// Hash any number of inputs convertible to field elements. Up to `width - 1` inputs are hashed
+/// with `Poseidon_hash_n` which is same as `Poseidon_hash_{2/4/8}` when there are exactly
+/// `width - 1` inputs. More inputs are absorbed in a `PoseidonSponge` of capacity 1 and the
+/// first squeezed element is returned. The capacity constant of `Poseidon_hash_n` and the padding
+/// of the sponge bind the number of inputs so inputs extended with 0s hash differently. Errors
+/// when there are no inputs.
+pub fn poseidon_hash<T: Into<FieldElement>>(
+    inputs: impl IntoIterator<Item = T>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, R1CSError> {
+    Poseidon_hash_iter(inputs.into_iter().map(|i| i.into()), params, sbox)
+}
+
+/// Same as `poseidon_hash` but takes the inputs from an iterator. Only up to `width - 1` inputs
+/// are buffered to decide between `Poseidon_hash_n` and the sponge, the remaining inputs are
+/// absorbed as the iterator produces them.
+pub fn Poseidon_hash_iter<I: IntoIterator<Item = FieldElement>>(
+    iter: I,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, R1CSError> {
+    let mut iter = iter.into_iter();
+    let mut buffer = Vec::with_capacity(params.width - 1);
+    while buffer.len() < params.width - 1 {
+        match iter.next() {
+            Some(e) => buffer.push(e),
+            None => break,
+        }
+    }
+    match iter.next() {
+        None => {
+            if buffer.is_empty() {
+                return Err(R1CSErrorKind::GadgetError {
+                    description: String::from("Need at least 1 input to hash"),
+                }
+                .into());
+            }
+            Poseidon_hash_n(buffer, params, sbox).map_err(|e| {
+                R1CSErrorKind::GadgetError {
+                    description: e.to_string(),
+                }
+                .into()
+            })
+        }
+        Some(next) => {
+            let mut sponge = PoseidonSponge::new(params, sbox, 1)?;
+            sponge.absorb(&buffer);
+            sponge.absorb(&[next]);
+            for e in iter {
+                sponge.absorb(std::slice::from_ref(&e));
+            }
+            Ok(sponge.squeeze(1).remove(0))
+        }
+    }
+}
+
+/// Hash integers like indices or counters, each converted to a field element, same as
+/// `poseidon_hash` of the values. Up to `width - 1` values are hashed with `Poseidon_hash_n` and
+/// more with the sponge.
+pub fn Poseidon_hash_u64s(
+    values: &[u64],
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, R1CSError> {
+    Poseidon_hash_iter(values.iter().map(|v| FieldElement::from(*v)), params, sbox)
+}
+
+fn check_matrix_shape(
+    num_rows: usize,
+    row_lens: impl Iterator<Item = usize>,
+) -> Result<(), R1CSError> {
+    if num_rows == 0 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: String::from("Need at least 1 row to hash"),
+        }
+        .into());
+    }
+    let mut row_lens = row_lens;
+    let num_cols = row_lens.next().unwrap_or(0);
+    if num_cols == 0 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: String::from("Need at least 1 column to hash"),
+        }
+        .into());
+    }
+    for (i, len) in row_lens.enumerate() {
+        if len != num_cols {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Row {} has {} elements but row 0 has {}",
+                    i + 1,
+                    len,
+                    num_cols
+                ),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Hash a matrix by hashing each row to a single element with `poseidon_hash` and then hashing
+/// the number of rows, the number of columns and the row hashes with `poseidon_hash`, so matrices
+/// of different shapes hash differently. All rows must have the same non-zero length.
+pub fn Poseidon_hash_matrix(
+    rows: &[Vec<FieldElement>],
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, R1CSError> {
+    check_matrix_shape(rows.len(), rows.iter().map(|r| r.len()))?;
+    let mut inputs = vec![
+        FieldElement::from(rows.len() as u64),
+        FieldElement::from(rows[0].len() as u64),
+    ];
+    for row in rows {
+        inputs.push(Poseidon_hash_iter(row.iter().cloned(), params, sbox)?);
+    }
+    Poseidon_hash_iter(inputs, params, sbox)
+}
+
+/// Enforces the constraints of `poseidon_hash`, i.e. `Poseidon_hash_n` for up to `width - 1`
+/// inputs and a sponge of capacity 1 for more.
+fn poseidon_hash_constraints<CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<LinearCombination>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<LinearCombination, R1CSError> {
+    if inputs.len() < params.width {
+        return Poseidon_hash_n_constraints::<CS>(cs, inputs, params, sbox);
+    }
+    let mut sponge = PoseidonSpongeConstraints::new(params, sbox, 1)?;
+    sponge.absorb(cs, &inputs)?;
+    Ok(sponge.squeeze(cs, 1)?.remove(0))
+}
+
+/// Enforces constraints for `Poseidon_hash_matrix` where `rows` are the matrix elements
+pub fn Poseidon_hash_matrix_constraints<CS: ConstraintSystem>(
+    cs: &mut CS,
+    rows: Vec<Vec<LinearCombination>>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<LinearCombination, R1CSError> {
+    check_matrix_shape(rows.len(), rows.iter().map(|r| r.len()))?;
+    let mut inputs = vec![
+        FieldElement::from(rows.len() as u64).into(),
+        FieldElement::from(rows[0].len() as u64).into(),
+    ];
+    for row in rows {
+        inputs.push(poseidon_hash_constraints(cs, row, params, sbox)?);
+    }
+    poseidon_hash_constraints(cs, inputs, params, sbox)
+}
+
+// A `FieldElement` is always reduced, so values not less than the curve order are silently
+// reduced when converted to field elements, like by `FieldElement::from_bytes`, before reaching
+// the native hashes. The `*_checked` variants below take the unreduced inputs as bytes and reject
+// non-canonical ones instead to catch callers passing values that were never reduced.
+
+/// Returns true if `bytes` are the `MODBYTES` big endian bytes, as output by
+/// `FieldElement::to_bytes`, of a number less than the curve order
+pub fn is_canonical_field_element_bytes(bytes: &[u8]) -> bool {
+    bytes.len() == MODBYTES && is_canonical_bignum(&BigNum::frombytes(bytes))
+}
+
+fn is_canonical_bignum(b: &BigNum) -> bool {
+    BigNum::comp(b, &CurveOrder) < 0
+}
+
+/// Convert the inputs to field elements, erroring with the index of the first non-canonical one
+fn canonical_inputs(inputs: &[Vec<u8>]) -> Result<Vec<FieldElement>, BulletproofError> {
+    inputs
+        .iter()
+        .enumerate()
+        .map(|(index, bytes)| {
+            if !is_canonical_field_element_bytes(bytes) {
+                return Err(BulletproofErrorKind::NonCanonicalInputForPoseidon { index }.into());
+            }
+            FieldElement::from_bytes(bytes)
+                .map_err(|_| BulletproofErrorKind::NonCanonicalInputForPoseidon { index }.into())
+        })
+        .collect()
+}
+
+/// Same as `Poseidon_hash_2` but takes the inputs as bytes of `FieldElement::to_bytes` and
+/// errors if any input is not canonical
+pub fn Poseidon_hash_2_checked(
+    inputs: Vec<Vec<u8>>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, BulletproofError> {
+    Poseidon_hash_2(canonical_inputs(&inputs)?, params, sbox)
+}
+
+/// Same as `Poseidon_hash_4` but takes the inputs as bytes of `FieldElement::to_bytes` and
+/// errors if any input is not canonical
+pub fn Poseidon_hash_4_checked(
+    inputs: Vec<Vec<u8>>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, BulletproofError> {
+    Poseidon_hash_4(canonical_inputs(&inputs)?, params, sbox)
+}
+
+/// Same as `Poseidon_hash_8` but takes the inputs as bytes of `FieldElement::to_bytes` and
+/// errors if any input is not canonical
+pub fn Poseidon_hash_8_checked(
+    inputs: Vec<Vec<u8>>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, BulletproofError> {
+    Poseidon_hash_8(canonical_inputs(&inputs)?, params, sbox)
+}
+
+/// Same as `Poseidon_hash_n` but takes the inputs as bytes of `FieldElement::to_bytes` and
+/// errors if any input is not canonical
+pub fn Poseidon_hash_n_checked(
+    inputs: Vec<Vec<u8>>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, BulletproofError> {
+    Poseidon_hash_n(canonical_inputs(&inputs)?, params, sbox)
+}
+
+/// Sponge construction over the Poseidon permutation for hashing variable number of inputs or
+/// producing variable number of outputs. The first `capacity` elements of the state are the
+/// capacity and the remaining `width - capacity` elements are the rate. The absorbed inputs are
+/// padded with a 1 before squeezing (10* padding, the 0s being implicit) so inputs differing only
+/// in trailing 0s, like `m` and `m || 0`, give different outputs.
+#[derive(Clone, Debug)]
+pub struct PoseidonSponge<'a> {
+    pub params: &'a PoseidonParams,
+    pub sbox: &'a SboxType,
+    capacity: usize,
+    state: Vec<FieldElement>,
+    // Position in the rate where the next element will be absorbed or squeezed
+    pos: usize,
+    // Whether the sponge has started squeezing
+    squeezing: bool,
+}
+
+impl<'a> PoseidonSponge<'a> {
+    /// Create a sponge with `capacity` capacity elements, the rate is `width - capacity`. The
+    /// initial state (IV) is all zeros except the first capacity element which is `capacity - 1`.
+    /// Thus sponges with capacity 1 start from the all zero state and sponges with different
+    /// capacities start from different states.
+    pub fn new(
+        params: &'a PoseidonParams,
+        sbox: &'a SboxType,
+        capacity: usize,
+    ) -> Result<Self, R1CSError> {
+        Self::check_capacity(params, capacity)?;
+        let mut state = vec![FieldElement::zero(); params.width];
+        state[0] = FieldElement::from((capacity - 1) as u64);
+        Ok(Self {
+            params,
+            sbox,
+            capacity,
+            state,
+            pos: 0,
+            squeezing: false,
+        })
+    }
+
+    /// Number of capacity elements
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of elements absorbed or squeezed per permutation
+    pub fn rate(&self) -> usize {
+        self.params.width - self.capacity
+    }
+
+    /// Absorb the inputs in the rate, permuting whenever the rate is full. Absorbing after
+    /// squeezing permutes the state before absorbing.
+    pub fn absorb(&mut self, inputs: &[FieldElement]) {
+        if self.squeezing {
+            self.squeezing = false;
+            self.pos = self.rate();
+        }
+        for input in inputs {
+            if self.pos == self.rate() {
+                self.permute();
+                self.pos = 0;
+            }
+            self.state[self.capacity + self.pos] += input;
+            self.pos += 1;
+        }
+    }
+
+    /// Absorb `elems` and then add `tweak` to the first capacity element and permute, the next
+    /// absorption starts from the beginning of the rate. Unlike domain separation, where a tag
+    /// is absorbed as data in the rate or fixes the IV before any input, the tweak goes in the
+    /// capacity between absorptions and so cannot be reproduced by absorbing any inputs. A tweak
+    /// of 0 only forces a permutation after `elems`.
+    pub fn absorb_with_tweak(&mut self, elems: &[FieldElement], tweak: FieldElement) {
+        // Leaves squeezing mode even when `elems` is empty
+        self.absorb(elems);
+        self.state[0] += tweak;
+        self.permute();
+        self.pos = 0;
+    }
+
+    /// Absorb a group of elements of a record with the group's `tag` added to the first element
+    /// of the group, an empty group absorbs only the tag. This separates groups within a single
+    /// pass, e.g. absorbing the same elements under different tags or with groups in a different
+    /// order gives a different digest. Since the tag is added, the group `(tag, [e, ...])`
+    /// absorbs exactly like `(tag', [e + tag - tag', ...])` so tags do not add collision
+    /// resistance when the elements can be chosen freely and group lengths are not fixed by the
+    /// record structure. Use `absorb_with_tweak` when groups must be separated in the capacity.
+    pub fn absorb_tagged(&mut self, tag: u64, elems: &[FieldElement]) {
+        let tag = FieldElement::from(tag);
+        match elems.split_first() {
+            Some((first, rest)) => {
+                self.absorb(&[first + &tag]);
+                self.absorb(rest);
+            }
+            None => self.absorb(&[tag]),
+        }
+    }
+
+    /// Squeeze `num_outputs` elements
+    pub fn squeeze(&mut self, num_outputs: usize) -> Vec<FieldElement> {
+        let mut out = vec![FieldElement::zero(); num_outputs];
+        self.squeeze_into(&mut out);
+        out
+    }
+
+    /// Fill `out` with squeezed elements, permuting whenever the rate is exhausted. Does not
+    /// allocate for the output.
+    pub fn squeeze_into(&mut self, out: &mut [FieldElement]) {
+        if !self.squeezing {
+            self.pad();
+            self.permute();
+            self.pos = 0;
+            self.squeezing = true;
+        }
+        for o in out.iter_mut() {
+            if self.pos == self.rate() {
+                self.permute();
+                self.pos = 0;
+            }
+            *o = self.state[self.capacity + self.pos].clone();
+            self.pos += 1;
+        }
+    }
+
+    /// Serialize the sponge so that hashing can be resumed later using `restore`. The
+    /// serialization is the state elements followed by 8 bytes of the position in the rate in big
+    /// endian and 1 byte indicating whether the sponge is squeezing.
+    pub fn checkpoint(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for s in &self.state {
+            bytes.extend_from_slice(&s.to_bytes());
+        }
+        bytes.extend_from_slice(&(self.pos as u64).to_be_bytes());
+        bytes.push(self.squeezing as u8);
+        bytes
+    }
+
+    /// Create a sponge from bytes returned by `checkpoint`. The params, sbox and capacity should be
+    /// the same as the ones of the checkpointed sponge.
+    pub fn restore(
+        bytes: &[u8],
+        params: &'a PoseidonParams,
+        sbox: &'a SboxType,
+        capacity: usize,
+    ) -> Result<PoseidonSponge<'a>, R1CSError> {
+        Self::check_capacity(params, capacity)?;
+        let state_size = params.width * MODBYTES;
+        if bytes.len() != state_size + 9 {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Checkpoint should be of {} bytes but is of {} bytes",
+                    state_size + 9,
+                    bytes.len()
+                ),
+            }
+            .into());
+        }
+
+        let mut state = vec![];
+        for chunk in bytes[..state_size].chunks(MODBYTES) {
+            state.push(FieldElement::from_bytes(chunk).map_err(|_| {
+                R1CSError::from(R1CSErrorKind::GadgetError {
+                    description: String::from("Cannot parse state element of checkpoint"),
+                })
+            })?);
+        }
+
+        let mut pos_bytes = [0u8; 8];
+        pos_bytes.copy_from_slice(&bytes[state_size..state_size + 8]);
+        let pos = u64::from_be_bytes(pos_bytes) as usize;
+        if pos > params.width - capacity {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!("Position {} in checkpoint is more than the rate", pos),
+            }
+            .into());
+        }
+
+        let squeezing = match bytes[state_size + 8] {
+            0 => false,
+            1 => true,
+            b => {
+                return Err(R1CSErrorKind::GadgetError {
+                    description: format!("Invalid squeezing flag {} in checkpoint", b),
+                }
+                .into())
+            }
+        };
+
+        Ok(Self {
+            params,
+            sbox,
+            capacity,
+            state,
+            pos,
+            squeezing,
+        })
+    }
+
+    fn check_capacity(params: &PoseidonParams, capacity: usize) -> Result<(), R1CSError> {
+        if capacity == 0 || capacity >= params.width {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Capacity should be in [1, {}) but is {}",
+                    params.width, capacity
+                ),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Add 1 after the last absorbed element, in a new block if the rate is full
+    fn pad(&mut self) {
+        if self.pos == self.rate() {
+            self.permute();
+            self.pos = 0;
+        }
+        self.state[self.capacity + self.pos] += FieldElement::one();
+    }
+
+    fn permute(&mut self) {
+        self.state = Poseidon_permutation(&self.state, self.params, self.sbox);
+    }
+}
+
+/// `PoseidonSponge` in the circuit. Absorbing, padding and squeezing follow the native sponge so
+/// the squeezed linear combinations evaluate to the elements squeezed by a native sponge with the
+/// same capacity after absorbing the same inputs.
+#[derive(Clone, Debug)]
+pub struct PoseidonSpongeConstraints<'a> {
+    pub params: &'a PoseidonParams,
+    pub sbox: &'a SboxType,
+    capacity: usize,
+    state: Vec<LinearCombination>,
+    // Position in the rate where the next element will be absorbed or squeezed
+    pos: usize,
+    // Whether the sponge has started squeezing
+    squeezing: bool,
+    // Whether the absorbed inputs already end with the padding
+    padded: bool,
+}
+
+impl<'a> PoseidonSpongeConstraints<'a> {
+    /// Create a sponge with `capacity` capacity elements and the IV of `PoseidonSponge::new`
+    pub fn new(
+        params: &'a PoseidonParams,
+        sbox: &'a SboxType,
+        capacity: usize,
+    ) -> Result<Self, R1CSError> {
+        PoseidonSponge::check_capacity(params, capacity)?;
+        let mut state = vec![LinearCombination::default(); params.width];
+        state[0] = FieldElement::from((capacity - 1) as u64).into();
+        Ok(Self {
+            params,
+            sbox,
+            capacity,
+            state,
+            pos: 0,
+            squeezing: false,
+            padded: false,
+        })
+    }
+
+    /// Number of elements absorbed or squeezed per permutation
+    pub fn rate(&self) -> usize {
+        self.params.width - self.capacity
+    }
+
+    /// Enforces the constraints of absorbing the inputs, as `PoseidonSponge::absorb`
+    pub fn absorb<CS: ConstraintSystem>(
+        &mut self,
+        cs: &mut CS,
+        inputs: &[LinearCombination],
+    ) -> Result<(), R1CSError> {
+        if self.squeezing {
+            self.squeezing = false;
+            self.pos = self.rate();
+        }
+        for input in inputs {
+            if self.pos == self.rate() {
+                self.permute(cs)?;
+                self.pos = 0;
+            }
+            let i = self.capacity + self.pos;
+            self.state[i] = self.state[i].clone() + input.clone();
+            self.pos += 1;
+            self.padded = false;
+        }
+        Ok(())
+    }
+
+    /// Same as `absorb` but the inputs are blocks already padded as `PoseidonSponge::pad` does,
+    /// like the ones of `allocate_sponge_blocks`, so squeezing after them does not pad again.
+    /// Errors if the sponge is not at the start of a block or the blocks are not of `rate`
+    /// elements as the padding would then not end where a block ends.
+    pub fn absorb_padded<CS: ConstraintSystem>(
+        &mut self,
+        cs: &mut CS,
+        blocks: &[Vec<LinearCombination>],
+    ) -> Result<(), R1CSError> {
+        let rate = self.rate();
+        if self.squeezing || (self.pos != 0 && self.pos != rate) {
+            return Err(R1CSErrorKind::GadgetError {
+                description: String::from("Padded blocks should be absorbed at a block boundary"),
+            }
+            .into());
+        }
+        if let Some(block) = blocks.iter().find(|b| b.len() != rate) {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Padded blocks should have {} elements but found {}",
+                    rate,
+                    block.len()
+                ),
+            }
+            .into());
+        }
+        for block in blocks {
+            self.absorb(cs, block)?;
+        }
+        self.padded = !blocks.is_empty();
+        Ok(())
+    }
+
+    /// Enforces the constraints of squeezing `num_outputs` elements, as `PoseidonSponge::squeeze`
+    pub fn squeeze<CS: ConstraintSystem>(
+        &mut self,
+        cs: &mut CS,
+        num_outputs: usize,
+    ) -> Result<Vec<LinearCombination>, R1CSError> {
+        if !self.squeezing {
+            if !self.padded {
+                self.pad(cs)?;
+            }
+            self.permute(cs)?;
+            self.pos = 0;
+            self.squeezing = true;
+        }
+        let mut out = Vec::with_capacity(num_outputs);
+        for _ in 0..num_outputs {
+            if self.pos == self.rate() {
+                self.permute(cs)?;
+                self.pos = 0;
+            }
+            out.push(self.state[self.capacity + self.pos].clone());
+            self.pos += 1;
+        }
+        Ok(out)
+    }
+
+    /// Add 1 after the last absorbed element as `PoseidonSponge::pad`
+    fn pad<CS: ConstraintSystem>(&mut self, cs: &mut CS) -> Result<(), R1CSError> {
+        if self.pos == self.rate() {
+            self.permute(cs)?;
+            self.pos = 0;
+        }
+        let i = self.capacity + self.pos;
+        self.state[i] = self.state[i].clone() + FieldElement::one();
+        Ok(())
+    }
+
+    fn permute<CS: ConstraintSystem>(&mut self, cs: &mut CS) -> Result<(), R1CSError> {
+        let state = std::mem::replace(&mut self.state, vec![]);
+        self.state = Poseidon_permutation_constraints::<CS>(cs, state, self.params, self.sbox)?
+            .into_iter()
+            .map(|s| s.simplify())
+            .collect();
+        Ok(())
+    }
+}
+
+/// Allocate `message` as blocks of `rate` elements to be absorbed with
+/// `PoseidonSpongeConstraints::absorb_padded`. The message is padded as the native sponge pads
+/// it, a 1 followed by as many 0s as needed to fill the last block, so there is a block only of
+/// padding when the message length is a multiple of `rate`. The padding elements are allocated
+/// and constrained to be 1 and 0 so that the prover cannot choose them. Absorbing the blocks gives
+/// the same digest as absorbing the message in the native sponge. The message values are only
+/// used by the prover. The verifier can pass any message of the same length.
+pub fn allocate_sponge_blocks<CS: ConstraintSystem>(
+    cs: &mut CS,
+    message: &[FieldElement],
+    rate: usize,
+) -> Result<Vec<Vec<AllocatedQuantity>>, R1CSError> {
+    if rate == 0 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: String::from("Rate should be at least 1"),
+        }
+        .into());
+    }
+    let mut elems = vec![];
+    for m in message {
+        let variable = cs.allocate(Some(m.clone()))?;
+        elems.push(AllocatedQuantity {
+            variable,
+            assignment: Some(m.clone()),
+        });
+    }
+    let num_padding = rate - message.len() % rate;
+    for i in 0..num_padding {
+        let p = if i == 0 {
+            FieldElement::one()
+        } else {
+            FieldElement::zero()
+        };
+        let variable = cs.allocate(Some(p.clone()))?;
+        constrain_lc_with_scalar(cs, variable.into(), &p);
+        elems.push(AllocatedQuantity {
+            variable,
+            assignment: Some(p),
+        });
+    }
+    Ok(elems.chunks(rate).map(|block| block.to_vec()).collect())
+}
+
+/// Hash the inputs to a point of group G1. The domain tag of `DOMAIN_LABEL_HASH_TO_POINT` and the
+/// inputs are absorbed in a `PoseidonSponge` and a squeezed element is mapped to the curve with
+/// `G1::from_msg_hash` on the element's bytes. `from_msg_hash` of `amcl_wrapper` uses the "hash and
+/// try-and-increment" map, i.e. the bytes are hashed to an x coordinate which is incremented until
+/// it is on the curve and the point is then multiplied by the cofactor. As the map cannot fail
+/// except by giving the identity, another element is squeezed if the identity is obtained.
+pub fn poseidon_hash_to_point(
+    inputs: &[FieldElement],
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> G1 {
+    // Capacity 1 is valid for any width
+    let mut sponge = PoseidonSponge::new(params, sbox, 1).unwrap();
+    sponge.absorb(&[domain_tag(DOMAIN_LABEL_HASH_TO_POINT)]);
+    sponge.absorb(inputs);
+    loop {
+        let e = sponge.squeeze(1).remove(0);
+        let point = G1::from_msg_hash(&e.to_bytes());
+        if !point.is_identity() {
+            return point;
+        }
+    }
+}
+
+/// Hash group elements to a field element. The coordinates of a point are elements of the base
+/// field which is bigger than the scalar field of `FieldElement`, so they cannot be absorbed
+/// directly. Instead the uncompressed serialization of the point by `amcl_wrapper`, i.e. a prefix
+/// byte followed by the affine x and y coordinates, is split into chunks of bytes small enough to
+/// always be less than the curve order and each chunk is absorbed as a field element. As the
+/// serialization of points of a group has fixed length, chunks of different points cannot be
+/// confused. The sponge first absorbs the domain tag of `DOMAIN_LABEL_HASH_POINTS` and the number
+/// of points. Each point is preceded by a flag, 0 for the identity and 1 otherwise, and the
+/// identity, having no affine coordinates, is absorbed as only the flag.
+pub fn poseidon_hash_points<G: GroupElement>(
+    points: &[G],
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, R1CSError> {
+    // Bytes per chunk such that the chunk has fewer bits than the curve order
+    let chunk_size = (CurveOrder.nbits() - 1) / 8;
+    let mut sponge = PoseidonSponge::new(params, sbox, 1)?;
+    sponge.absorb(&[
+        domain_tag(DOMAIN_LABEL_HASH_POINTS),
+        FieldElement::from(points.len() as u64),
+    ]);
+    for point in points {
+        if point.is_identity() {
+            sponge.absorb(&[FieldElement::zero()]);
+            continue;
+        }
+        sponge.absorb(&[FieldElement::one()]);
+        for chunk in point.to_bytes().chunks(chunk_size) {
+            let mut bytes = vec![0u8; MODBYTES - chunk.len()];
+            bytes.extend_from_slice(chunk);
+            let elem = FieldElement::from_bytes(&bytes).map_err(|_| {
+                R1CSError::from(R1CSErrorKind::GadgetError {
+                    description: String::from("Cannot convert point bytes to field element"),
+                })
+            })?;
+            sponge.absorb(&[elem]);
+        }
+    }
+    Ok(sponge.squeeze(1).remove(0))
+}
+
+/// Derive the nonce for `counter` under `key` for duplex encryption. The permutation is applied to
+/// the domain tag of `DOMAIN_LABEL_DUPLEX_NONCE` as capacity, the key and the counter (padded with
+/// 0s for width more than 3). As in the hash functions, the nonce is the output at index 1 and
+/// the first output is never taken. Errors if the width is less than 3 since the key and the
+/// counter would not both fit. Derivation is deterministic so distinct counters must be used for distinct messages:
+/// callers must never reuse a (key, nonce) pair, i.e. never encrypt twice with the same key and
+/// counter, as that leaks the relation between the plaintexts.
+/// There is no duplex encryption in this crate yet, an encryption function should take its nonce
+/// from here.
+pub fn derive_nonce(
+    key: FieldElement,
+    counter: u64,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, R1CSError> {
+    if params.width < 3 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Nonce derivation needs width at least 3 but found {}",
+                params.width
+            ),
+        }
+        .into());
+    }
+    let mut input = vec![
+        domain_tag(DOMAIN_LABEL_DUPLEX_NONCE),
+        key,
+        FieldElement::from(counter),
+    ];
+    input.resize(params.width, FieldElement::zero());
+    Ok(Poseidon_permutation_owned(input, params, sbox).remove(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::gadgets::helper_constraints::positive_no::positive_no_gadget;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    /// All sboxes enabled by features
+    fn all_sboxes() -> Vec<SboxType> {
+        let mut sboxes = vec![SboxType::Cube, SboxType::Quint];
+        #[cfg(feature = "inverse_sbox")]
+        sboxes.push(SboxType::Inverse);
+        sboxes
+    }
+
+    fn check_permutation_cross(params: &PoseidonParams, sbox_type: &SboxType) {
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let input = (0..params.width)
+            .map(|_| FieldElement::random())
+            .collect::<Vec<_>>();
+        let expected_output = Poseidon_permutation(&input, params, sbox_type);
+
+        let mut prover_transcript = Transcript::new(b"PoseidonPermutation");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+
+        let mut input_vars = vec![];
+        for i in 0..params.width {
+            let (_, var) = prover.commit(input[i].clone(), FieldElement::random());
+            input_vars.push(var.into());
+        }
+
+        let output_vars =
+            Poseidon_permutation_constraints(&mut prover, input_vars, params, sbox_type).unwrap();
+        for i in 0..params.width {
+            assert_eq!(
+                prover.evaluate_lc(&output_vars[i]).unwrap(),
+                expected_output[i]
+            );
+        }
+    }
+
+    fn sponge_block_lcs(blocks: &[Vec<AllocatedQuantity>]) -> Vec<Vec<LinearCombination>> {
+        blocks
+            .iter()
+            .map(|block| block.iter().map(|q| q.variable.into()).collect())
+            .collect()
+    }
+
+    fn check_sponge_blocks(
+        message: &[FieldElement],
+        digest: &FieldElement,
+        params: &PoseidonParams,
+        capacity: usize,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 1024).into();
+        let H: G1Vector = get_generators("H", 1024).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let sbox = SboxType::Quint;
+        let rate = params.width - capacity;
+
+        let proof = {
+            let mut prover_transcript = Transcript::new(b"SpongeBlocks");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let blocks = allocate_sponge_blocks(&mut prover, message, rate).unwrap();
+            assert_eq!(blocks.len(), message.len() / rate + 1);
+            assert!(blocks.iter().all(|b| b.len() == rate));
+            // The message followed by 1 and then 0s
+            let assignments = blocks
+                .iter()
+                .flatten()
+                .map(|q| q.assignment.clone().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(&assignments[..message.len()], message);
+            assert_eq!(assignments[message.len()], FieldElement::one());
+            assert!(assignments[message.len() + 1..].iter().all(|a| a.is_zero()));
+
+            let mut sponge = PoseidonSpongeConstraints::new(params, &sbox, capacity).unwrap();
+            sponge
+                .absorb_padded(&mut prover, &sponge_block_lcs(&blocks))
+                .unwrap();
+            let out = sponge.squeeze(&mut prover, 1).unwrap().remove(0);
+            constrain_lc_with_scalar(&mut prover, out, digest);
+            prover.prove(&G, &H).unwrap()
+        };
+
+        let mut verifier_transcript = Transcript::new(b"SpongeBlocks");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let unknown = vec![FieldElement::zero(); message.len()];
+        let blocks = allocate_sponge_blocks(&mut verifier, &unknown, rate).unwrap();
+        let mut sponge = PoseidonSpongeConstraints::new(params, &sbox, capacity).unwrap();
+        sponge
+            .absorb_padded(&mut verifier, &sponge_block_lcs(&blocks))
+            .unwrap();
+        let out = sponge.squeeze(&mut verifier, 1).unwrap().remove(0);
+        constrain_lc_with_scalar(&mut verifier, out, digest);
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_allocate_sponge_blocks() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let sbox = SboxType::Quint;
+
+        // Rate 3 and 4, with and without a partial last block and the empty message
+        for &(capacity, len) in [(2, 7), (2, 6), (1, 5), (1, 1), (1, 8), (2, 0)].iter() {
+            let message = (0..len).map(|_| FieldElement::random()).collect::<Vec<_>>();
+            let mut native = PoseidonSponge::new(&params, &sbox, capacity).unwrap();
+            native.absorb(&message);
+            let digest = native.squeeze(1).remove(0);
+            assert!(check_sponge_blocks(&message, &digest, &params, capacity));
+            assert!(!check_sponge_blocks(
+                &message,
+                &FieldElement::random(),
+                &params,
+                capacity
+            ));
+        }
+
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let mut prover_transcript = Transcript::new(b"SpongeBlocks");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        assert!(allocate_sponge_blocks(&mut prover, &[FieldElement::one()], 0).is_err());
+        let blocks = allocate_sponge_blocks(&mut prover, &[], 2).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0][0].assignment, Some(FieldElement::one()));
+        assert_eq!(blocks[0][1].assignment, Some(FieldElement::zero()));
+
+        // Padded blocks are rejected when not aligned to the rate of the sponge
+        let blocks = sponge_block_lcs(&allocate_sponge_blocks(&mut prover, &[], 3).unwrap());
+        let mut sponge = PoseidonSpongeConstraints::new(&params, &sbox, 1).unwrap();
+        assert!(sponge.absorb_padded(&mut prover, &blocks).is_err());
+        let blocks = sponge_block_lcs(&allocate_sponge_blocks(&mut prover, &[], 4).unwrap());
+        sponge
+            .absorb(&mut prover, &[FieldElement::one().into()])
+            .unwrap();
+        assert!(sponge.absorb_padded(&mut prover, &blocks).is_err());
+    }
+
+    #[test]
+    fn test_sponge_padding() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let sbox = SboxType::Quint;
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        // Trailing 0s, including ones filling the rate, change the output
+        let message = (0..3).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let digest = |m: &[FieldElement]| {
+            let mut sponge = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+            sponge.absorb(m);
+            sponge.squeeze(1).remove(0)
+        };
+        let mut padded = message.clone();
+        let mut digests = vec![digest(&message)];
+        for _ in 0..5 {
+            padded.push(FieldElement::zero());
+            digests.push(digest(&padded));
+        }
+        for i in 0..digests.len() {
+            for j in i + 1..digests.len() {
+                assert_ne!(digests[i], digests[j]);
+            }
+        }
+
+        // Same as the explicit 1 followed by 0s absorbed without padding
+        let mut state = vec![FieldElement::zero()];
+        state.extend_from_slice(&message);
+        state.push(FieldElement::one());
+        assert_eq!(digests[0], Poseidon_permutation(&state, &params, &sbox)[1]);
+
+        // Nothing absorbed still pads
+        let mut sponge = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+        let mut state = vec![FieldElement::zero(); 5];
+        state[1] = FieldElement::one();
+        assert_eq!(
+            sponge.squeeze(1)[0],
+            Poseidon_permutation(&state, &params, &sbox)[1]
+        );
+
+        // Constraints pad the same way
+        let mut prover_transcript = Transcript::new(b"SpongePadding");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        padded.truncate(message.len());
+        for expected in &digests {
+            let inputs = padded
+                .iter()
+                .map(|m| prover.commit(m.clone(), FieldElement::random()).1.into())
+                .collect::<Vec<LinearCombination>>();
+            let mut sponge = PoseidonSpongeConstraints::new(&params, &sbox, 1).unwrap();
+            sponge.absorb(&mut prover, &inputs).unwrap();
+            let out = sponge.squeeze(&mut prover, 1).unwrap().remove(0);
+            assert_eq!(prover.evaluate_lc(&out).unwrap(), *expected);
+            padded.push(FieldElement::zero());
+        }
+    }
+
+    #[test]
+    fn test_sponge_squeeze_into() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox = SboxType::Quint;
+        let inputs = (0..5).map(|_| FieldElement::random()).collect::<Vec<_>>();
+
+        let mut sponge = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+        sponge.absorb(&inputs);
+        let mut sponge_1 = sponge.clone();
+
+        let expected = sponge.squeeze(2);
+        let mut out = vec![FieldElement::zero(); 2];
+        sponge_1.squeeze_into(&mut out);
+        for i in 0..2 {
+            assert_eq!(out[i], expected[i]);
+        }
+
+        // Squeezing more than the rate permutes and both continue to match
+        let expected = sponge.squeeze(3);
+        let mut out = vec![FieldElement::zero(); 3];
+        sponge_1.squeeze_into(&mut out);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_sponge_capacity() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let sbox = SboxType::Quint;
+        assert!(PoseidonSponge::new(&params, &sbox, 0).is_err());
+        assert!(PoseidonSponge::new(&params, &sbox, 5).is_err());
+
+        let inputs = (0..3).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let mut sponge = PoseidonSponge::new(&params, &sbox, 2).unwrap();
+        assert_eq!(sponge.capacity(), 2);
+        assert_eq!(sponge.rate(), 3);
+        sponge.absorb(&inputs);
+        let out = sponge.squeeze(4);
+
+        // Rate of 3 elements after the 2 capacity elements, first of which is 1 in the IV. The
+        // inputs fill the rate so the padding 1 is in the next block.
+        let mut state = vec![FieldElement::one(), FieldElement::zero()];
+        state.extend_from_slice(&inputs);
+        let mut state = Poseidon_permutation(&state, &params, &sbox);
+        state[2] += FieldElement::one();
+        let state = Poseidon_permutation(&state, &params, &sbox);
+        assert_eq!(&out[..3], &state[2..]);
+        let state = Poseidon_permutation(&state, &params, &sbox);
+        assert_eq!(out[3], state[2]);
+
+        // Differs from capacity 1 sponge over the same inputs
+        let mut sponge_1 = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+        sponge_1.absorb(&inputs);
+        assert_ne!(sponge_1.squeeze(1)[0], out[0]);
+
+        // Checkpoint round trip keeps the capacity
+        let mut sponge = PoseidonSponge::new(&params, &sbox, 2).unwrap();
+        sponge.absorb(&inputs[..2]);
+        let bytes = sponge.checkpoint();
+        let mut restored = PoseidonSponge::restore(&bytes, &params, &sbox, 2).unwrap();
+        sponge.absorb(&inputs[2..]);
+        restored.absorb(&inputs[2..]);
+        assert_eq!(restored.squeeze(4), out);
+        assert_eq!(sponge.squeeze(4), out);
+    }
+
+    #[test]
+    fn test_sponge_absorb_with_tweak() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let sbox = SboxType::Quint;
+        let inputs = (0..6).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let tweak_1 = FieldElement::random();
+        let tweak_2 = FieldElement::random();
+
+        let hash = |tweak: &FieldElement| {
+            let mut sponge = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+            sponge.absorb_with_tweak(&inputs[..3], tweak.clone());
+            sponge.absorb(&inputs[3..]);
+            sponge.squeeze(2)
+        };
+        let out_1 = hash(&tweak_1);
+        assert_eq!(hash(&tweak_1), out_1);
+        assert_ne!(hash(&tweak_2), out_1);
+
+        // Tweak is added to the capacity before the permutation following the first absorption
+        let mut state = vec![tweak_1.clone()];
+        state.extend_from_slice(&inputs[..3]);
+        state.push(FieldElement::zero());
+        let mut state = Poseidon_permutation(&state, &params, &sbox);
+        for i in 0..3 {
+            state[1 + i] += &inputs[3 + i];
+        }
+        state[4] += FieldElement::one();
+        let state = Poseidon_permutation(&state, &params, &sbox);
+        assert_eq!(&out_1[..], &state[1..3]);
+
+        // Differs from absorbing the tweak as an input
+        let mut sponge = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+        sponge.absorb(&inputs[..3]);
+        sponge.absorb(&[tweak_1]);
+        sponge.absorb(&inputs[3..]);
+        assert_ne!(sponge.squeeze(2), out_1);
+    }
+
+    #[test]
+    fn test_sponge_absorb_tagged() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let sbox = SboxType::Quint;
+        let ids = (0..2).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let amounts = (0..3).map(|_| FieldElement::random()).collect::<Vec<_>>();
+
+        let hash = |groups: &[(u64, &[FieldElement])]| {
+            let mut sponge = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+            for (tag, elems) in groups {
+                sponge.absorb_tagged(*tag, elems);
+            }
+            sponge.squeeze(1)
+        };
+        let digest = hash(&[(1, &ids), (2, &amounts)]);
+        assert_eq!(hash(&[(1, &ids), (2, &amounts)]), digest);
+
+        // Same as absorbing the elements with the tag added to the first element of each group
+        let mut sponge = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+        sponge.absorb(&[&ids[0] + &FieldElement::one(), ids[1].clone()]);
+        sponge.absorb(&[&amounts[0] + &FieldElement::from(2u64)]);
+        sponge.absorb(&amounts[1..]);
+        assert_eq!(sponge.squeeze(1), digest);
+
+        // Swapping the tags or reordering the groups changes the digest
+        assert_ne!(hash(&[(2, &ids), (1, &amounts)]), digest);
+        assert_ne!(hash(&[(2, &amounts), (1, &ids)]), digest);
+        assert_ne!(hash(&[(1, &amounts), (2, &ids)]), digest);
+
+        // Differs from untagged absorption and an empty group still absorbs its tag
+        let mut sponge = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+        sponge.absorb(&ids);
+        sponge.absorb(&amounts);
+        assert_ne!(sponge.squeeze(1), digest);
+        assert_ne!(hash(&[(1, &ids), (3, &[]), (2, &amounts)]), digest);
+    }
+
+    #[test]
+    fn test_sponge_checkpoint_restore() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let sbox = SboxType::Quint;
+        let inputs = (0..10).map(|_| FieldElement::random()).collect::<Vec<_>>();
+
+        let mut sponge = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+        sponge.absorb(&inputs);
+        let expected = sponge.squeeze(1);
+
+        // Absorb half, checkpoint, restore and absorb the rest
+        let mut sponge_1 = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+        sponge_1.absorb(&inputs[..5]);
+        let bytes = sponge_1.checkpoint();
+        let mut sponge_2 = PoseidonSponge::restore(&bytes, &params, &sbox, 1).unwrap();
+        sponge_2.absorb(&inputs[5..]);
+        assert_eq!(sponge_2.squeeze(1), expected);
+
+        assert!(PoseidonSponge::restore(&bytes[1..], &params, &sbox, 1).is_err());
+        let mut bad_flag = bytes.clone();
+        *bad_flag.last_mut().unwrap() = 2;
+        assert!(PoseidonSponge::restore(&bad_flag, &params, &sbox, 1).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_hash_2_prove() {
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let G: G1Vector = get_generators("G", 512).into();
+        let H: G1Vector = get_generators("H", 512).into();
+
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        for sbox_type in all_sboxes() {
+            let inputs = vec![FieldElement::random(), FieldElement::random()];
+            let expected = Poseidon_hash_2(inputs.clone(), &params, &sbox_type).unwrap();
+
+            let proof = {
+                let mut prover_transcript = Transcript::new(b"Hash2Prove");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                assert!(Poseidon_hash_2_prove(
+                    &mut prover,
+                    vec![FieldElement::random()],
+                    &params,
+                    &sbox_type
+                )
+                .is_err());
+                let (digest, hash) =
+                    Poseidon_hash_2_prove(&mut prover, inputs, &params, &sbox_type).unwrap();
+                assert_eq!(digest, expected);
+                constrain_lc_with_scalar(&mut prover, hash, &digest);
+                prover.prove(&G, &H).unwrap()
+            };
+
+            // Verifier allocates the inputs the same way without values
+            let mut verifier_transcript = Transcript::new(b"Hash2Prove");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let vars = vec![
+                verifier.allocate(None).unwrap().into(),
+                verifier.allocate(None).unwrap().into(),
+            ];
+            let hash = Poseidon_hash_2_constraints(
+                &mut verifier,
+                vars,
+                FieldElement::from(CAP_CONST_W_3).into(),
+                &params,
+                &sbox_type,
+            )
+            .unwrap();
+            constrain_lc_with_scalar(&mut verifier, hash, &expected);
+            assert!(verifier.verify(&proof, &g, &h, &G, &H).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_constrain_hash_sum() {
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let G: G1Vector = get_generators("G", 1024).into();
+        let H: G1Vector = get_generators("H", 1024).into();
+
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox_type = &SboxType::Quint;
+        let inputs = (0..6).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let hash_1 = Poseidon_hash_2(inputs[0..2].to_vec(), &params, sbox_type).unwrap();
+        let hash_2 = Poseidon_hash_2(inputs[2..4].to_vec(), &params, sbox_type).unwrap();
+        let sum = &hash_1 + &hash_2;
+
+        // Hashes of the first 2 pairs of inputs are computed in the circuit. The third term is a
+        // committed value in the first 2 cases and the hash of the third pair of inputs in the
+        // last case, for which the relation does not hold.
+        for (third, expected) in vec![
+            (Some(sum.clone()), true),
+            (Some(&sum + &FieldElement::one()), false),
+            (None, false),
+        ] {
+            let (proof, commitments) = {
+                let mut prover_transcript = Transcript::new(b"HashSum");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let (mut comms, vars): (Vec<_>, Vec<_>) = inputs
+                    .iter()
+                    .map(|i| prover.commit(i.clone(), FieldElement::random()))
+                    .unzip();
+                let mut hashes = vec![];
+                for pair in vars.chunks(2) {
+                    hashes.push(
+                        Poseidon_hash_2_constraints(
+                            &mut prover,
+                            vec![pair[0].into(), pair[1].into()],
+                            FieldElement::from(CAP_CONST_W_3).into(),
+                            &params,
+                            sbox_type,
+                        )
+                        .unwrap(),
+                    );
+                }
+                let h3 = match &third {
+                    Some(v) => {
+                        let (c, var) = prover.commit(v.clone(), FieldElement::random());
+                        comms.push(c);
+                        var.into()
+                    }
+                    None => hashes.pop().unwrap(),
+                };
+                let h2 = hashes.remove(1);
+                let h1 = hashes.remove(0);
+                constrain_hash_sum_gadget(&mut prover, h1, h2, h3);
+                (prover.prove(&G, &H).unwrap(), comms)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"HashSum");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let vars = commitments
+                .iter()
+                .map(|c| verifier.commit(c.clone()))
+                .collect::<Vec<_>>();
+            let mut hashes = vec![];
+            for pair in vars[..6].chunks(2) {
+                hashes.push(
+                    Poseidon_hash_2_constraints(
+                        &mut verifier,
+                        vec![pair[0].into(), pair[1].into()],
+                        FieldElement::from(CAP_CONST_W_3).into(),
+                        &params,
+                        sbox_type,
+                    )
+                    .unwrap(),
+                );
+            }
+            let h3 = match third {
+                Some(_) => vars[6].into(),
+                None => hashes.pop().unwrap(),
+            };
+            let h2 = hashes.remove(1);
+            let h1 = hashes.remove(0);
+            constrain_hash_sum_gadget(&mut verifier, h1, h2, h3);
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+
+    #[test]
+    fn test_poseidon_public_output() {
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let G: G1Vector = get_generators("G", 512).into();
+        let H: G1Vector = get_generators("H", 512).into();
+
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox_type = &SboxType::Quint;
+        let inputs = vec![FieldElement::random(), FieldElement::random()];
+        let expected = Poseidon_hash_2(inputs.clone(), &params, sbox_type).unwrap();
+
+        // Circuit A computes the hash and returns its value
+        let mut transcript_a = Transcript::new(b"CircuitA");
+        let mut prover_a = Prover::new(&g, &h, &mut transcript_a);
+        let input_vars = inputs
+            .iter()
+            .map(|i| prover_a.commit(i.clone(), FieldElement::random()).1.into())
+            .collect::<Vec<_>>();
+        let (_, value) =
+            poseidon_public_output_gadget(&mut prover_a, input_vars, &params, sbox_type).unwrap();
+        let value = value.unwrap();
+        assert_eq!(value, expected);
+
+        // Circuit B uses the value as public input
+        let (proof, commitments) = {
+            let mut transcript_b = Transcript::new(b"CircuitB");
+            let mut prover_b = Prover::new(&g, &h, &mut transcript_b);
+            let (comms, vars): (Vec<_>, Vec<_>) = inputs
+                .iter()
+                .map(|i| prover_b.commit(i.clone(), FieldElement::random()))
+                .unzip();
+            let (hash, _) = poseidon_public_output_gadget(
+                &mut prover_b,
+                vars.into_iter().map(|v| v.into()).collect(),
+                &params,
+                sbox_type,
+            )
+            .unwrap();
+            constrain_lc_with_scalar(&mut prover_b, hash, &value);
+            (prover_b.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut transcript_b = Transcript::new(b"CircuitB");
+        let mut verifier_b = Verifier::new(&mut transcript_b);
+        let vars = commitments
+            .into_iter()
+            .map(|c| verifier_b.commit(c).into())
+            .collect();
+        let (hash, v) =
+            poseidon_public_output_gadget(&mut verifier_b, vars, &params, sbox_type).unwrap();
+        assert!(v.is_none());
+        constrain_lc_with_scalar(&mut verifier_b, hash, &value);
+        assert!(verifier_b.verify(&proof, &g, &h, &G, &H).is_ok());
+    }
+
+    #[test]
+    fn test_poseidon_hash_2_as_quantity() {
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let G: G1Vector = get_generators("G", 1024).into();
+        let H: G1Vector = get_generators("H", 1024).into();
+
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox_type = &SboxType::Quint;
+        let inputs = vec![FieldElement::random(), FieldElement::random()];
+        let expected = Poseidon_hash_2(inputs.clone(), &params, sbox_type).unwrap();
+
+        // Hash output is in [0, 2^255) but is almost certainly not in [0, 2^64)
+        for &(n, expected_result) in [(255, true), (64, false)].iter() {
+            let (proof, commitments) = {
+                let mut prover_transcript = Transcript::new(b"HashAsQuantity");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let (comms, vars): (Vec<_>, Vec<_>) = inputs
+                    .iter()
+                    .map(|i| prover.commit(i.clone(), FieldElement::random()))
+                    .unzip();
+                let hash = Poseidon_hash_2_as_quantity(
+                    &mut prover,
+                    vars.into_iter().map(|v| v.into()).collect(),
+                    FieldElement::from(CAP_CONST_W_3).into(),
+                    &params,
+                    sbox_type,
+                )
+                .unwrap();
+                assert_eq!(hash.assignment.clone().unwrap(), expected);
+                positive_no_gadget(&mut prover, hash, n).unwrap();
+                (prover.prove(&G, &H).unwrap(), comms)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"HashAsQuantity");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let vars = commitments
+                .into_iter()
+                .map(|c| verifier.commit(c).into())
+                .collect();
+            let hash = Poseidon_hash_2_as_quantity(
+                &mut verifier,
+                vars,
+                FieldElement::from(CAP_CONST_W_3).into(),
+                &params,
+                sbox_type,
+            )
+            .unwrap();
+            positive_no_gadget(&mut verifier, hash, n).unwrap();
+            assert_eq!(
+                verifier.verify(&proof, &g, &h, &G, &H).is_ok(),
+                expected_result
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_integrity() {
+        for &(width, partial_rounds) in [(3, 55), (5, 56), (9, 57)].iter() {
+            let params = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+            assert!(params.verify_integrity());
+
+            let mut mutated = params.clone();
+            mutated.MDS_matrix[width - 1][1] += FieldElement::one();
+            assert!(!mutated.verify_integrity());
+
+            let mut mutated = params.clone();
+            mutated.round_keys[10] = FieldElement::zero();
+            assert!(!mutated.verify_integrity());
+
+            // Not using all round constants
+            let params = PoseidonParams::new(width, 4, 4, partial_rounds - 1).unwrap();
+            assert!(!params.verify_integrity());
+        }
+    }
+
+    #[cfg(any(feature = "bls381", feature = "bn254", feature = "ed25519"))]
+    #[test]
+    fn test_fingerprint() {
+        let mut fingerprints = vec![];
+        for &(width, partial_rounds) in [(3, 55), (5, 56), (9, 57)].iter() {
+            let params = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+            let fingerprint = params.fingerprint();
+            assert_eq!(
+                PoseidonParams::new(width, 4, 4, partial_rounds)
+                    .unwrap()
+                    .fingerprint(),
+                fingerprint
+            );
+            fingerprints.push(fingerprint.clone());
+
+            let mut mutated = params.clone();
+            mutated.MDS_matrix[0][width - 1] += FieldElement::one();
+            fingerprints.push(mutated.fingerprint());
+
+            let mut mutated = params.clone();
+            mutated.round_keys[3] += FieldElement::one();
+            fingerprints.push(mutated.fingerprint());
+
+            // Same round keys but different split of rounds
+            let mut mutated = params.clone();
+            mutated.full_rounds_beginning += 1;
+            mutated.partial_rounds -= 1;
+            fingerprints.push(mutated.fingerprint());
+
+            fingerprints.push(
+                PoseidonParams::new(width, 4, 4, partial_rounds - 1)
+                    .unwrap()
+                    .fingerprint(),
+            );
+        }
+        for i in 0..fingerprints.len() {
+            for j in i + 1..fingerprints.len() {
+                assert_ne!(fingerprints[i], fingerprints[j]);
+            }
+        }
+    }
+
+    #[cfg(any(feature = "bls381", feature = "bn254", feature = "ed25519"))]
+    #[test]
+    fn test_checked_hashes() {
+        let params = PoseidonParams::new(9, 4, 4, 57).unwrap();
+        let sbox = SboxType::Quint;
+        let inputs = (0..8).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let bytes = inputs.iter().map(|i| i.to_bytes()).collect::<Vec<_>>();
+        assert!(bytes.iter().all(|b| is_canonical_field_element_bytes(b)));
+        for n in 1..9 {
+            assert_eq!(
+                Poseidon_hash_n_checked(bytes[..n].to_vec(), &params, &sbox).unwrap(),
+                Poseidon_hash_n(inputs[..n].to_vec(), &params, &sbox).unwrap()
+            );
+        }
+        assert_eq!(
+            Poseidon_hash_8_checked(bytes.clone(), &params, &sbox).unwrap(),
+            Poseidon_hash_8(inputs.clone(), &params, &sbox).unwrap()
+        );
+        // Errors of the unchecked hash are still returned
+        assert!(Poseidon_hash_8_checked(bytes[..7].to_vec(), &params, &sbox).is_err());
+
+        // The curve order plus 1 is not canonical, the checked hash rejects it with its index
+        // while the default path hashes it reduced to 1
+        let mut order_plus_1 = CurveOrder;
+        order_plus_1.w[0] += 1;
+        let mut non_canonical = vec![0u8; MODBYTES];
+        order_plus_1.tobytes(&mut non_canonical);
+        let mut curve_order = CurveOrder;
+        let mut order = vec![0u8; MODBYTES];
+        curve_order.tobytes(&mut order);
+        assert!(!is_canonical_field_element_bytes(&non_canonical));
+        assert!(!is_canonical_field_element_bytes(&order));
+        assert!(is_canonical_field_element_bytes(
+            &(-FieldElement::one()).to_bytes()
+        ));
+        assert!(!is_canonical_field_element_bytes(&bytes[0][1..]));
+
+        let reduced = FieldElement::from_bytes(&non_canonical).unwrap();
+        assert_eq!(reduced, FieldElement::one());
+        assert_eq!(
+            Poseidon_hash_n(vec![inputs[0].clone(), reduced], &params, &sbox).unwrap(),
+            Poseidon_hash_n(vec![inputs[0].clone(), FieldElement::one()], &params, &sbox).unwrap()
+        );
+        for (checked, index) in vec![
+            (vec![bytes[0].clone(), non_canonical.clone()], 1),
+            (vec![order.clone(), bytes[0].clone()], 0),
+            (vec![bytes[0].clone(), bytes[1][1..].to_vec()], 1),
+        ] {
+            match Poseidon_hash_n_checked(checked, &params, &sbox)
+                .unwrap_err()
+                .kind()
+            {
+                BulletproofErrorKind::NonCanonicalInputForPoseidon { index: i } => {
+                    assert_eq!(i, index)
+                }
+                k => panic!("Unexpected error {:?}", k),
+            }
+        }
+    }
+
+    #[test]
+    fn test_new_validated() {
+        // Rounds used throughout for widths 3, 5 and 9 are enough for quint sbox
+        for &(width, partial_rounds) in [(3, 55), (5, 56), (9, 57)].iter() {
+            let params =
+                PoseidonParams::new_validated(width, 4, 4, partial_rounds, &SboxType::Quint)
+                    .unwrap();
+            assert_eq!(
+                params.to_bytes(),
+                PoseidonParams::new(width, 4, 4, partial_rounds)
+                    .unwrap()
+                    .to_bytes()
+            );
+            assert_eq!(
+                PoseidonParams::min_secure_rounds(width, 8, &SboxType::Quint),
+                (8, partial_rounds)
+            );
+            // and are 1 partial round above the insecure ones
+            let err =
+                PoseidonParams::new_validated(width, 4, 4, partial_rounds - 1, &SboxType::Quint)
+                    .unwrap_err();
+            assert!(err
+                .to_string()
+                .contains(&format!("at least {} partial rounds", partial_rounds)));
+        }
+
+        // Recommendations of calc_round_numbers.py for 128 bit security, the same for fields of 253
+        // to 256 bits
+        let mut expected = vec![
+            (SboxType::Cube, [(8, 83), (8, 83), (8, 84)]),
+            (SboxType::Quint, [(8, 55), (8, 56), (8, 57)]),
+        ];
+        #[cfg(feature = "inverse_sbox")]
+        expected.push((SboxType::Inverse, [(8, 133), (8, 128), (8, 124)]));
+        for (sbox, rounds) in expected {
+            for (&width, &r) in [3, 5, 9].iter().zip(rounds.iter()) {
+                assert_eq!(PoseidonParams::recommended_rounds(width, &sbox), r);
+                assert_eq!(PoseidonParams::min_secure_rounds(width, r.0, &sbox), r);
+            }
+        }
+
+        // More full rounds need fewer partial rounds. With 8 full rounds without the margin, the
+        // interpolation bound needs 0.43 * 128 + log_2(3) - 8 = 48.6 partial rounds and
+        // 49 * 1.075 = 52.7
+        assert_eq!(
+            PoseidonParams::min_secure_rounds(3, 10, &SboxType::Quint),
+            (8, 53)
+        );
+        assert!(PoseidonParams::new_validated(3, 5, 5, 53, &SboxType::Quint).is_ok());
+        assert!(PoseidonParams::new_validated(3, 5, 5, 52, &SboxType::Quint).is_err());
+
+        // Cube sbox has lower degree so needs more rounds
+        let err = PoseidonParams::new_validated(3, 4, 4, 55, &SboxType::Cube).unwrap_err();
+        assert!(err.to_string().contains("at least 83 partial rounds"));
+        #[cfg(feature = "inverse_sbox")]
+        assert!(PoseidonParams::new_validated(5, 4, 4, 56, &SboxType::Inverse).is_err());
+
+        // Too few full rounds even with many partial rounds
+        assert!(PoseidonParams::new(5, 3, 3, 58).is_ok());
+        assert!(PoseidonParams::new_validated(5, 3, 3, 58, &SboxType::Quint).is_err());
+        assert!(PoseidonParams::new_validated(3, 3, 3, 60, &SboxType::Quint).is_err());
+
+        // Unvalidated constructor still allows reduced rounds
+        assert!(PoseidonParams::new(3, 1, 1, 2).is_ok());
+    }
+
+    #[test]
+    fn test_validate_partial_rounds() {
+        for (width, partial_rounds) in vec![(3, 55), (5, 56), (9, 57)] {
+            let params = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+            assert!(params.validate().is_ok());
+        }
+
+        // Only full rounds for wide params is flagged
+        for &width in [5, 9].iter() {
+            let params = PoseidonParams::new(width, 4, 4, 0).unwrap();
+            assert!(params.validate().is_err());
+        }
+        // but allowed for width 3
+        let params = PoseidonParams::new(3, 4, 4, 0).unwrap();
+        assert!(params.validate().is_ok());
+    }
+
+    #[test]
+    fn test_permutation_constraints_jagged_mds() {
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+
+        let mut short_row = params.clone();
+        short_row.MDS_matrix[1].pop();
+        let mut missing_row = params.clone();
+        missing_row.MDS_matrix.pop();
+
+        for p in vec![&short_row, &missing_row] {
+            assert!(p.validate().is_err());
+            let mut prover_transcript = Transcript::new(b"JaggedMDS");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let input = (0..3)
+                .map(|_| {
+                    let (_, var) = prover.commit(FieldElement::random(), FieldElement::random());
+                    var.into()
+                })
+                .collect::<Vec<LinearCombination>>();
+            assert!(
+                Poseidon_permutation_constraints(&mut prover, input, p, &SboxType::Quint).is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_random_for_testing() {
+        let sbox = SboxType::Quint;
+        for &width in [2, 3, 5, 9].iter() {
+            let params = PoseidonParams::random_for_testing(width, 42);
+            assert!(params.validate().is_ok());
+            assert!(!params.mds_determinant().is_zero());
+            assert!(!params.verify_integrity());
+
+            // Same seed gives same params, different seed gives different params
+            let same = PoseidonParams::random_for_testing(width, 42);
+            assert_eq!(same.to_bytes(), params.to_bytes());
+            let other = PoseidonParams::random_for_testing(width, 43);
+            assert_ne!(other.to_bytes(), params.to_bytes());
+
+            let input = (0..width)
+                .map(|_| FieldElement::random())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                Poseidon_permutation(&input, &params, &sbox),
+                Poseidon_permutation(&input, &same, &sbox)
+            );
+        }
+    }
+
+    #[cfg(any(feature = "bls381", feature = "bn254", feature = "ed25519"))]
+    #[test]
+    fn test_from_trusted_blob() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let fingerprint = params.fingerprint();
+        let bytes = params.to_bytes();
+
+        // Good blob
+        let restored = PoseidonParams::from_trusted_blob(&bytes, fingerprint.clone()).unwrap();
+        assert_eq!(restored.to_bytes(), bytes);
+        assert_eq!(restored.round_keys, params.round_keys);
+        let input = (0..5).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let sbox = SboxType::Quint;
+        assert_eq!(
+            Poseidon_permutation(&input, &restored, &sbox),
+            Poseidon_permutation(&input, &params, &sbox)
+        );
+
+        // Tampered blob, a changed round key
+        let mut tampered = params.clone();
+        tampered.round_keys[7] += FieldElement::one();
+        let tampered_bytes = tampered.to_bytes();
+        assert!(PoseidonParams::from_bytes(&tampered_bytes).is_ok());
+        assert!(PoseidonParams::from_trusted_blob(&tampered_bytes, fingerprint.clone()).is_err());
+
+        // Structurally invalid blobs
+        assert!(PoseidonParams::from_trusted_blob(&[], fingerprint.clone()).is_err());
+        let mut wrong_version = bytes.clone();
+        wrong_version[0] = 2;
+        assert!(PoseidonParams::from_trusted_blob(&wrong_version, fingerprint.clone()).is_err());
+        assert!(
+            PoseidonParams::from_trusted_blob(&bytes[..bytes.len() - 1], fingerprint.clone())
+                .is_err()
+        );
+        let mut huge_width = bytes.clone();
+        huge_width[1..9].copy_from_slice(&u64::max_value().to_be_bytes());
+        assert!(PoseidonParams::from_trusted_blob(&huge_width, fingerprint.clone()).is_err());
+
+        // Well formed but singular MDS matrix
+        let mut singular = params.clone();
+        singular.MDS_matrix[1] = singular.MDS_matrix[0].clone();
+        assert!(PoseidonParams::from_bytes(&singular.to_bytes()).is_ok());
+        assert!(
+            PoseidonParams::from_trusted_blob(&singular.to_bytes(), singular.fingerprint())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_mds_full_diffusion() {
+        // Diffusion property: after a single full round, changing any one element of the input
+        // changes every element of the output. The Sbox layer changes only that element, the
+        // linear layer then adds `delta * MDS[i][j]` to every output `j` so this holds when no
+        // entry of the MDS matrix is zero. Not a proof of the matrix being MDS but catches gross
+        // corruption of the tables like a row or column of zeros.
+        for &width in [3, 5, 9].iter() {
+            // Only 1 full round
+            let params = PoseidonParams::new(width, 1, 0, 0).unwrap();
+            for sbox in all_sboxes().iter() {
+                for _ in 0..5 {
+                    let input = (0..width)
+                        .map(|_| FieldElement::random())
+                        .collect::<Vec<_>>();
+                    let output = Poseidon_permutation(&input, &params, sbox);
+                    for i in 0..width {
+                        let mut perturbed = input.clone();
+                        perturbed[i] += FieldElement::random();
+                        let perturbed_output = Poseidon_permutation(&perturbed, &params, sbox);
+                        for j in 0..width {
+                            assert_ne!(output[j], perturbed_output[j]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_constant_tables_structure() {
+        // The constants of the tables are also checked at compile time by
+        // `assert_table_structure` in `poseidon_constants.rs`. Changing a constant of
+        // `ROUND_CONSTS_W_9` to the curve order or removing the "0x" prefix of a constant fails
+        // `cargo build` as evaluating `all_canonical` for the table panics. That check compares
+        // against `CURVE_ORDER_HEX` so it should be the curve order.
+        assert_eq!(
+            CURVE_ORDER_HEX,
+            CurveOrder.tostring().trim_start_matches('0').to_lowercase()
+        );
+        for &(width, partial_rounds) in [(3, 55), (5, 56), (9, 57)].iter() {
+            let params = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+            assert_eq!(params.round_keys.len(), width * (8 + partial_rounds));
+            assert_eq!(params.MDS_matrix.len(), width);
+            for row in &params.MDS_matrix {
+                assert_eq!(row.len(), width);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reject_non_canonical_constant() {
+        let order = CurveOrder.tostring();
+        let order_minus_1 = (-FieldElement::one()).to_hex();
+        assert!(
+            PoseidonParams::<FieldElement>::get_field_element_from_hex_str(&format!(
+                "0x{}",
+                order_minus_1
+            ))
+            .is_ok()
+        );
+        // Curve order and values greater than it are rejected even with leading zeros or lowercase
+        for c in vec![
+            format!("0x{}", order),
+            format!("0x000{}", order.to_lowercase()),
+            format!("0x{}", "f".repeat(order.trim_start_matches('0').len())),
+            format!("0x1{}", order_minus_1),
+        ] {
+            assert!(PoseidonParams::<FieldElement>::get_field_element_from_hex_str(&c).is_err());
+        }
+    }
+
+    #[test]
+    fn test_round_keys_by_round() {
+        for &(width, partial_rounds) in [(3, 55), (5, 56), (9, 57)].iter() {
+            let params = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+            assert_eq!(params.round_keys_by_round().len(), 8 + partial_rounds);
+            for (r, keys) in params.round_keys_by_round().iter().enumerate() {
+                assert_eq!(*keys, &params.round_keys[r * width..(r + 1) * width]);
+                assert_eq!(*keys, params.round_keys_of(r));
+            }
+
+            // Changing the round keys changes the keys of the round and the permutation
+            let sbox = SboxType::Quint;
+            let input = (0..width)
+                .map(|_| FieldElement::random())
+                .collect::<Vec<_>>();
+            let mut changed = params.clone();
+            let mut round_keys = params.round_keys.clone();
+            round_keys[width + 1] += FieldElement::one();
+            changed.set_round_keys(round_keys);
+            assert_eq!(
+                changed.round_keys_of(1)[1],
+                &params.round_keys_of(1)[1] + &FieldElement::one()
+            );
+            assert_ne!(
+                Poseidon_permutation(&input, &changed, &sbox),
+                Poseidon_permutation(&input, &params, &sbox)
+            );
+            assert!(changed.validate().is_ok());
+
+            // Changing the round keys directly leaves the keys used by the permutation stale
+            let mut stale = params.clone();
+            stale.round_keys[width + 1] += FieldElement::one();
+            assert!(stale.validate().is_err());
+            assert!(Poseidon_permutation_checked(&input, &stale, &sbox).is_err());
+        }
+    }
+
+    #[test]
+    fn test_permutation_native_and_constraints_match() {
+        for &(width, partial_rounds) in [(3, 55), (5, 56), (9, 57)].iter() {
+            let params = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+            check_permutation_cross(&params, &SboxType::Cube);
+            #[cfg(feature = "inverse_sbox")]
+            check_permutation_cross(&params, &SboxType::Inverse);
+            check_permutation_cross(&params, &SboxType::Quint);
+        }
+    }
+
+    fn check_permutation_two_sboxes(
+        params: &PoseidonParams,
+        sbox_type_1: &SboxType,
+        sbox_type_2: &SboxType,
+        input: &[FieldElement],
+        output_1: &[FieldElement],
+        output_2: &[FieldElement],
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"PermutationTwoSboxes");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let mut comms = vec![];
+            let mut vars = vec![];
+            for i in input {
+                let (c, v) = prover.commit(i.clone(), FieldElement::random());
+                comms.push(c);
+                vars.push(v.into());
+            }
+            let (out_1, out_2) = Poseidon_permutation_two_sboxes_constraints(
+                &mut prover,
+                vars,
+                params,
+                sbox_type_1,
+                sbox_type_2,
+            )
+            .unwrap();
+            for (lc, o) in out_1.into_iter().zip(output_1) {
+                constrain_lc_with_scalar(&mut prover, lc, o);
+            }
+            for (lc, o) in out_2.into_iter().zip(output_2) {
+                constrain_lc_with_scalar(&mut prover, lc, o);
+            }
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"PermutationTwoSboxes");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let vars = commitments
+            .into_iter()
+            .map(|c| verifier.commit(c).into())
+            .collect();
+        let (out_1, out_2) = Poseidon_permutation_two_sboxes_constraints(
+            &mut verifier,
+            vars,
+            params,
+            sbox_type_1,
+            sbox_type_2,
+        )
+        .unwrap();
+        for (lc, o) in out_1.into_iter().zip(output_1) {
+            constrain_lc_with_scalar(&mut verifier, lc, o);
+        }
+        for (lc, o) in out_2.into_iter().zip(output_2) {
+            constrain_lc_with_scalar(&mut verifier, lc, o);
+        }
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_permutation_two_sboxes() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let input = (0..width)
+            .map(|_| FieldElement::random())
+            .collect::<Vec<_>>();
+
+        let (sbox_1, sbox_2) = (SboxType::Cube, SboxType::Quint);
+        let output_1 = Poseidon_permutation(&input, &params, &sbox_1);
+        let output_2 = Poseidon_permutation(&input, &params, &sbox_2);
+        assert_ne!(output_1, output_2);
+
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let mut prover_transcript = Transcript::new(b"PermutationTwoSboxes");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        let vars = input
+            .iter()
+            .map(|i| prover.commit(i.clone(), FieldElement::random()).1.into())
+            .collect();
+        let (out_1, out_2) = Poseidon_permutation_two_sboxes_constraints(
+            &mut prover,
+            vars,
+            &params,
+            &sbox_1,
+            &sbox_2,
+        )
+        .unwrap();
+        for i in 0..width {
+            assert_eq!(prover.evaluate_lc(&out_1[i]).unwrap(), output_1[i]);
+            assert_eq!(prover.evaluate_lc(&out_2[i]).unwrap(), output_2[i]);
+        }
+
+        assert!(check_permutation_two_sboxes(
+            &params, &sbox_1, &sbox_2, &input, &output_1, &output_2
+        ));
+        // Outputs swapped
+        assert!(!check_permutation_two_sboxes(
+            &params, &sbox_1, &sbox_2, &input, &output_2, &output_1
+        ));
+    }
+
+    #[test]
+    fn test_partial_sbox_positions() {
+        let sbox = SboxType::Quint;
+        for &(width, partial_rounds) in [(3, 55), (5, 56)].iter() {
+            let default = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+            assert_eq!(default.partial_sbox_positions, vec![width - 1]);
+            assert!(default.has_default_partial_sbox_positions());
+
+            // Sbox on 2 positions in partial rounds
+            let mut params = default.clone();
+            params.partial_sbox_positions = vec![0, width - 1];
+            assert!(!params.has_default_partial_sbox_positions());
+            assert!(params.validate().is_ok());
+            check_permutation_cross(&params, &sbox);
+
+            let input = (0..width)
+                .map(|_| FieldElement::random())
+                .collect::<Vec<_>>();
+            let output = Poseidon_permutation(&input, &params, &sbox);
+            assert_eq!(
+                output,
+                Poseidon_permutation_owned(input.clone(), &params, &sbox)
+            );
+            assert_ne!(output, Poseidon_permutation(&input, &default, &sbox));
+
+            // One more Sbox per partial round
+            assert_eq!(
+                Poseidon_permutation_gate_count(&params, &sbox),
+                Poseidon_permutation_gate_count(&default, &sbox)
+                    + partial_rounds * sbox.gate_count()
+            );
+            let g = G1::from_msg_hash("g".as_bytes());
+            let h = G1::from_msg_hash("h".as_bytes());
+            let mut prover_transcript = Transcript::new(b"PartialSboxPositions");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let input_vars = input
+                .iter()
+                .map(|i| prover.commit(i.clone(), FieldElement::random()).1.into())
+                .collect();
+            Poseidon_permutation_constraints(&mut prover, input_vars, &params, &sbox).unwrap();
+            assert_eq!(
+                prover.num_multipliers(),
+                Poseidon_permutation_gate_count(&params, &sbox)
+            );
+
+            // Positions survive serialization and change the fingerprint
+            let bytes = params.to_bytes();
+            assert_eq!(
+                bytes[0],
+                PoseidonParams::SERIALIZATION_VERSION_PARTIAL_SBOX_POSITIONS
+            );
+            let restored = PoseidonParams::from_bytes(&bytes).unwrap();
+            assert_eq!(
+                restored.partial_sbox_positions,
+                params.partial_sbox_positions
+            );
+            assert_eq!(restored.to_bytes(), bytes);
+            assert!(PoseidonParams::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+            assert_eq!(
+                PoseidonParams::from_bytes(&default.to_bytes())
+                    .unwrap()
+                    .partial_sbox_positions,
+                vec![width - 1]
+            );
+            #[cfg(any(feature = "bls381", feature = "bn254", feature = "ed25519"))]
+            assert_ne!(params.fingerprint(), default.fingerprint());
+
+            // Invalid positions
+            for positions in vec![vec![], vec![width], vec![1, 1]] {
+                let mut invalid = default.clone();
+                invalid.partial_sbox_positions = positions;
+                assert!(invalid.validate().is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_1_width() {
+        let sbox_type = &SboxType::Quint;
+        let params_5 = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let expected = BulletproofErrorKind::UnacceptableWidthForPoseidon {
+            width: 5,
+            acceptable: vec![3],
+        };
+        match Poseidon_hash_1(FieldElement::random(), &params_5, sbox_type)
+            .unwrap_err()
+            .kind()
+        {
+            BulletproofErrorKind::UnacceptableWidthForPoseidon { width, acceptable } => {
+                assert_eq!(width, 5);
+                assert_eq!(acceptable, vec![3]);
+            }
+            _ => panic!("Unexpected error"),
+        }
+
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let mut prover_transcript = Transcript::new(b"PoseidonHash1");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        let (_, var) = prover.commit(FieldElement::random(), FieldElement::random());
+        match Poseidon_hash_1_constraints(
+            &mut prover,
+            var.into(),
+            FieldElement::from(CAP_CONST_1_W_3).into(),
+            &params_5,
+            sbox_type,
+        )
+        .unwrap_err()
+        .kind()
+        {
+            R1CSErrorKind::GadgetError { description } => {
+                assert_eq!(description, expected.to_string())
+            }
+            _ => panic!("Unexpected error"),
+        }
+        assert_eq!(prover.num_multipliers(), 0);
+    }
+
+    #[test]
+    fn test_poseidon_hash_n() {
+        let sbox_type = &SboxType::Quint;
+        let params_3 = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let params_5 = PoseidonParams::new(5, 4, 4, 56).unwrap();
+
+        // Same as the fixed input hashes when the inputs fill the rate
+        let inputs = (0..4).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        assert_eq!(
+            Poseidon_hash_n(inputs[..2].to_vec(), &params_3, sbox_type).unwrap(),
+            Poseidon_hash_2(inputs[..2].to_vec(), &params_3, sbox_type).unwrap()
+        );
+        assert_eq!(
+            Poseidon_hash_n(inputs.clone(), &params_5, sbox_type).unwrap(),
+            Poseidon_hash_4(inputs.clone(), &params_5, sbox_type).unwrap()
+        );
+        assert_eq!(
+            Poseidon_hash_n(inputs[..1].to_vec(), &params_3, sbox_type).unwrap(),
+            Poseidon_hash_1(inputs[0].clone(), &params_3, sbox_type).unwrap()
+        );
+
+        // Padding with 0 gives a different hash than hashing the 0
+        let mut padded = inputs[..3].to_vec();
+        padded.push(FieldElement::zero());
+        assert_ne!(
+            Poseidon_hash_n(inputs[..3].to_vec(), &params_5, sbox_type).unwrap(),
+            Poseidon_hash_n(padded, &params_5, sbox_type).unwrap()
+        );
+
+        assert!(Poseidon_hash_n(vec![], &params_5, sbox_type).is_err());
+        assert!(Poseidon_hash_n(inputs[..3].to_vec(), &params_3, sbox_type).is_err());
+
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let mut prover_transcript = Transcript::new(b"PoseidonHashN");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        let vars = inputs[..3]
+            .iter()
+            .map(|i| prover.commit(i.clone(), FieldElement::random()).1.into())
+            .collect::<Vec<_>>();
+        let hash = Poseidon_hash_n_constraints(&mut prover, vars, &params_5, sbox_type).unwrap();
+        assert_eq!(
+            prover.evaluate_lc(&hash).unwrap(),
+            Poseidon_hash_n(inputs[..3].to_vec(), &params_5, sbox_type).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_poseidon_hash_generic() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let sbox = SboxType::Quint;
+
+        for n in 1..8u64 {
+            let ints = (1..=n).collect::<Vec<u64>>();
+            let elems = ints
+                .iter()
+                .map(|i| FieldElement::from(*i))
+                .collect::<Vec<_>>();
+            let digest = poseidon_hash(ints.clone(), &params, &sbox).unwrap();
+            assert_eq!(
+                digest,
+                poseidon_hash(elems.clone(), &params, &sbox).unwrap()
+            );
+            assert_eq!(
+                digest,
+                poseidon_hash(ints.iter().cloned(), &params, &sbox).unwrap()
+            );
+            if n < 5 {
+                assert_eq!(digest, Poseidon_hash_n(elems, &params, &sbox).unwrap());
+            } else {
+                let mut sponge = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+                sponge.absorb(&elems);
+                assert_eq!(digest, sponge.squeeze(1)[0]);
+            }
+        }
+        assert_eq!(
+            poseidon_hash(vec![1u64, 2, 3, 4], &params, &sbox).unwrap(),
+            Poseidon_hash_4((1..=4u64).map(FieldElement::from).collect(), &params, &sbox).unwrap()
+        );
+        assert!(poseidon_hash(Vec::<u64>::new(), &params, &sbox).is_err());
+
+        // Extending with 0s changes the hash, with and without the sponge
+        let mut inputs = vec![FieldElement::random()];
+        let mut digests = vec![];
+        for _ in 0..10 {
+            digests.push(poseidon_hash(inputs.clone(), &params, &sbox).unwrap());
+            inputs.push(FieldElement::zero());
+        }
+        for i in 0..digests.len() {
+            for j in i + 1..digests.len() {
+                assert_ne!(digests[i], digests[j]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_capacity_consts() {
+        assert_eq!(
+            (CAP_CONST_1_W_3, CAP_CONST_W_3, CAP_CONST_W_5, CAP_CONST_W_9),
+            (1, 3, 31, 511)
+        );
+        assert!(validate_capacity_const(3, CAP_CONST_1_W_3));
+        assert!(validate_capacity_const(3, CAP_CONST_W_3));
+        assert!(validate_capacity_const(5, CAP_CONST_W_5));
+        assert!(validate_capacity_const(9, CAP_CONST_W_9));
+        for &width in [3, 5, 9].iter() {
+            for n in 1..width {
+                assert!(validate_capacity_const(
+                    width,
+                    capacity_const_for_n(n, width)
+                ));
+            }
+        }
+
+        assert!(!validate_capacity_const(3, 0));
+        assert!(!validate_capacity_const(5, 30));
+        assert!(!validate_capacity_const(9, 512));
+        assert!(!validate_capacity_const(5, 63));
+    }
+
+    #[test]
+    fn test_domain_tag() {
+        let labels = [
+            DOMAIN_LABEL_COMMITMENT,
+            DOMAIN_LABEL_PRF,
+            DOMAIN_LABEL_MAC,
+            DOMAIN_LABEL_MERKLE_KV_LEAF,
+            DOMAIN_LABEL_DUPLEX_NONCE,
+            DOMAIN_LABEL_MERKLE_STRUCTURE,
+            DOMAIN_LABEL_DERIVE_CHILD,
+        ];
+        for (i, l1) in labels.iter().enumerate() {
+            // Stable
+            assert_eq!(domain_tag(l1), domain_tag(l1));
+            for l2 in labels.iter().skip(i + 1) {
+                assert_ne!(domain_tag(l1), domain_tag(l2));
+            }
+            for c in &[CAP_CONST_W_3, CAP_CONST_1_W_3, CAP_CONST_W_5, CAP_CONST_W_9] {
+                assert_ne!(domain_tag(l1), FieldElement::from(*c));
+            }
+        }
+    }
+
+    #[test]
+    fn test_reserved_domain_tags() {
+        let reserved = reserved_domain_tags();
+        for c in &[CAP_CONST_W_3, CAP_CONST_1_W_3, CAP_CONST_W_5, CAP_CONST_W_9] {
+            assert_eq!(reserved.iter().filter(|(t, _)| t == c).count(), 1);
+        }
+        for (i, (t1, n1)) in reserved.iter().enumerate() {
+            for (t2, n2) in reserved.iter().skip(i + 1) {
+                assert_ne!(t1, t2);
+                assert_ne!(n1, n2);
+            }
+            assert!(*t1 <= MAX_RESERVED_DOMAIN_TAG);
+            assert!(check_domain_tag_available(*t1).is_err());
+        }
+
+        // Sponge IVs are in the reserved range
+        for capacity in 1..9 {
+            assert!(check_domain_tag_available(capacity - 1).is_err());
+        }
+        assert!(check_domain_tag_available(MAX_RESERVED_DOMAIN_TAG + 1).is_ok());
+        assert!(check_domain_tag_available(std::u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn test_mds_determinant() {
+        for (width, partial_rounds) in vec![(3, 55), (5, 56), (9, 57)] {
+            let params = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+            assert!(!params.mds_determinant().is_zero());
+        }
+
+        // Matrix with known determinant -(2*3 - 1*4) = -2, needs a row swap during elimination
+        let mut params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        params.MDS_matrix = vec![
+            vec![
+                FieldElement::zero(),
+                FieldElement::from(2u64),
+                FieldElement::one(),
+            ],
+            vec![
+                FieldElement::one(),
+                FieldElement::zero(),
+                FieldElement::zero(),
+            ],
+            vec![
+                FieldElement::zero(),
+                FieldElement::from(4u64),
+                FieldElement::from(3u64),
+            ],
+        ];
+        assert_eq!(
+            params.mds_determinant(),
+            FieldElement::from(2u64).negation()
+        );
+
+        // Singular when a row is a multiple of another
+        let mut params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        params.MDS_matrix[2] = params.MDS_matrix[0]
+            .iter()
+            .map(|e| e * &FieldElement::from(7u64))
+            .collect();
+        assert!(params.mds_determinant().is_zero());
+    }
+
+    #[test]
+    fn test_hash_gate_count() {
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let hashes: Vec<(usize, (usize, usize, usize), fn(&SboxType) -> usize)> = vec![
+            (3, HASH_2_ROUNDS, Poseidon_hash_2_gate_count),
+            (5, HASH_4_ROUNDS, Poseidon_hash_4_gate_count),
+            (9, HASH_8_ROUNDS, Poseidon_hash_8_gate_count),
+        ];
+        for (width, (full_b, full_e, partial), gate_count) in hashes {
+            let params = PoseidonParams::new(width, full_b, full_e, partial).unwrap();
+            for sbox_type in &all_sboxes() {
+                let mut prover_transcript = Transcript::new(b"PoseidonGateCount");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let inputs = (0..width - 1)
+                    .map(|_| {
+                        let (_, var) =
+                            prover.commit(FieldElement::random(), FieldElement::random());
+                        var.into()
+                    })
+                    .collect::<Vec<LinearCombination>>();
+                let cap = FieldElement::from(capacity_const_for_n(width - 1, width)).into();
+                match width {
+                    3 => Poseidon_hash_2_constraints(&mut prover, inputs, cap, &params, sbox_type),
+                    5 => Poseidon_hash_4_constraints(&mut prover, inputs, cap, &params, sbox_type),
+                    _ => Poseidon_hash_8_constraints(&mut prover, inputs, cap, &params, sbox_type),
+                }
+                .unwrap();
+                assert_eq!(prover.num_multipliers(), gate_count(sbox_type));
+                assert_eq!(
+                    prover.num_multipliers(),
+                    Poseidon_permutation_gate_count(&params, sbox_type)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_gate_count_regression() {
+        // Fixed maximums, not derived from the estimator, so that a change adding gates to the
+        // hashes fails here. With the Cube sbox, each sbox needs 2 multiplication gates, full
+        // rounds have `width` sboxes and partial rounds 1, so
+        // Poseidon_hash_2: width 3, rounds (4, 4, 55) -> (8*3 + 55) * 2 = 158
+        // Poseidon_hash_4: width 5, rounds (4, 4, 56) -> (8*5 + 56) * 2 = 192
+        // Poseidon_hash_8: width 9, rounds (4, 4, 57) -> (8*9 + 57) * 2 = 258
+        const MAX_HASH_2_GATES: usize = 158;
+        const MAX_HASH_4_GATES: usize = 192;
+        const MAX_HASH_8_GATES: usize = 258;
+
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let sbox_type = &SboxType::Cube;
+
+        for &(width, partial_rounds, max_gates) in [
+            (3, 55, MAX_HASH_2_GATES),
+            (5, 56, MAX_HASH_4_GATES),
+            (9, 57, MAX_HASH_8_GATES),
+        ]
+        .iter()
+        {
+            let params = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+            let mut prover_transcript = Transcript::new(b"PoseidonGateRegression");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let inputs = (0..width - 1)
+                .map(|_| {
+                    let (_, var) = prover.commit(FieldElement::random(), FieldElement::random());
+                    var.into()
+                })
+                .collect::<Vec<LinearCombination>>();
+            let cap = FieldElement::from(capacity_const_for_n(width - 1, width)).into();
+            match width {
+                3 => Poseidon_hash_2_constraints(&mut prover, inputs, cap, &params, sbox_type),
+                5 => Poseidon_hash_4_constraints(&mut prover, inputs, cap, &params, sbox_type),
+                _ => Poseidon_hash_8_constraints(&mut prover, inputs, cap, &params, sbox_type),
+            }
+            .unwrap();
+            assert!(
+                prover.num_multipliers() <= max_gates,
+                "Hash of width {} uses {} multiplication gates, more than the maximum {}",
+                width,
+                prover.num_multipliers(),
+                max_gates
+            );
+        }
+    }
+
+    #[test]
+    fn test_sbox_constraint_cost() {
+        // Prints the cost of the permutation for each sbox with the same width and the round
+        // schedule each sbox needs for the same security, as given by `min_secure_rounds`. The
+        // constant tables do not have enough round keys for all schedules but the cost does not
+        // depend on the round keys so random ones are used.
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let width = 5;
+
+        for sbox_type in &all_sboxes() {
+            let (full_rounds, partial_rounds) =
+                PoseidonParams::min_secure_rounds(width, 8, sbox_type);
+            let mut params = PoseidonParams::random_for_testing(width, 0);
+            params.full_rounds_beginning = full_rounds / 2;
+            params.full_rounds_end = full_rounds - full_rounds / 2;
+            params.partial_rounds = partial_rounds;
+            params.set_round_keys(
+                (0..(full_rounds + partial_rounds) * width)
+                    .map(|_| FieldElement::random())
+                    .collect(),
+            );
+            let num_sboxes = full_rounds * width + partial_rounds;
+
+            let mut prover_transcript = Transcript::new(b"SboxCost");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let inputs = (0..width)
+                .map(|_| {
+                    let (_, var) = prover.commit(FieldElement::random(), FieldElement::random());
+                    var.into()
+                })
+                .collect::<Vec<LinearCombination>>();
+            Poseidon_permutation_constraints(&mut prover, inputs, &params, sbox_type).unwrap();
+
+            let multipliers = prover.num_multipliers();
+            let variables = prover.num_variables();
+            assert_eq!(multipliers, num_sboxes * sbox_type.gate_count());
+            assert_eq!(
+                multipliers,
+                Poseidon_permutation_gate_count(&params, sbox_type)
+            );
+            // The committed inputs besides the variables of the multipliers
+            assert_eq!(variables, 3 * multipliers + width);
+            println!(
+                "Sbox {:?} with {} full and {} partial rounds: {} gates per sbox, {} sboxes, {} multiplication gates, {} variables and {} constraints",
+                sbox_type,
+                full_rounds,
+                partial_rounds,
+                sbox_type.gate_count(),
+                num_sboxes,
+                multipliers,
+                variables,
+                prover.num_constraints()
+            );
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_to_point() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let inputs_1 = vec![FieldElement::random(), FieldElement::random()];
+        let inputs_2 = vec![FieldElement::random(), FieldElement::random()];
+        let p1 = poseidon_hash_to_point(&inputs_1, &params, sbox_type);
+        let p2 = poseidon_hash_to_point(&inputs_2, &params, sbox_type);
+
+        // Deserializing checks that the point is on the curve
+        for p in vec![&p1, &p2] {
+            assert!(!p.is_identity());
+            assert_eq!(&G1::from_bytes(&p.to_bytes()).unwrap(), p);
+        }
+        assert_ne!(p1, p2);
+        assert_eq!(p1, poseidon_hash_to_point(&inputs_1, &params, sbox_type));
+    }
+
+    #[cfg(feature = "poseidon_instrumentation")]
+    #[test]
+    fn test_permutation_instrumented() {
+        let width = 3;
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let input = (0..width)
+            .map(|_| FieldElement::random())
+            .collect::<Vec<_>>();
+
+        let (output, counts) = Poseidon_permutation_instrumented(&input, &params, &SboxType::Cube);
+        assert_eq!(
+            output,
+            Poseidon_permutation(&input, &params, &SboxType::Cube)
+        );
+        // Cube Sbox is a squaring and a multiplication, the linear layer is width^2
+        // multiplications each round
+        let sboxes = (full_b + full_e) * width + partial_rounds;
+        let rounds = full_b + full_e + partial_rounds;
+        assert_eq!(
+            counts,
+            FieldOpCounts {
+                multiplications: 2 * sboxes + rounds * width * width,
+                inversions: 0
+            }
+        );
+        assert_eq!(counts.multiplications, 725);
+
+        let (_, counts) = Poseidon_permutation_instrumented(&input, &params, &SboxType::Quint);
+        assert_eq!(counts.multiplications, 3 * sboxes + rounds * width * width);
+        assert_eq!(counts.inversions, 0);
+
+        #[cfg(feature = "inverse_sbox")]
+        {
+            let (_, counts) =
+                Poseidon_permutation_instrumented(&input, &params, &SboxType::Inverse);
+            assert_eq!(counts.multiplications, rounds * width * width);
+            assert_eq!(counts.inversions, sboxes);
+        }
+    }
+
+    // Field of integers modulo the Mersenne prime 2^61 - 1, only for testing `PoseidonField`
+    #[derive(Clone, Debug, PartialEq)]
+    struct MockField(u128);
+
+    const MOCK_MODULUS: u128 = (1 << 61) - 1;
+
+    impl PoseidonField for MockField {
+        fn zero() -> Self {
+            MockField(0)
+        }
+
+        fn one() -> Self {
+            MockField(1)
+        }
+
+        fn add(&self, b: &Self) -> Self {
+            MockField((self.0 + b.0) % MOCK_MODULUS)
+        }
+
+        fn mul(&self, b: &Self) -> Self {
+            MockField((self.0 * b.0) % MOCK_MODULUS)
+        }
+
+        fn square(&self) -> Self {
+            self.mul(self)
+        }
+
+        fn inverse(&self) -> Self {
+            // Fermat's little theorem, 0 maps to 0
+            let mut result = Self::one();
+            let mut base = self.clone();
+            let mut e = MOCK_MODULUS - 2;
+            while e > 0 {
+                if e & 1 == 1 {
+                    result = result.mul(&base);
+                }
+                base = base.square();
+                e >>= 1;
+            }
+            result
+        }
+
+        fn is_zero(&self) -> bool {
+            self.0 == 0
+        }
+
+        fn from_hex(hex_str: &str) -> Result<Self, BulletproofError> {
+            let err = |msg: String| {
+                BulletproofError::from(BulletproofErrorKind::ParseErrorForPoseidonConstant {
+                    constant: hex_str.to_string(),
+                    error_msg: msg,
+                })
+            };
+            let mut v = 0u128;
+            for c in hex_str.chars() {
+                let d = c
+                    .to_digit(16)
+                    .ok_or_else(|| err(format!("Invalid hex digit {}", c)))?;
+                // `v` stays below the modulus so this does not overflow
+                v = v * 16 + d as u128;
+                if v >= MOCK_MODULUS {
+                    return Err(err(String::from("Constant is not less than the modulus")));
+                }
+            }
+            Ok(MockField(v))
+        }
+    }
+
+    // The table constants are bigger than the modulus of `MockField` so its params are built
+    // here. Round keys are an arbitrary sequence and the MDS matrix is the Cauchy matrix
+    // `1 / (i + width + j)`.
+    fn mock_params(
+        width: usize,
+        full_b: usize,
+        full_e: usize,
+        partial_rounds: usize,
+    ) -> PoseidonParams<MockField> {
+        let total_rounds = full_b + full_e + partial_rounds;
+        let round_keys = (0..total_rounds * width)
+            .map(|i| MockField((i as u128 * 0x9e3779b97f4a7c15 + 1) % MOCK_MODULUS))
+            .collect::<Vec<_>>();
+        PoseidonParams {
+            width,
+            full_rounds_beginning: full_b,
+            full_rounds_end: full_e,
+            partial_rounds,
+            round_keys_by_round: split_round_keys(&round_keys, width),
+            round_keys,
+            MDS_matrix: (0..width)
+                .map(|i| {
+                    (0..width)
+                        .map(|j| MockField((i + width + j) as u128).inverse())
+                        .collect()
+                })
+                .collect(),
+            partial_sbox_positions: vec![width - 1],
+        }
+    }
+
+    #[test]
+    fn test_permutation_mock_field() {
+        let width = 3;
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+        let params = mock_params(width, full_b, full_e, partial_rounds);
+        assert_eq!(
+            MockField::from_hex("1f").unwrap().mul(&MockField(3)),
+            MockField(93)
+        );
+        assert_eq!(
+            MockField::from_hex("1ffffffffffffffe").unwrap(),
+            MockField(MOCK_MODULUS - 1)
+        );
+        // Values not less than the modulus are rejected rather than reduced
+        assert!(MockField::from_hex("1fffffffffffffff").is_err());
+        assert!(MockField::from_hex("2000000000000000").is_err());
+        assert!(MockField::from_hex("1g").is_err());
+        assert!(
+            PoseidonParams::<MockField>::new_for_field(width, full_b, full_e, partial_rounds)
+                .is_err()
+        );
+        assert_eq!(
+            MockField(12345).mul(&MockField(12345).inverse()),
+            MockField(1)
+        );
+
+        let input = vec![MockField(1), MockField(2), MockField(3)];
+        for sbox in all_sboxes() {
+            let output = Poseidon_permutation(&input, &params, &sbox);
+            assert_eq!(output.len(), width);
+            assert_eq!(output, Poseidon_permutation(&input, &params, &sbox));
+            assert_eq!(
+                output,
+                Poseidon_permutation_owned(input.clone(), &params, &sbox)
+            );
+            let other = vec![MockField(1), MockField(2), MockField(4)];
+            assert_ne!(output, Poseidon_permutation(&other, &params, &sbox));
+        }
+
+        // A single full round computed by hand
+        let one_round = mock_params(width, 1, 0, 0);
+        let sbox = SboxType::Quint;
+        let after_sbox = (0..width)
+            .map(|i| sbox.apply_sbox(&input[i].add(&one_round.round_keys[i])))
+            .collect::<Vec<_>>();
+        let expected = (0..width)
+            .map(|i| {
+                (0..width).fold(MockField::zero(), |acc, j| {
+                    acc.add(&after_sbox[j].mul(&one_round.MDS_matrix[j][i]))
+                })
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(Poseidon_permutation(&input, &one_round, &sbox), expected);
+    }
+
+    #[test]
+    fn test_permutation_checked() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let sbox = SboxType::Quint;
+        let input = (0..5).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        assert_eq!(
+            Poseidon_permutation_checked(&input, &params, &sbox).unwrap(),
+            Poseidon_permutation(&input, &params, &sbox)
+        );
+        assert!(Poseidon_permutation_checked(&input[..4], &params, &sbox).is_err());
+
+        // Rounds changed without changing the round keys, fewer keys are consumed than present
+        let mut fewer_rounds = params.clone();
+        fewer_rounds.partial_rounds -= 1;
+        assert!(Poseidon_permutation_checked(&input, &fewer_rounds, &sbox).is_err());
+
+        // More rounds than round keys, the unchecked permutation would panic
+        let mut more_rounds = params.clone();
+        more_rounds.full_rounds_end += 1;
+        assert!(Poseidon_permutation_checked(&input, &more_rounds, &sbox).is_err());
+
+        // Flat round keys not matching the schedule
+        let mut extra_keys = params.clone();
+        extra_keys.round_keys.push(FieldElement::random());
+        assert!(Poseidon_permutation_checked(&input, &extra_keys, &sbox).is_err());
+
+        // MDS matrix not of width x width, the unchecked permutation would panic
+        let mut short_mds = params.clone();
+        short_mds.MDS_matrix.pop();
+        assert!(Poseidon_permutation_checked(&input, &short_mds, &sbox).is_err());
+        let mut short_row = params.clone();
+        short_row.MDS_matrix[2].pop();
+        assert!(Poseidon_permutation_checked(&input, &short_row, &sbox).is_err());
+
+        // Partial Sbox positions out of the state, repeated or missing
+        for positions in vec![vec![5], vec![1, 1], vec![]] {
+            let mut bad_positions = params.clone();
+            bad_positions.partial_sbox_positions = positions;
+            assert!(Poseidon_permutation_checked(&input, &bad_positions, &sbox).is_err());
+        }
+
+        let mut other_positions = params.clone();
+        other_positions.partial_sbox_positions = vec![0, 3];
+        assert_eq!(
+            Poseidon_permutation_checked(&input, &other_positions, &sbox).unwrap(),
+            Poseidon_permutation(&input, &other_positions, &sbox)
+        );
+    }
+
+    #[test]
+    fn test_poseidon_hash_points() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let sbox = SboxType::Quint;
+        let p1 = G1::from_msg_hash("p1".as_bytes());
+        let p2 = G1::from_msg_hash("p2".as_bytes());
+
+        let digest = poseidon_hash_points(&[p1.clone(), p2.clone()], &params, &sbox).unwrap();
+        assert_eq!(
+            poseidon_hash_points(&[p1.clone(), p2.clone()], &params, &sbox).unwrap(),
+            digest
+        );
+        let mut digests = vec![
+            digest,
+            poseidon_hash_points(&[p2.clone(), p1.clone()], &params, &sbox).unwrap(),
+            poseidon_hash_points(&[p1.clone()], &params, &sbox).unwrap(),
+            poseidon_hash_points(&[p2.clone()], &params, &sbox).unwrap(),
+            poseidon_hash_points(&[p1.clone(), p1.clone()], &params, &sbox).unwrap(),
+            poseidon_hash_points(&[p1.clone(), G1::identity()], &params, &sbox).unwrap(),
+            poseidon_hash_points(&[G1::identity()], &params, &sbox).unwrap(),
+            poseidon_hash_points::<G1>(&[], &params, &sbox).unwrap(),
+        ];
+        // Hashing points differs from hashing a field element
+        digests.push(poseidon_hash(vec![FieldElement::one()], &params, &sbox).unwrap());
+        for i in 0..digests.len() {
+            for j in i + 1..digests.len() {
+                assert_ne!(digests[i], digests[j]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_iter() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox = SboxType::Quint;
+        let a = FieldElement::random();
+        let b = FieldElement::random();
+        assert_eq!(
+            Poseidon_hash_iter(vec![a.clone(), b.clone()], &params, &sbox).unwrap(),
+            Poseidon_hash_2(vec![a.clone(), b.clone()], &params, &sbox).unwrap()
+        );
+
+        // Lazily generated inputs, more than the rate
+        let gen = (1..=7u64).map(|i| FieldElement::from(i * i));
+        let inputs = gen.clone().collect::<Vec<_>>();
+        let mut sponge = PoseidonSponge::new(&params, &sbox, 1).unwrap();
+        sponge.absorb(&inputs);
+        assert_eq!(
+            Poseidon_hash_iter(gen.clone(), &params, &sbox).unwrap(),
+            sponge.squeeze(1).remove(0)
+        );
+        assert_eq!(
+            Poseidon_hash_iter(gen, &params, &sbox).unwrap(),
+            poseidon_hash(inputs, &params, &sbox).unwrap()
+        );
+        assert_eq!(
+            Poseidon_hash_iter(std::iter::once(a.clone()), &params, &sbox).unwrap(),
+            Poseidon_hash_n(vec![a], &params, &sbox).unwrap()
+        );
+        assert!(Poseidon_hash_iter(std::iter::empty(), &params, &sbox).is_err());
+    }
+
+    #[test]
+    fn test_hash_n_multi() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let sbox = SboxType::Quint;
+        let inputs = (0..3).map(|_| FieldElement::random()).collect::<Vec<_>>();
+
+        assert_eq!(
+            Poseidon_hash_n_multi(inputs.clone(), &params, &sbox, 1, 1).unwrap(),
+            vec![Poseidon_hash_n(inputs.clone(), &params, &sbox).unwrap()]
+        );
+        assert!(Poseidon_hash_n_multi(inputs.clone(), &params, &sbox, 0, 1).is_err());
+        assert!(Poseidon_hash_n_multi(inputs.clone(), &params, &sbox, 5, 1).is_err());
+        // 3 inputs do not fit in a rate of 2
+        assert!(Poseidon_hash_n_multi(inputs.clone(), &params, &sbox, 3, 1).is_err());
+
+        // Capacity 2 so rate is 3, ask for rate + 1 outputs
+        let capacity = 2;
+        let rate = 3;
+        let outputs =
+            Poseidon_hash_n_multi(inputs.clone(), &params, &sbox, capacity, rate + 1).unwrap();
+        assert_eq!(outputs.len(), rate + 1);
+
+        let mut initial = vec![
+            FieldElement::from(capacity_const_for_n(3, 5)),
+            FieldElement::zero(),
+        ];
+        initial.extend_from_slice(&inputs);
+        let state_1 = Poseidon_permutation(&initial, &params, &sbox);
+        assert_eq!(&outputs[..rate], &state_1[capacity..]);
+        // Last output needs a second permutation and is not a capacity element of the first
+        let state_2 = Poseidon_permutation(&state_1, &params, &sbox);
+        assert_eq!(outputs[rate], state_2[capacity]);
+        assert!(!state_1[..capacity].contains(&outputs[rate]));
+
+        // Same as continuing to squeeze a sponge in the same state with the rate exhausted
+        let mut bytes = vec![];
+        for e in &initial {
+            bytes.extend_from_slice(&e.to_bytes());
+        }
+        bytes.extend_from_slice(&(rate as u64).to_be_bytes());
+        bytes.push(1);
+        let mut sponge = PoseidonSponge::restore(&bytes, &params, &sbox, capacity).unwrap();
+        assert_eq!(sponge.squeeze(rate + 1), outputs);
+    }
+
+    #[test]
+    fn test_hash_2_with_state() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox = SboxType::Quint;
+        let inputs = vec![FieldElement::random(), FieldElement::random()];
+
+        let (hash, state) = Poseidon_hash_2_with_state(inputs.clone(), &params, &sbox).unwrap();
+        assert_eq!(state.len(), 3);
+        assert_eq!(state[1], hash);
+        assert_eq!(
+            hash,
+            Poseidon_hash_2(inputs.clone(), &params, &sbox).unwrap()
+        );
+        let mut input = vec![FieldElement::from(CAP_CONST_W_3)];
+        input.extend_from_slice(&inputs);
+        assert_eq!(state, Poseidon_permutation(&input, &params, &sbox));
+
+        assert!(Poseidon_hash_2_with_state(inputs[..1].to_vec(), &params, &sbox).is_err());
+    }
+
+    #[test]
+    fn test_hash_u64s() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox = SboxType::Quint;
+
+        let expected = Poseidon_hash_2(
+            vec![
+                FieldElement::from(7u64),
+                FieldElement::from(u64::max_value()),
+            ],
+            &params,
+            &sbox,
+        )
+        .unwrap();
+        assert_eq!(
+            Poseidon_hash_u64s(&[7, u64::max_value()], &params, &sbox).unwrap(),
+            expected
+        );
+        assert_ne!(
+            Poseidon_hash_u64s(&[u64::max_value(), 7], &params, &sbox).unwrap(),
+            expected
+        );
+
+        // More values than the rate go through the sponge
+        let values = [1u64, 2, 3, 4, 5];
+        assert_eq!(
+            Poseidon_hash_u64s(&values, &params, &sbox).unwrap(),
+            poseidon_hash(values.iter().cloned(), &params, &sbox).unwrap()
+        );
+        assert!(Poseidon_hash_u64s(&[], &params, &sbox).is_err());
+    }
+
+    #[test]
+    fn test_hash_matrix() {
+        let sbox = SboxType::Quint;
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let matrix = (0..3)
+            .map(|_| (0..3).map(|_| FieldElement::random()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        // Width 5 hashes rows with `Poseidon_hash_n`, width 3 with the sponge
+        for (width, partial_rounds) in vec![(5, 56), (3, 55)] {
+            let params = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+            let expected = Poseidon_hash_matrix(&matrix, &params, &sbox).unwrap();
+            let mut inputs = vec![FieldElement::from(3u64), FieldElement::from(3u64)];
+            inputs.extend(
+                matrix
+                    .iter()
+                    .map(|r| poseidon_hash(r.clone(), &params, &sbox).unwrap()),
+            );
+            assert_eq!(expected, poseidon_hash(inputs, &params, &sbox).unwrap());
+
+            let mut prover_transcript = Transcript::new(b"PoseidonHashMatrix");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let rows = matrix
+                .iter()
+                .map(|r| {
+                    r.iter()
+                        .map(|e| prover.commit(e.clone(), FieldElement::random()).1.into())
+                        .collect::<Vec<LinearCombination>>()
+                })
+                .collect::<Vec<_>>();
+            let hash = Poseidon_hash_matrix_constraints(&mut prover, rows, &params, &sbox).unwrap();
+            assert_eq!(prover.evaluate_lc(&hash).unwrap(), expected);
+
+            // Changing a single cell changes the hash
+            let mut changed = matrix.clone();
+            changed[1][2] = &changed[1][2] + &FieldElement::one();
+            assert_ne!(
+                Poseidon_hash_matrix(&changed, &params, &sbox).unwrap(),
+                expected
+            );
+        }
+
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        assert!(Poseidon_hash_matrix(&[], &params, &sbox).is_err());
+        assert!(Poseidon_hash_matrix(&[vec![]], &params, &sbox).is_err());
+        let mut ragged = matrix.clone();
+        ragged[2].pop();
+        assert!(Poseidon_hash_matrix(&ragged, &params, &sbox).is_err());
+
+        // Matrices of different shapes, including ones extended with 0s or with the same
+        // elements, hash differently
+        let params_3 = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let (a, b, c, d) = (
+            FieldElement::random(),
+            FieldElement::random(),
+            FieldElement::random(),
+            FieldElement::random(),
+        );
+        let zero = FieldElement::zero();
+        for p in vec![&params, &params_3] {
+            let shapes = vec![
+                vec![vec![a.clone(), b.clone(), c.clone()]],
+                vec![vec![a.clone(), b.clone(), c.clone(), zero.clone()]],
+                vec![vec![a.clone()], vec![b.clone()], vec![c.clone()]],
+                vec![vec![a.clone(), b.clone()], vec![c.clone(), d.clone()]],
+                vec![vec![a.clone(), b.clone(), c.clone(), d.clone()]],
+                vec![
+                    vec![a.clone()],
+                    vec![b.clone()],
+                    vec![c.clone()],
+                    vec![d.clone()],
+                ],
+            ];
+            let hashes = shapes
+                .iter()
+                .map(|m| Poseidon_hash_matrix(m, p, &sbox).unwrap())
+                .collect::<Vec<_>>();
+            for i in 0..hashes.len() {
+                for j in i + 1..hashes.len() {
+                    assert_ne!(hashes[i], hashes[j]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_derive_nonce() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox = SboxType::Quint;
+        let key = FieldElement::random();
+
+        // Deterministic
+        let nonce = derive_nonce(key.clone(), 0, &params, &sbox).unwrap();
+        assert_eq!(derive_nonce(key.clone(), 0, &params, &sbox).unwrap(), nonce);
+
+        // Distinct for distinct counters and keys
+        let nonces = (0..10)
+            .map(|c| derive_nonce(key.clone(), c, &params, &sbox).unwrap())
+            .collect::<Vec<_>>();
+        for i in 0..nonces.len() {
+            for j in i + 1..nonces.len() {
+                assert_ne!(nonces[i], nonces[j]);
+            }
+        }
+        assert_ne!(
+            derive_nonce(FieldElement::random(), 0, &params, &sbox).unwrap(),
+            nonce
+        );
+
+        // Not the plain hash of the key and counter
+        assert_ne!(
+            Poseidon_hash_2(vec![key, FieldElement::zero()], &params, &sbox).unwrap(),
+            nonce
+        );
+
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let key = FieldElement::random();
+        assert_ne!(
+            derive_nonce(key.clone(), 1, &params, &sbox).unwrap(),
+            derive_nonce(key, 2, &params, &sbox).unwrap()
+        );
+
+        // Width 2 has no room for the counter
+        let params = PoseidonParams::random_for_testing(2, 0);
+        assert!(derive_nonce(FieldElement::random(), 1, &params, &sbox).is_err());
+    }
+
+    thread_local! {
+        // Number of non-zero `TrackedField`s dropped on the current thread
+        static NON_ZERO_DROPS: std::cell::Cell<usize> = std::cell::Cell::new(0);
+    }
+
+    // Field element that records being dropped without being zeroized
+    #[derive(Clone, Debug, PartialEq)]
+    struct TrackedField(FieldElement);
+
+    impl Drop for TrackedField {
+        fn drop(&mut self) {
+            if !self.0.is_zero() {
+                NON_ZERO_DROPS.with(|c| c.set(c.get() + 1));
+            }
+        }
+    }
+
+    impl Zeroize for TrackedField {
+        fn zeroize(&mut self) {
+            self.0.zeroize();
+        }
+    }
+
+    impl PoseidonField for TrackedField {
+        fn zero() -> Self {
+            TrackedField(FieldElement::zero())
+        }
+
+        fn one() -> Self {
+            TrackedField(FieldElement::one())
+        }
+
+        fn add(&self, b: &Self) -> Self {
+            TrackedField(PoseidonField::add(&self.0, &b.0))
+        }
+
+        fn mul(&self, b: &Self) -> Self {
+            TrackedField(PoseidonField::mul(&self.0, &b.0))
+        }
+
+        fn square(&self) -> Self {
+            TrackedField(PoseidonField::square(&self.0))
+        }
+
+        fn inverse(&self) -> Self {
+            TrackedField(PoseidonField::inverse(&self.0))
+        }
+
+        fn is_zero(&self) -> bool {
+            PoseidonField::is_zero(&self.0)
+        }
+
+        fn from_hex(hex_str: &str) -> Result<Self, BulletproofError> {
+            <FieldElement as PoseidonField>::from_hex(hex_str).map(TrackedField)
+        }
+    }
+
+    #[test]
+    fn test_permutation_secret() {
+        let width = 5;
+        let params = PoseidonParams::<TrackedField>::new_for_field(width, 4, 4, 56).unwrap();
+        let input = (0..width)
+            .map(|_| TrackedField(FieldElement::random()))
+            .collect::<Vec<_>>();
+        for sbox in all_sboxes() {
+            // The non-secret permutation drops its intermediate values as they are
+            NON_ZERO_DROPS.with(|c| c.set(0));
+            let expected = Poseidon_permutation(&input, &params, &sbox);
+            assert!(NON_ZERO_DROPS.with(|c| c.get()) > 0);
+
+            // Params, input and output outlive the call so any non-zero value dropped during
+            // it is an intermediate value that was not zeroized
+            NON_ZERO_DROPS.with(|c| c.set(0));
+            let output = Poseidon_permutation_secret(&input, &params, &sbox);
+            assert_eq!(NON_ZERO_DROPS.with(|c| c.get()), 0);
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn test_permutation_capacity() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox = SboxType::Quint;
+        let input = (0..3).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let output = Poseidon_permutation(&input, &params, &sbox);
+
+        assert_eq!(
+            Poseidon_permutation_capacity(&input, &params, &sbox, 1),
+            output[0..1].to_vec()
+        );
+        assert_eq!(
+            Poseidon_permutation_capacity(&input, &params, &sbox, 2),
+            output[0..2].to_vec()
+        );
+        assert!(Poseidon_permutation_capacity(&input, &params, &sbox, 0).is_empty());
+
+        // The sponge's capacity after squeezing is the capacity of the permutation, the last
+        // element of the rate is the padding
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let mut input = vec![FieldElement::one(), FieldElement::zero()];
+        input.extend((0..2).map(|_| FieldElement::random()));
+        input.push(FieldElement::one());
+        let mut sponge = PoseidonSponge::new(&params, &sbox, 2).unwrap();
+        sponge.absorb(&input[2..4]);
+        let squeezed = sponge.squeeze(1);
+        let output = Poseidon_permutation(&input, &params, &sbox);
+        assert_eq!(squeezed[0], output[2]);
+        assert_eq!(
+            Poseidon_permutation_capacity(&input, &params, &sbox, 2),
+            output[0..2].to_vec()
+        );
+    }
+
+    #[test]
+    fn test_permutation_fn() {
+        fn apply_twice<P: Fn(&[FieldElement]) -> Vec<FieldElement>>(
+            perm: P,
+            input: &[FieldElement],
+        ) -> Vec<FieldElement> {
+            perm(&perm(input))
+        }
+
+        for (width, partial_rounds) in vec![(3, 55), (5, 56)] {
+            let params = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+            for sbox in &all_sboxes() {
+                let perm = params.permutation_fn(*sbox);
+                let input = (0..width)
+                    .map(|_| FieldElement::random())
+                    .collect::<Vec<_>>();
+                let expected = Poseidon_permutation(&input, &params, sbox);
+                assert_eq!(perm(&input), expected);
+                assert_eq!(
+                    apply_twice(&perm, &input),
+                    Poseidon_permutation(&expected, &params, sbox)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_key() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        assert_eq!(params.round_key(0, 0), Some(&params.round_keys[0]));
+        assert_eq!(params.round_key(2, 3), Some(&params.round_keys[13]));
+        assert_eq!(
+            params.round_key(2, 3),
+            Some(&params.round_keys_by_round()[2][3])
+        );
+        assert_eq!(params.round_key(63, 4), params.round_keys.last());
+
+        // Position past the width is not the key of the next round
+        assert_eq!(params.round_key(0, 5), None);
+        assert_eq!(params.round_key(64, 0), None);
+        assert_eq!(params.round_key(usize::max_value(), 1), None);
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let sbox = SboxType::Quint;
+        let inputs = (0..4).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let expected = Poseidon_hash_4(inputs.clone(), &params, &sbox).unwrap();
+
+        // Oversized tables as when built by hand
+        let mut oversized = params.clone();
+        oversized
+            .round_keys
+            .extend((0..10).map(|_| FieldElement::random()));
+        for row in oversized.MDS_matrix.iter_mut() {
+            row.push(FieldElement::random());
+        }
+        oversized
+            .MDS_matrix
+            .push((0..6).map(|_| FieldElement::random()).collect());
+
+        oversized.shrink_to_fit();
+        assert_eq!(oversized.round_keys, params.round_keys);
+        assert_eq!(oversized.MDS_matrix, params.MDS_matrix);
+        assert_eq!(oversized.round_keys.capacity(), oversized.round_keys.len());
+        assert_eq!(
+            Poseidon_hash_4(inputs.clone(), &oversized, &sbox).unwrap(),
+            expected
+        );
+
+        // Already exact params are unchanged
+        let mut exact = params.clone();
+        exact.shrink_to_fit();
+        assert_eq!(exact.round_keys, params.round_keys);
+        assert_eq!(Poseidon_hash_4(inputs, &exact, &sbox).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_permutation_owned() {
+        for &(width, partial_rounds) in [(3, 55), (5, 56), (9, 57)].iter() {
+            let params = PoseidonParams::new(width, 4, 4, partial_rounds).unwrap();
+            for sbox in all_sboxes() {
+                let input = (0..width)
+                    .map(|_| FieldElement::random())
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    Poseidon_permutation(&input, &params, &sbox),
+                    Poseidon_permutation_owned(input.clone(), &params, &sbox)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_hex_tables() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let (round_keys, mds) = params.to_hex_tables();
+        assert_eq!(round_keys.len(), params.round_keys.len());
+        assert_eq!(mds.len(), 3);
+
+        // Parsing the hex back gives the same elements
+        let parse = |hex: &str| {
+            PoseidonParams::<FieldElement>::get_field_element_from_hex_str(hex).unwrap()
+        };
+        for (hex, key) in round_keys.iter().zip(params.round_keys.iter()) {
+            assert_eq!(&parse(hex), key);
+        }
+        for (hex_row, row) in mds.iter().zip(params.MDS_matrix.iter()) {
+            assert_eq!(hex_row.len(), 3);
+            for (hex, entry) in hex_row.iter().zip(row.iter()) {
+                assert_eq!(&parse(hex), entry);
+            }
+        }
+
+        // Textually same as the hardcoded tables
+        assert_eq!(
+            &round_keys[..],
+            &ROUND_CONSTS_W_3[..round_keys.len()]
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()[..]
+        );
+        for i in 0..3 {
+            assert_eq!(
+                mds[i],
+                MDS_ENTRIES_W_3[i]
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_permutation_w3() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        for sbox in all_sboxes() {
+            for _ in 0..50 {
+                let input = [
+                    FieldElement::random(),
+                    FieldElement::random(),
+                    FieldElement::random(),
+                ];
+                let generic = Poseidon_permutation_owned(input.to_vec(), &params, &sbox);
+                assert_eq!(
+                    poseidon_permutation_w3(input.clone(), &params, &sbox).to_vec(),
+                    generic
+                );
+                assert_eq!(Poseidon_permutation(&input, &params, &sbox), generic);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_permutation_w3_wrong_width() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        poseidon_permutation_w3(
+            [
+                FieldElement::one(),
+                FieldElement::one(),
+                FieldElement::one(),
+            ],
+            &params,
+            &SboxType::Quint,
+        );
+    }
+
+    #[test]
+    fn test_wider_hashes() {
+        // Digest is output 1 of the permutation of the capacity constant followed by the inputs
+        let params_5 = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let params_9 = PoseidonParams::new(9, 4, 4, 57).unwrap();
+        for sbox in all_sboxes() {
+            let inputs = (1..=4u64).map(FieldElement::from).collect::<Vec<_>>();
+            let mut state = vec![FieldElement::from(CAP_CONST_W_5)];
+            state.extend_from_slice(&inputs);
+            assert_eq!(
+                Poseidon_hash_4(inputs, &params_5, &sbox).unwrap(),
+                Poseidon_permutation(&state, &params_5, &sbox)[1]
+            );
+
+            let inputs = (1..=8u64).map(FieldElement::from).collect::<Vec<_>>();
+            let mut state = vec![FieldElement::from(CAP_CONST_W_9)];
+            state.extend_from_slice(&inputs);
+            assert_eq!(
+                Poseidon_hash_8(inputs, &params_9, &sbox).unwrap(),
+                Poseidon_permutation(&state, &params_9, &sbox)[1]
+            );
+        }
+
+        let sbox = SboxType::Quint;
+        let inputs = (1..=3u64).map(FieldElement::from).collect::<Vec<_>>();
+        match Poseidon_hash_4(inputs, &params_5, &sbox)
+            .unwrap_err()
+            .kind()
+        {
+            BulletproofErrorKind::IncorrectWidthForPoseidon { width, expected } => {
+                assert_eq!((width, expected), (3, 4))
+            }
+            k => panic!("Unexpected error {:?}", k),
+        }
+        let inputs = (1..=7u64).map(FieldElement::from).collect::<Vec<_>>();
+        match Poseidon_hash_8(inputs, &params_9, &sbox)
+            .unwrap_err()
+            .kind()
+        {
+            BulletproofErrorKind::IncorrectWidthForPoseidon { width, expected } => {
+                assert_eq!((width, expected), (7, 8))
+            }
+            k => panic!("Unexpected error {:?}", k),
+        }
+    }
+
+    #[cfg(feature = "bls381")]
+    #[test]
+    fn test_permutation_kat() {
+        // Computed with an independent implementation of the permutation
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let input = vec![
+            FieldElement::from(7u64),
+            FieldElement::from(8u64),
+            FieldElement::from(9u64),
+        ];
+        let expected = vec![
+            "0x48ad7057b3fa1aa516b8a0426f641a303c0d615f81b4f91ffd79ac30b531bfdb",
+            "0x531e87f26ab671ae0915e55d0642ba1cbbbb88afb2442683c9d3b2abf146c30d",
+            "0x58936c8dd71d6c8ee42e6d2ba15a6205f43ad3d2d2868925efafe4ac5c406a97",
+        ]
+        .into_iter()
+        .map(|h| PoseidonParams::<FieldElement>::get_field_element_from_hex_str(h).unwrap())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            Poseidon_permutation(&input, &params, &SboxType::Quint),
+            expected
+        );
+    }
+}
@@ -0,0 +1,3257 @@
+use crate::errors::{BulletproofError, R1CSError, R1CSErrorKind};
+use crate::r1cs::linear_combination::AllocatedQuantity;
+use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
+use amcl_wrapper::constants::MODBYTES;
+use amcl_wrapper::field_elem::FieldElement;
+use amcl_wrapper::types::BigNum;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use super::bit::bit_gadget;
+use super::poseidon::{
+    domain_tag, PoseidonParams, PoseidonSponge, Poseidon_hash_2, Poseidon_hash_2_constraints,
+    Poseidon_hash_4, Poseidon_hash_4_constraints, Poseidon_hash_8, Poseidon_hash_8_constraints,
+    Poseidon_hash_n_constraints, Poseidon_permutation_owned, SboxType, CAP_CONST_W_3,
+    CAP_CONST_W_5, CAP_CONST_W_9, DOMAIN_LABEL_MERKLE_KV_LEAF, DOMAIN_LABEL_MERKLE_STRUCTURE,
+};
+use super::positive_no::positive_no_gadget;
+use super::{allocate_lc_as_quantity, constrain_lc_with_scalar};
+use crate::r1cs::gadgets::merkle_tree_hash::{PoseidonHash2, TwoToOneHash};
+use crate::r1cs::gadgets::poseidon_commitment::poseidon_commit_constraints;
+
+// Binary merkle tree where each node is the Poseidon 2:1 hash of its 2 children. A path is given
+// from leaf to root as the sibling nodes and the direction at each level, a direction being 1
+// when the node on the path is the right child and 0 when its the left child. Thus the
+// directions are the bits of the leaf index, least significant bit first.
+
+/// Hash a key and value into a leaf of a key-value tree. The key always occupies the first slot
+/// of the hash and the value the second so the same (key, value) always give the same leaf.
+/// Uses the domain tag of `DOMAIN_LABEL_MERKLE_KV_LEAF` as capacity constant so a leaf can never
+/// equal an internal node with the same children. Errors if the params are not of width 3.
+pub fn poseidon_kv_leaf(
+    key: FieldElement,
+    value: FieldElement,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, R1CSError> {
+    check_kv_leaf_width(params)?;
+    let input = vec![domain_tag(DOMAIN_LABEL_MERKLE_KV_LEAF), key, value];
+    Ok(Poseidon_permutation_owned(input, params, sbox).remove(1))
+}
+
+/// Enforces the constraints of `poseidon_kv_leaf` and returns the leaf
+pub fn poseidon_kv_leaf_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    key: LinearCombination,
+    value: LinearCombination,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<LinearCombination, R1CSError> {
+    check_kv_leaf_width(params)?;
+    Poseidon_hash_2_constraints::<CS>(
+        cs,
+        vec![key, value],
+        domain_tag(DOMAIN_LABEL_MERKLE_KV_LEAF).into(),
+        params,
+        sbox_type,
+    )
+}
+
+fn check_kv_leaf_width(params: &PoseidonParams) -> Result<(), R1CSError> {
+    if params.width != 3 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Key-value leaf needs params of width 3 but found {}",
+                params.width
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Directions of the path of the leaf at `index` in a tree of depth `depth`, i.e. the bits of the
+/// index, least significant bit first. Errors if the index does not fit in `depth` bits.
+pub fn index_bits(index: usize, depth: usize) -> Result<Vec<bool>, R1CSError> {
+    if depth < 8 * std::mem::size_of::<usize>() && (index >> depth) != 0 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!("Index {} is too large for tree of depth {}", index, depth),
+        }
+        .into());
+    }
+    Ok((0..depth)
+        .map(|i| i < 8 * std::mem::size_of::<usize>() && (index >> i) & 1 == 1)
+        .collect())
+}
+
+/// Compute the root of the tree from the leaf, its path and the directions of the path.
+pub fn poseidon_merkle_root(
+    leaf: &FieldElement,
+    path: &[FieldElement],
+    directions: &[bool],
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, BulletproofError> {
+    merkle_root(&PoseidonHash2 { params, sbox }, leaf, path, directions)
+}
+
+/// Same as `poseidon_merkle_root` but with any 2:1 hash for the nodes
+pub fn merkle_root<H: TwoToOneHash>(
+    hasher: &H,
+    leaf: &FieldElement,
+    path: &[FieldElement],
+    directions: &[bool],
+) -> Result<FieldElement, BulletproofError> {
+    if path.len() != directions.len() {
+        return Err(R1CSError::from(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Path has {} nodes but {} directions given",
+                path.len(),
+                directions.len()
+            ),
+        })
+        .into());
+    }
+    let mut cur = leaf.clone();
+    for (sibling, is_right) in path.iter().zip(directions.iter()) {
+        cur = if *is_right {
+            hasher.hash(sibling, &cur)?
+        } else {
+            hasher.hash(&cur, sibling)?
+        };
+    }
+    Ok(cur)
+}
+
+/// Path of a leaf, the sibling nodes from leaf to root and the index of the leaf. The directions
+/// of the path are the bits of the index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleProof {
+    pub siblings: Vec<FieldElement>,
+    pub index: u64,
+}
+
+impl MerkleProof {
+    /// Version of the serialization format, written as the first byte by `to_bytes`
+    pub const VERSION: u8 = 1;
+
+    /// Serialize as the version byte, the index as 8 bytes in big endian and then the siblings,
+    /// leaf level first, each of `MODBYTES` bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![Self::VERSION];
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        for s in &self.siblings {
+            bytes.extend_from_slice(&s.to_bytes());
+        }
+        bytes
+    }
+
+    /// Deserialize bytes returned by `to_bytes`. Errors if the version is unknown, the length is
+    /// invalid or the index does not fit in as many bits as the number of siblings.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, R1CSError> {
+        if bytes.is_empty() || bytes[0] != Self::VERSION {
+            return Err(R1CSErrorKind::GadgetError {
+                description: String::from("Unknown version of merkle proof"),
+            }
+            .into());
+        }
+        if bytes.len() < 9 || (bytes.len() - 9) % MODBYTES != 0 {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!("Invalid length {} of merkle proof", bytes.len()),
+            }
+            .into());
+        }
+
+        let mut index_bytes = [0u8; 8];
+        index_bytes.copy_from_slice(&bytes[1..9]);
+        let index = u64::from_be_bytes(index_bytes);
+        let depth = (bytes.len() - 9) / MODBYTES;
+        if depth < 64 && (index >> depth) != 0 {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Index {} is too large for merkle proof with {} siblings",
+                    index, depth
+                ),
+            }
+            .into());
+        }
+
+        let mut siblings = vec![];
+        for chunk in bytes[9..].chunks(MODBYTES) {
+            siblings.push(FieldElement::from_bytes(chunk).map_err(|_| {
+                R1CSError::from(R1CSErrorKind::GadgetError {
+                    description: String::from("Cannot parse sibling of merkle proof"),
+                })
+            })?);
+        }
+        Ok(Self { siblings, index })
+    }
+
+    /// Directions of the path, i.e. the bits of the index, least significant bit first. Errors
+    /// if the index does not fit in as many bits as the number of siblings.
+    pub fn directions(&self) -> Result<Vec<bool>, R1CSError> {
+        let index = usize::try_from(self.index).map_err(|_| {
+            R1CSError::from(R1CSErrorKind::GadgetError {
+                description: format!("Index {} does not fit in usize", self.index),
+            })
+        })?;
+        index_bits(index, self.siblings.len())
+    }
+
+    /// Compute the root of the tree with `leaf` at this path
+    pub fn root(
+        &self,
+        leaf: &FieldElement,
+        params: &PoseidonParams,
+        sbox: &SboxType,
+    ) -> Result<FieldElement, BulletproofError> {
+        poseidon_merkle_root(leaf, &self.siblings, &self.directions()?, params, sbox)
+    }
+}
+
+/// Append only tree of fixed depth. Leaves are added from left to right and the positions not
+/// yet appended are empty. An empty leaf is 0 and an empty node is the hash of 2 empty children.
+/// The tree keeps only the frontier, i.e. for each level the last node waiting for its right
+/// sibling, which is enough to compute the root. When created with `new_retaining`, it also keeps
+/// all nodes of the completely appended subtrees (at most `2*n - 1` for `n` leaves, not the
+/// `2^(depth+1) - 1` nodes of the whole tree) so that `proof_for` can give the path of any
+/// appended leaf. Needs params of width 3.
+#[derive(Clone, Debug)]
+pub struct PoseidonMerkleTree<'a> {
+    pub depth: usize,
+    pub params: &'a PoseidonParams,
+    pub sbox: &'a SboxType,
+    num_leaves: u64,
+    // `frontier[i]` is the last left node at level i, valid when bit i of `num_leaves` is 1
+    frontier: Vec<FieldElement>,
+    // `empty[i]` is the root of an empty subtree of height i
+    empty: Vec<FieldElement>,
+    // Root once the tree is full since the frontier is all consumed
+    full_root: Option<FieldElement>,
+    // `levels[i]` has the nodes at level i of completely appended subtrees, leaves at level 0
+    levels: Option<Vec<Vec<FieldElement>>>,
+}
+
+impl<'a> PoseidonMerkleTree<'a> {
+    /// Create an empty tree that can only compute the root
+    pub fn new(
+        depth: usize,
+        params: &'a PoseidonParams,
+        sbox: &'a SboxType,
+    ) -> Result<Self, R1CSError> {
+        if params.width != 3 {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!("Tree needs params of width 3 but found {}", params.width),
+            }
+            .into());
+        }
+        if depth == 0 || depth >= 64 {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!("Depth should be in [1, 63] but is {}", depth),
+            }
+            .into());
+        }
+        let mut empty = vec![FieldElement::zero()];
+        for i in 0..depth {
+            let e = hash_nodes(&empty[i], &empty[i], params, sbox);
+            empty.push(e);
+        }
+        Ok(Self {
+            depth,
+            params,
+            sbox,
+            num_leaves: 0,
+            frontier: vec![FieldElement::zero(); depth],
+            empty,
+            full_root: None,
+            levels: None,
+        })
+    }
+
+    /// Create an empty tree that retains nodes to give proofs with `proof_for`
+    pub fn new_retaining(
+        depth: usize,
+        params: &'a PoseidonParams,
+        sbox: &'a SboxType,
+    ) -> Result<Self, R1CSError> {
+        let mut tree = Self::new(depth, params, sbox)?;
+        tree.levels = Some(vec![vec![]; depth]);
+        Ok(tree)
+    }
+
+    pub fn num_leaves(&self) -> u64 {
+        self.num_leaves
+    }
+
+    /// Append the leaf and return its index. Errors if the tree is full.
+    pub fn append(&mut self, leaf: FieldElement) -> Result<u64, R1CSError> {
+        let index = self.num_leaves;
+        if index == 1u64 << self.depth {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!("Tree of depth {} is full", self.depth),
+            }
+            .into());
+        }
+        let mut cur = leaf;
+        for i in 0..self.depth {
+            if let Some(levels) = self.levels.as_mut() {
+                levels[i].push(cur.clone());
+            }
+            if (index >> i) & 1 == 0 {
+                self.frontier[i] = cur;
+                self.num_leaves += 1;
+                return Ok(index);
+            }
+            cur = hash_nodes(&self.frontier[i], &cur, self.params, self.sbox);
+        }
+        // Last leaf completed all levels
+        self.full_root = Some(cur);
+        self.num_leaves += 1;
+        Ok(index)
+    }
+
+    /// Root of the tree with the positions not appended yet being empty
+    pub fn root(&self) -> FieldElement {
+        if let Some(r) = &self.full_root {
+            return r.clone();
+        }
+        // Go up from the first empty position, combining with the frontier on the left or
+        // an empty subtree on the right.
+        let mut cur = self.empty[0].clone();
+        for i in 0..self.depth {
+            cur = if (self.num_leaves >> i) & 1 == 1 {
+                hash_nodes(&self.frontier[i], &cur, self.params, self.sbox)
+            } else {
+                hash_nodes(&cur, &self.empty[i], self.params, self.sbox)
+            };
+        }
+        cur
+    }
+
+    /// Commitment to the root together with the shape of the tree, i.e. the number of appended
+    /// leaves, the arity and the depth. Appending empty leaves (0) does not change the root but
+    /// changes this so snapshots of the tree can be told apart. The domain tag of
+    /// `DOMAIN_LABEL_MERKLE_STRUCTURE`, the root, the number of leaves, the arity 2 and the depth
+    /// are absorbed in a `PoseidonSponge` and one element is squeezed.
+    pub fn structure_commitment(&self) -> FieldElement {
+        // Capacity 1 is valid for any width
+        let mut sponge = PoseidonSponge::new(self.params, self.sbox, 1).unwrap();
+        sponge.absorb(&[
+            domain_tag(DOMAIN_LABEL_MERKLE_STRUCTURE),
+            self.root(),
+            FieldElement::from(self.num_leaves),
+            FieldElement::from(2u64),
+            FieldElement::from(self.depth as u64),
+        ]);
+        sponge.squeeze(1).remove(0)
+    }
+
+    /// Path of the appended leaf at `index` to the current root. Errors if the tree does not
+    /// retain nodes or the leaf at `index` was not appended.
+    pub fn proof_for(&self, index: u64) -> Result<MerkleProof, R1CSError> {
+        let levels = match &self.levels {
+            Some(l) => l,
+            None => {
+                return Err(R1CSErrorKind::GadgetError {
+                    description: String::from("Tree does not retain nodes for proofs"),
+                }
+                .into())
+            }
+        };
+        if index >= self.num_leaves {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Index {} is not less than number of leaves {}",
+                    index, self.num_leaves
+                ),
+            }
+            .into());
+        }
+        let siblings = (0..self.depth)
+            .map(|i| self.node(levels, i, (index >> i) ^ 1))
+            .collect();
+        Ok(MerkleProof { siblings, index })
+    }
+
+    /// Multiproof of the appended leaves at `indices`. Errors if the tree does not retain nodes,
+    /// no index is given or a leaf was not appended. Duplicate indices are ignored.
+    pub fn multiproof_for(&self, indices: &[u64]) -> Result<MerkleMultiProof, R1CSError> {
+        let levels = match &self.levels {
+            Some(l) => l,
+            None => {
+                return Err(R1CSErrorKind::GadgetError {
+                    description: String::from("Tree does not retain nodes for proofs"),
+                }
+                .into())
+            }
+        };
+        let mut indices = indices.to_vec();
+        indices.sort();
+        indices.dedup();
+        match indices.last() {
+            None => {
+                return Err(R1CSErrorKind::GadgetError {
+                    description: String::from("Need at least 1 index for multiproof"),
+                }
+                .into())
+            }
+            Some(i) if *i >= self.num_leaves => {
+                return Err(R1CSErrorKind::GadgetError {
+                    description: format!(
+                        "Index {} is not less than number of leaves {}",
+                        i, self.num_leaves
+                    ),
+                }
+                .into())
+            }
+            _ => (),
+        }
+
+        let mut siblings = vec![];
+        let mut known = indices.clone();
+        for i in 0..self.depth {
+            let mut level_siblings = vec![];
+            for pos in &known {
+                // Nodes whose sibling is known are computed
+                if known.binary_search(&(pos ^ 1)).is_err() {
+                    level_siblings.push(self.node(levels, i, pos ^ 1));
+                }
+            }
+            siblings.push(level_siblings);
+            known = known.iter().map(|p| p >> 1).collect();
+            known.dedup();
+        }
+        Ok(MerkleMultiProof { indices, siblings })
+    }
+
+    /// Node at position `pos` of `level`, either retained, empty or the only partially appended
+    /// node of the level which is computed from its children.
+    fn node(&self, levels: &[Vec<FieldElement>], level: usize, pos: u64) -> FieldElement {
+        if (pos as usize) < levels[level].len() {
+            levels[level][pos as usize].clone()
+        } else if pos << level >= self.num_leaves {
+            self.empty[level].clone()
+        } else {
+            let left = self.node(levels, level - 1, 2 * pos);
+            let right = self.node(levels, level - 1, 2 * pos + 1);
+            hash_nodes(&left, &right, self.params, self.sbox)
+        }
+    }
+}
+
+/// Proof of membership of several leaves of the same tree where each node is given at most once,
+/// as an Octopus multiproof. `indices` are the sorted indices of the leaves and `siblings[i]` are
+/// the nodes at level i that are needed but cannot be computed from the leaves, in increasing
+/// order of position. Nodes shared by the paths of several leaves are computed only once.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleMultiProof {
+    pub indices: Vec<u64>,
+    pub siblings: Vec<Vec<FieldElement>>,
+}
+
+impl MerkleMultiProof {
+    /// Number of hashes needed to compute the root, at most `depth` for each leaf
+    pub fn num_hashes(&self) -> usize {
+        let mut known = self.indices.clone();
+        let mut hashes = 0;
+        for _ in 0..self.siblings.len() {
+            known = known.iter().map(|p| p >> 1).collect();
+            known.dedup();
+            hashes += known.len();
+        }
+        hashes
+    }
+
+    /// Compute the root of the tree with `leaves` at `indices`
+    pub fn root(
+        &self,
+        leaves: &[FieldElement],
+        params: &PoseidonParams,
+        sbox: &SboxType,
+    ) -> Result<FieldElement, R1CSError> {
+        let levels = self.expand(leaves, params, sbox)?;
+        Ok(levels.last().unwrap()[&0].clone())
+    }
+
+    /// Individual path of each leaf, in the order of `indices`
+    pub fn paths(
+        &self,
+        leaves: &[FieldElement],
+        params: &PoseidonParams,
+        sbox: &SboxType,
+    ) -> Result<Vec<MerkleProof>, R1CSError> {
+        let levels = self.expand(leaves, params, sbox)?;
+        Ok(self
+            .indices
+            .iter()
+            .map(|index| MerkleProof {
+                siblings: (0..self.siblings.len())
+                    .map(|i| levels[i][&((index >> i) ^ 1)].clone())
+                    .collect(),
+                index: *index,
+            })
+            .collect())
+    }
+
+    /// Returns the nodes known at each level by position, the leaves and given siblings at
+    /// level 0 and so on, with the root alone at the last level
+    fn expand(
+        &self,
+        leaves: &[FieldElement],
+        params: &PoseidonParams,
+        sbox: &SboxType,
+    ) -> Result<Vec<BTreeMap<u64, FieldElement>>, R1CSError> {
+        let depth = self.siblings.len();
+        if params.width != 3 {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Multiproof needs params of width 3 but found {}",
+                    params.width
+                ),
+            }
+            .into());
+        }
+        if leaves.len() != self.indices.len() || leaves.is_empty() {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Multiproof has {} indices but {} leaves given",
+                    self.indices.len(),
+                    leaves.len()
+                ),
+            }
+            .into());
+        }
+        if self.indices.windows(2).any(|w| w[0] >= w[1])
+            || (depth < 64 && self.indices.iter().any(|i| i >> depth != 0))
+        {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Indices should be sorted, distinct and less than 2^{}",
+                    depth
+                ),
+            }
+            .into());
+        }
+        let err = || {
+            R1CSError::from(R1CSErrorKind::GadgetError {
+                description: String::from("Multiproof does not have the needed siblings"),
+            })
+        };
+
+        let mut known = self
+            .indices
+            .iter()
+            .cloned()
+            .zip(leaves.iter().cloned())
+            .collect::<BTreeMap<_, _>>();
+        let mut levels = vec![];
+        for level_siblings in &self.siblings {
+            let mut given = level_siblings.iter();
+            let mut all = known.clone();
+            let mut next = BTreeMap::new();
+            for (pos, node) in &known {
+                if next.contains_key(&(pos >> 1)) {
+                    continue;
+                }
+                let sibling = match known.get(&(pos ^ 1)) {
+                    Some(s) => s.clone(),
+                    None => {
+                        let s = given.next().ok_or_else(err)?.clone();
+                        all.insert(pos ^ 1, s.clone());
+                        s
+                    }
+                };
+                let parent = if pos & 1 == 0 {
+                    hash_nodes(node, &sibling, params, sbox)
+                } else {
+                    hash_nodes(&sibling, node, params, sbox)
+                };
+                next.insert(pos >> 1, parent);
+            }
+            if given.next().is_some() {
+                return Err(err());
+            }
+            levels.push(all);
+            known = next;
+        }
+        levels.push(known);
+        Ok(levels)
+    }
+}
+
+fn hash_nodes(
+    left: &FieldElement,
+    right: &FieldElement,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> FieldElement {
+    // Poseidon_hash_2 only errors when not given 2 inputs. It panics on params not of width 3 so
+    // `PoseidonMerkleTree::new` and `MerkleMultiProof::expand` check the width before hashing.
+    Poseidon_hash_2(vec![left.clone(), right.clone()], params, sbox).unwrap()
+}
+
+/// Enforces the constraints for computing the root of the tree from the leaf, its path and
+/// the directions of the path. Each direction is constrained to be a bit. Returns the root
+/// as a linear combination.
+pub fn poseidon_merkle_path_constraints<CS: ConstraintSystem>(
+    cs: &mut CS,
+    leaf: LinearCombination,
+    path: &[Variable],
+    directions: &[AllocatedQuantity],
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<LinearCombination, R1CSError> {
+    let hasher = PoseidonHash2 {
+        params,
+        sbox: sbox_type,
+    };
+    merkle_path_constraints(cs, &hasher, leaf, path, directions)
+}
+
+/// Same as `poseidon_merkle_path_constraints` but with any 2:1 hash for the nodes
+pub fn merkle_path_constraints<CS: ConstraintSystem, H: TwoToOneHash>(
+    cs: &mut CS,
+    hasher: &H,
+    leaf: LinearCombination,
+    path: &[Variable],
+    directions: &[AllocatedQuantity],
+) -> Result<LinearCombination, R1CSError> {
+    if path.len() != directions.len() {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Path has {} nodes but {} directions given",
+                path.len(),
+                directions.len()
+            ),
+        }
+        .into());
+    }
+
+    let mut cur = leaf;
+    for (sibling, direction) in path.iter().zip(directions.iter()) {
+        bit_gadget(cs, direction)?;
+
+        // left = cur + direction*(sibling - cur) and right = sibling + cur - left
+        // so when direction is 0, left = cur, right = sibling and when direction is 1, left = sibling, right = cur
+        let (_, _, o) = cs.multiply(direction.variable.into(), *sibling - cur.clone());
+        let left = cur + o;
+        let right = *sibling - o;
+
+        cur = hasher.hash_constraints(cs, left, right)?.simplify();
+    }
+    Ok(cur)
+}
+
+/// Constraints for proving that several leaves are in the tree with the given `root`, following
+/// the nodes of a `MerkleMultiProof`. `leaves` are the leaves at the sorted and distinct
+/// `indices` and `siblings[i]` are the nodes at level i as in `MerkleMultiProof::siblings`. Each
+/// node needed for the root is hashed exactly once, `MerkleMultiProof::num_hashes` hashes in
+/// total. The indices are public as they decide which nodes are hashed together, the leaves and
+/// siblings stay hidden.
+pub fn poseidon_merkle_multiproof_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    leaves: Vec<Variable>,
+    indices: &[u64],
+    siblings: &[Vec<Variable>],
+    root: &FieldElement,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    let depth = siblings.len();
+    if leaves.is_empty() || leaves.len() != indices.len() {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Need at least 1 leaf for multiproof and as many leaves as indices but found {} \
+                 leaves and {} indices",
+                leaves.len(),
+                indices.len()
+            ),
+        }
+        .into());
+    }
+    if indices.windows(2).any(|w| w[0] >= w[1])
+        || (depth < 64 && indices.iter().any(|i| i >> depth != 0))
+    {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Indices should be sorted, distinct and less than 2^{}",
+                depth
+            ),
+        }
+        .into());
+    }
+    let err = || {
+        R1CSError::from(R1CSErrorKind::GadgetError {
+            description: String::from("Multiproof does not have the needed siblings"),
+        })
+    };
+
+    let hasher = PoseidonHash2 {
+        params,
+        sbox: sbox_type,
+    };
+    // Same traversal as `MerkleMultiProof::expand`
+    let mut known = indices
+        .iter()
+        .cloned()
+        .zip(leaves.into_iter().map(LinearCombination::from))
+        .collect::<BTreeMap<_, _>>();
+    for level_siblings in siblings {
+        let mut given = level_siblings.iter();
+        let mut next = BTreeMap::new();
+        for (pos, node) in &known {
+            if next.contains_key(&(pos >> 1)) {
+                continue;
+            }
+            let sibling = match known.get(&(pos ^ 1)) {
+                Some(s) => s.clone(),
+                None => LinearCombination::from(*given.next().ok_or_else(err)?),
+            };
+            let parent = if pos & 1 == 0 {
+                hasher.hash_constraints(cs, node.clone(), sibling)?
+            } else {
+                hasher.hash_constraints(cs, sibling, node.clone())?
+            };
+            next.insert(pos >> 1, parent.simplify());
+        }
+        if given.next().is_some() {
+            return Err(err());
+        }
+        known = next;
+    }
+    constrain_lc_with_scalar::<CS>(cs, known.remove(&0).unwrap(), root);
+    Ok(())
+}
+
+/// Constraints for proving that a record is in the tree with the given `root` where the leaf is
+/// the hash of the record's fields with `Poseidon_hash_n`. The fields are hashed with
+/// `params_leaf` which can be of a different width than `params_tree` used for the tree nodes,
+/// thus records of up to `params_leaf.width - 1` fields are supported.
+pub fn poseidon_record_membership_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    record_fields: Vec<Variable>,
+    path: &[Variable],
+    directions: &[AllocatedQuantity],
+    root: &FieldElement,
+    params_leaf: &PoseidonParams,
+    params_tree: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    let leaf = Poseidon_hash_n_constraints::<CS>(
+        cs,
+        record_fields.into_iter().map(|f| f.into()).collect(),
+        params_leaf,
+        sbox_type,
+    )?
+    .simplify();
+    let computed_root =
+        poseidon_merkle_path_constraints(cs, leaf, path, directions, params_tree, sbox_type)?;
+    constrain_lc_with_scalar::<CS>(cs, computed_root, root);
+    Ok(())
+}
+
+/// Constraints for proving that replacing the hidden leaf `old_leaf` with hidden `new_leaf`
+/// transforms the tree with root `old_root` into the tree with root `new_root`. The same siblings
+/// `path` and directions `bits` are used for both roots so only the leaf changes.
+pub fn merkle_update_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    old_leaf: Variable,
+    new_leaf: Variable,
+    path: Vec<AllocatedQuantity>,
+    bits: &[AllocatedQuantity],
+    old_root: &FieldElement,
+    new_root: &FieldElement,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    let path = path.into_iter().map(|p| p.variable).collect::<Vec<_>>();
+    let computed_old_root =
+        poseidon_merkle_path_constraints(cs, old_leaf.into(), &path, bits, params, sbox_type)?;
+    constrain_lc_with_scalar::<CS>(cs, computed_old_root, old_root);
+    let computed_new_root =
+        poseidon_merkle_path_constraints(cs, new_leaf.into(), &path, bits, params, sbox_type)?;
+    constrain_lc_with_scalar::<CS>(cs, computed_new_root, new_root);
+    Ok(())
+}
+
+/// Constraints for revealing a leaf of the tree with the given `root`, i.e. proving that `leaf`
+/// is in the tree and equals `public_value`. The path and directions stay hidden.
+pub fn poseidon_reveal_leaf_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    leaf: Variable,
+    public_value: FieldElement,
+    path: &[Variable],
+    directions: &[AllocatedQuantity],
+    root: &FieldElement,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    constrain_lc_with_scalar::<CS>(cs, leaf.into(), &public_value);
+    let computed_root =
+        poseidon_merkle_path_constraints(cs, leaf.into(), path, directions, params, sbox_type)?;
+    constrain_lc_with_scalar::<CS>(cs, computed_root, root);
+    Ok(())
+}
+
+/// Constraints for proving that the commitment to hidden `value` with hidden `randomness`, as
+/// computed by `poseidon_commit`, is a leaf of the tree with the given `root`. The
+/// leaf, its path and directions `bits` stay hidden.
+pub fn committed_leaf_membership_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    value: AllocatedQuantity,
+    randomness: AllocatedQuantity,
+    path: &[Variable],
+    bits: &[AllocatedQuantity],
+    root: &FieldElement,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    let leaf = poseidon_commit_constraints(
+        cs,
+        value.variable.into(),
+        randomness.variable.into(),
+        params,
+        sbox_type,
+    )?
+    .simplify();
+    let computed_root = poseidon_merkle_path_constraints(cs, leaf, path, bits, params, sbox_type)?;
+    constrain_lc_with_scalar::<CS>(cs, computed_root, root);
+    Ok(())
+}
+
+/// Constraints for proving that `leaf` is at position `index` of the tree with the given `root`.
+/// Rather than taking the directions, `index` is decomposed into `depth` bits which are used as
+/// the directions, so the directions are proved to come from an index in `[0, 2^depth)`. The
+/// leaf, index and path stay hidden.
+pub fn poseidon_indexed_membership_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    leaf: Variable,
+    index: AllocatedQuantity,
+    depth: usize,
+    path: &[Variable],
+    root: &FieldElement,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    if path.len() != depth {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!("Path has {} nodes but depth is {}", path.len(), depth),
+        }
+        .into());
+    }
+
+    let mut directions = vec![];
+    for i in 0..depth {
+        let assignment = index.assignment.as_ref().map(|idx| {
+            if idx.shift_right(i).is_odd() {
+                FieldElement::one()
+            } else {
+                FieldElement::zero()
+            }
+        });
+        let variable = cs.allocate(assignment.clone())?;
+        directions.push(AllocatedQuantity {
+            variable,
+            assignment,
+        });
+    }
+    // index = Sum(directions[i] * 2^i), with the directions being bits this also proves that
+    // index < 2^depth
+    cs.constrain(bits_to_lc(&directions) - index.variable);
+
+    let computed_root =
+        poseidon_merkle_path_constraints(cs, leaf.into(), path, &directions, params, sbox_type)?;
+    constrain_lc_with_scalar::<CS>(cs, computed_root, root);
+    Ok(())
+}
+
+/// Returns the linear combination `Sum(bits[i] * 2^i)`. Does not constrain the bits.
+fn bits_to_lc(bits: &[AllocatedQuantity]) -> LinearCombination {
+    let mut exp_2 = FieldElement::one();
+    let mut terms = vec![];
+    for b in bits {
+        terms.push((b.variable, exp_2.clone()));
+        exp_2 = &exp_2 + &exp_2;
+    }
+    terms.iter().collect()
+}
+
+/// Proves that `query` is not a leaf of a sorted tree (leaves are in increasing order from left
+/// to right) with root `root`. This is done by proving that `left_neighbor` and `right_neighbor`
+/// are leaves of the tree, they are adjacent, i.e. index of `right_neighbor` is 1 more than index
+/// of `left_neighbor` and `left_neighbor < query < right_neighbor`.
+/// `directions` are the directions of the path of `left_neighbor`, the directions for the path of
+/// `right_neighbor` are allocated by the gadget. The values of `query`, `left_neighbor` and
+/// `right_neighbor` should be less than 2^n.
+pub fn poseidon_sorted_nonmembership_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    query: AllocatedQuantity,
+    left_neighbor: AllocatedQuantity,
+    right_neighbor: AllocatedQuantity,
+    left_path: &[Variable],
+    right_path: &[Variable],
+    directions: &[AllocatedQuantity],
+    root: &FieldElement,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    n: usize,
+) -> Result<(), R1CSError> {
+    // Directions of the right neighbor's path are the bits of (index of left neighbor + 1).
+    // When index of left neighbor is the last index, the addition overflows and the prover
+    // allocates 0 which fails the adjacency constraint below.
+    let right_directions = allocate_directions_after(cs, directions, 1)?;
+
+    // Both neighbors are members of the tree
+    let left_root = poseidon_merkle_path_constraints(
+        cs,
+        left_neighbor.variable.into(),
+        left_path,
+        directions,
+        params,
+        sbox_type,
+    )?;
+    constrain_lc_with_scalar::<CS>(cs, left_root, root);
+    let right_root = poseidon_merkle_path_constraints(
+        cs,
+        right_neighbor.variable.into(),
+        right_path,
+        &right_directions,
+        params,
+        sbox_type,
+    )?;
+    constrain_lc_with_scalar::<CS>(cs, right_root, root);
+
+    // Neighbors are adjacent, index of right neighbor - index of left neighbor = 1
+    constrain_lc_with_scalar::<CS>(
+        cs,
+        bits_to_lc(&right_directions) - bits_to_lc(directions),
+        &FieldElement::one(),
+    );
+
+    // left_neighbor < query => query - left_neighbor - 1 in [0, 2^n)
+    let diff = allocate_lc_as_quantity(
+        cs,
+        query.variable - left_neighbor.variable - FieldElement::one(),
+    )?;
+    positive_no_gadget(cs, diff, n)?;
+    // query < right_neighbor => right_neighbor - query - 1 in [0, 2^n)
+    let diff = allocate_lc_as_quantity(
+        cs,
+        right_neighbor.variable - query.variable - FieldElement::one(),
+    )?;
+    positive_no_gadget(cs, diff, n)?;
+
+    Ok(())
+}
+
+/// Allocate the directions of the path of the leaf `k` positions after the leaf with the given
+/// `directions`, i.e. the bits of `index + k` where `index` is the index given by `directions`.
+/// The addition wraps around on overflow so the caller should constrain the relation of the
+/// indices for the result to be meaningful. The directions are not constrained to be bits.
+fn allocate_directions_after<CS: ConstraintSystem>(
+    cs: &mut CS,
+    directions: &[AllocatedQuantity],
+    k: usize,
+) -> Result<Vec<AllocatedQuantity>, R1CSError> {
+    let bits = directions
+        .iter()
+        .map(|d| d.assignment.as_ref().map(|b| b.is_one()))
+        .collect::<Option<Vec<bool>>>();
+    let bits = bits.map(|mut bits| {
+        for _ in 0..k {
+            for b in bits.iter_mut() {
+                *b = !*b;
+                // Stop at first 0 turned 1, i.e. when no carry
+                if *b {
+                    break;
+                }
+            }
+        }
+        bits
+    });
+    let mut allocated = vec![];
+    for i in 0..directions.len() {
+        let assignment = bits.as_ref().map(|bits| {
+            if bits[i] {
+                FieldElement::one()
+            } else {
+                FieldElement::zero()
+            }
+        });
+        let variable = cs.allocate(assignment.clone())?;
+        allocated.push(AllocatedQuantity {
+            variable,
+            assignment,
+        });
+    }
+    Ok(allocated)
+}
+
+// A sorted tree can leave empty leaves (0) between its members so that values can be inserted
+// later without moving the other leaves. A value is inserted in an empty leaf whose left and
+// right leaves are members, the value being more than the left one and less than the right one.
+
+/// Index of the empty leaf of the sorted `leaves` where `value` can be inserted, i.e. the leaf
+/// is 0, the leaves on both its sides are non-zero and `value` lies strictly between them.
+/// Returns `None` if there is no such leaf.
+pub fn sorted_insert_position(leaves: &[FieldElement], value: &FieldElement) -> Option<usize> {
+    let less =
+        |a: &FieldElement, b: &FieldElement| BigNum::comp(&a.to_bignum(), &b.to_bignum()) < 0;
+    (1..leaves.len().saturating_sub(1)).find(|&i| {
+        leaves[i].is_zero()
+            && !leaves[i - 1].is_zero()
+            && !leaves[i + 1].is_zero()
+            && less(&leaves[i - 1], value)
+            && less(value, &leaves[i + 1])
+    })
+}
+
+/// Proves that inserting hidden `new_value` in the sorted tree with root `old_root` gives the
+/// tree with root `new_root`. This is done by proving that `left_neighbor` and `right_neighbor`
+/// are leaves of the old tree with an empty leaf between them, i.e. index of `right_neighbor` is
+/// 2 more than index of `left_neighbor`, that `left_neighbor < new_value < right_neighbor` and
+/// that replacing the empty leaf with `new_value` gives `new_root`.
+/// `directions` are the directions of the path of `left_neighbor`. The path of the empty leaf is
+/// not taken, its sibling is `left_neighbor` when it is a right child and `right_neighbor`
+/// otherwise, and its nodes above that are the same as those of the path of that sibling. The
+/// values of `new_value`, `left_neighbor` and `right_neighbor` should be less than 2^n.
+pub fn poseidon_sorted_insert_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    new_value: AllocatedQuantity,
+    left_neighbor: AllocatedQuantity,
+    right_neighbor: AllocatedQuantity,
+    left_path: &[Variable],
+    right_path: &[Variable],
+    directions: &[AllocatedQuantity],
+    old_root: &FieldElement,
+    new_root: &FieldElement,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    n: usize,
+) -> Result<(), R1CSError> {
+    let depth = directions.len();
+    if depth == 0 || left_path.len() != depth || right_path.len() != depth {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Paths have {} and {} nodes but {} directions given",
+                left_path.len(),
+                right_path.len(),
+                depth
+            ),
+        }
+        .into());
+    }
+
+    // Both neighbors are members of the old tree
+    let left_root = poseidon_merkle_path_constraints(
+        cs,
+        left_neighbor.variable.into(),
+        left_path,
+        directions,
+        params,
+        sbox_type,
+    )?;
+    constrain_lc_with_scalar::<CS>(cs, left_root, old_root);
+    let right_directions = allocate_directions_after(cs, directions, 2)?;
+    let right_root = poseidon_merkle_path_constraints(
+        cs,
+        right_neighbor.variable.into(),
+        right_path,
+        &right_directions,
+        params,
+        sbox_type,
+    )?;
+    constrain_lc_with_scalar::<CS>(cs, right_root, old_root);
+
+    // Empty leaf is between the neighbors
+    let gap_directions = allocate_directions_after(cs, directions, 1)?;
+    constrain_lc_with_scalar::<CS>(
+        cs,
+        bits_to_lc(&gap_directions) - bits_to_lc(directions),
+        &FieldElement::one(),
+    );
+    constrain_lc_with_scalar::<CS>(
+        cs,
+        bits_to_lc(&right_directions) - bits_to_lc(directions),
+        &FieldElement::from(2u64),
+    );
+
+    // Path of the empty leaf. When left neighbor is a right child, i.e. its first direction is
+    // 1, the empty leaf shares its parent with the right neighbor else with the left neighbor.
+    let is_right = directions[0].variable;
+    let mut gap_path = vec![];
+    let mut choose = |l: LinearCombination, r: LinearCombination| {
+        let (_, _, o) = cs.multiply(is_right.into(), r - l.clone());
+        allocate_lc_as_quantity(cs, l + o).map(|q| q.variable)
+    };
+    gap_path.push(choose(
+        left_neighbor.variable.into(),
+        right_neighbor.variable.into(),
+    )?);
+    for i in 1..depth {
+        gap_path.push(choose(left_path[i].into(), right_path[i].into())?);
+    }
+
+    // The gap is empty in the old tree and has the new value in the new tree
+    let gap_root = poseidon_merkle_path_constraints(
+        cs,
+        LinearCombination::default(),
+        &gap_path,
+        &gap_directions,
+        params,
+        sbox_type,
+    )?;
+    constrain_lc_with_scalar::<CS>(cs, gap_root, old_root);
+    let inserted_root = poseidon_merkle_path_constraints(
+        cs,
+        new_value.variable.into(),
+        &gap_path,
+        &gap_directions,
+        params,
+        sbox_type,
+    )?;
+    constrain_lc_with_scalar::<CS>(cs, inserted_root, new_root);
+
+    // left_neighbor < new_value => new_value - left_neighbor - 1 in [0, 2^n)
+    let diff = allocate_lc_as_quantity(
+        cs,
+        new_value.variable - left_neighbor.variable - FieldElement::one(),
+    )?;
+    positive_no_gadget(cs, diff, n)?;
+    // new_value < right_neighbor => right_neighbor - new_value - 1 in [0, 2^n)
+    let diff = allocate_lc_as_quantity(
+        cs,
+        right_neighbor.variable - new_value.variable - FieldElement::one(),
+    )?;
+    positive_no_gadget(cs, diff, n)?;
+
+    Ok(())
+}
+
+/// Poseidon params for each arity of a `MixedArityMerkleTree`. Nodes of arity 2, 4 and 8 are
+/// hashes of their children with `Poseidon_hash_2`, `Poseidon_hash_4` and `Poseidon_hash_8`
+/// which need params of width 3, 5 and 9 respectively. Only the params of the arities present in
+/// the tree are needed.
+#[derive(Clone, Debug, Default)]
+pub struct MixedArityParams<'a> {
+    pub params_2: Option<&'a PoseidonParams>,
+    pub params_4: Option<&'a PoseidonParams>,
+    pub params_8: Option<&'a PoseidonParams>,
+}
+
+impl<'a> MixedArityParams<'a> {
+    /// Params for hashing the children of a node with the given arity
+    pub fn for_arity(&self, arity: usize) -> Result<&'a PoseidonParams, R1CSError> {
+        let params = match arity {
+            2 => self.params_2,
+            4 => self.params_4,
+            8 => self.params_8,
+            _ => {
+                return Err(R1CSErrorKind::GadgetError {
+                    description: format!("Arity should be 2, 4 or 8 but is {}", arity),
+                }
+                .into())
+            }
+        };
+        match params {
+            Some(p) if p.width == arity + 1 => Ok(p),
+            Some(p) => Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Arity {} needs params of width {} but found {}",
+                    arity,
+                    arity + 1,
+                    p.width
+                ),
+            }
+            .into()),
+            None => Err(R1CSErrorKind::GadgetError {
+                description: format!("No params given for arity {}", arity),
+            }
+            .into()),
+        }
+    }
+
+    fn hash(
+        &self,
+        children: Vec<FieldElement>,
+        sbox: &SboxType,
+    ) -> Result<FieldElement, R1CSError> {
+        let arity = children.len();
+        let params = self.for_arity(arity)?;
+        let res = match arity {
+            2 => Poseidon_hash_2(children, params, sbox),
+            4 => Poseidon_hash_4(children, params, sbox),
+            _ => Poseidon_hash_8(children, params, sbox),
+        };
+        res.map_err(|e| {
+            R1CSErrorKind::GadgetError {
+                description: e.to_string(),
+            }
+            .into()
+        })
+    }
+
+    fn hash_constraints<CS: ConstraintSystem>(
+        &self,
+        cs: &mut CS,
+        children: Vec<LinearCombination>,
+        sbox_type: &SboxType,
+    ) -> Result<LinearCombination, R1CSError> {
+        let arity = children.len();
+        let params = self.for_arity(arity)?;
+        let hash = match arity {
+            2 => Poseidon_hash_2_constraints::<CS>(
+                cs,
+                children,
+                FieldElement::from(CAP_CONST_W_3).into(),
+                params,
+                sbox_type,
+            )?,
+            4 => Poseidon_hash_4_constraints::<CS>(
+                cs,
+                children,
+                FieldElement::from(CAP_CONST_W_5).into(),
+                params,
+                sbox_type,
+            )?,
+            _ => Poseidon_hash_8_constraints::<CS>(
+                cs,
+                children,
+                FieldElement::from(CAP_CONST_W_9).into(),
+                params,
+                sbox_type,
+            )?,
+        };
+        Ok(hash.simplify())
+    }
+}
+
+/// Merkle tree where the nodes of each level can have a different arity, 2, 4 or 8, like a tree
+/// that is wide near the root. `arities[i]` is the arity of the nodes at level `i + 1`, leaves
+/// being at level 0, so the tree has `arities.iter().product()` leaves. The leaf at `index` is at
+/// position `index % arities[0]` among its siblings, its parent at position
+/// `(index / arities[0]) % arities[1]` and so on. The leaves not given are 0. Keeps all nodes.
+#[derive(Clone, Debug)]
+pub struct MixedArityMerkleTree<'a> {
+    pub arities: Vec<usize>,
+    pub params: MixedArityParams<'a>,
+    pub sbox: &'a SboxType,
+    // `levels[i]` has the nodes at level i, leaves at level 0 and the root at the last level
+    levels: Vec<Vec<FieldElement>>,
+}
+
+/// Path of a leaf in a `MixedArityMerkleTree`. `siblings[i]` are the siblings of the node on the
+/// path at level i, in order and excluding that node, and `positions[i]` is the position of that
+/// node among its siblings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MixedArityMerkleProof {
+    pub siblings: Vec<Vec<FieldElement>>,
+    pub positions: Vec<usize>,
+}
+
+impl<'a> MixedArityMerkleTree<'a> {
+    pub fn new(
+        mut leaves: Vec<FieldElement>,
+        arities: Vec<usize>,
+        params: MixedArityParams<'a>,
+        sbox: &'a SboxType,
+    ) -> Result<Self, R1CSError> {
+        if arities.is_empty() {
+            return Err(R1CSErrorKind::GadgetError {
+                description: String::from("Tree needs at least 1 level"),
+            }
+            .into());
+        }
+        let mut num_leaves = 1usize;
+        for a in &arities {
+            params.for_arity(*a)?;
+            num_leaves = num_leaves.checked_mul(*a).ok_or_else(|| {
+                R1CSError::from(R1CSErrorKind::GadgetError {
+                    description: String::from("Tree has too many leaves"),
+                })
+            })?;
+        }
+        if leaves.len() > num_leaves {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!("Tree has {} leaves but {} given", num_leaves, leaves.len()),
+            }
+            .into());
+        }
+        leaves.resize(num_leaves, FieldElement::zero());
+
+        let mut levels = vec![leaves];
+        for a in &arities {
+            let mut next = vec![];
+            for children in levels.last().unwrap().chunks(*a) {
+                next.push(params.hash(children.to_vec(), sbox)?);
+            }
+            levels.push(next);
+        }
+        Ok(Self {
+            arities,
+            params,
+            sbox,
+            levels,
+        })
+    }
+
+    pub fn num_leaves(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn root(&self) -> FieldElement {
+        self.levels.last().unwrap()[0].clone()
+    }
+
+    /// Path of the leaf at `index`
+    pub fn proof_for(&self, mut index: usize) -> Result<MixedArityMerkleProof, R1CSError> {
+        if index >= self.num_leaves() {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Index {} is not less than number of leaves {}",
+                    index,
+                    self.num_leaves()
+                ),
+            }
+            .into());
+        }
+        let mut siblings = vec![];
+        let mut positions = vec![];
+        for (level, a) in self.levels.iter().zip(self.arities.iter()) {
+            let pos = index % a;
+            let first = index - pos;
+            let mut s = level[first..first + a].to_vec();
+            s.remove(pos);
+            siblings.push(s);
+            positions.push(pos);
+            index /= a;
+        }
+        Ok(MixedArityMerkleProof {
+            siblings,
+            positions,
+        })
+    }
+}
+
+impl MixedArityMerkleProof {
+    /// Directions of the path to use with `poseidon_mixed_arity_path_constraints`, for each
+    /// level the `log2(arity)` bits of the position, least significant bit first.
+    pub fn directions(&self) -> Result<Vec<Vec<bool>>, R1CSError> {
+        self.siblings
+            .iter()
+            .zip(self.positions.iter())
+            .map(|(s, p)| index_bits(*p, (s.len() + 1).trailing_zeros() as usize))
+            .collect()
+    }
+
+    /// Root of the tree computed from the leaf and this path
+    pub fn root(
+        &self,
+        leaf: &FieldElement,
+        params: &MixedArityParams,
+        sbox: &SboxType,
+    ) -> Result<FieldElement, R1CSError> {
+        if self.siblings.len() != self.positions.len() {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Path has {} levels but {} positions given",
+                    self.siblings.len(),
+                    self.positions.len()
+                ),
+            }
+            .into());
+        }
+        let mut cur = leaf.clone();
+        for (s, p) in self.siblings.iter().zip(self.positions.iter()) {
+            if *p > s.len() {
+                return Err(R1CSErrorKind::GadgetError {
+                    description: format!("Position {} is more than {} siblings", p, s.len()),
+                }
+                .into());
+            }
+            let mut children = s.clone();
+            children.insert(*p, cur);
+            cur = params.hash(children, sbox)?;
+        }
+        Ok(cur)
+    }
+}
+
+/// Enforces the constraints for computing the root of a `MixedArityMerkleTree` with the given
+/// `arities` from the leaf and its path. For each level, `path` has the `arity - 1` siblings in
+/// order and `directions` the `log2(arity)` bits of the position of the node on the path, least
+/// significant bit first. Each direction is constrained to be a bit. Returns the root as a
+/// linear combination.
+pub fn poseidon_mixed_arity_path_constraints<CS: ConstraintSystem>(
+    cs: &mut CS,
+    leaf: LinearCombination,
+    path: &[Vec<Variable>],
+    directions: &[Vec<AllocatedQuantity>],
+    arities: &[usize],
+    params: &MixedArityParams,
+    sbox_type: &SboxType,
+) -> Result<LinearCombination, R1CSError> {
+    if path.len() != arities.len() || directions.len() != arities.len() {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Tree has {} levels but path has {} and directions have {}",
+                arities.len(),
+                path.len(),
+                directions.len()
+            ),
+        }
+        .into());
+    }
+
+    let mut cur = leaf;
+    for ((siblings, bits), arity) in path.iter().zip(directions.iter()).zip(arities.iter()) {
+        params.for_arity(*arity)?;
+        if siblings.len() != arity - 1 || bits.len() != arity.trailing_zeros() as usize {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Level of arity {} has {} siblings and {} directions",
+                    arity,
+                    siblings.len(),
+                    bits.len()
+                ),
+            }
+            .into());
+        }
+        for b in bits {
+            bit_gadget(cs, b)?;
+        }
+
+        // `selectors[j]` is 1 when the node on the path is at position j and 0 otherwise
+        let mut selectors: Vec<LinearCombination> = vec![Variable::One().into()];
+        for (i, b) in bits.iter().enumerate() {
+            let mut high = vec![];
+            for s in selectors.iter_mut() {
+                // Selector of position j + 2^i is selector of j times the bit, for the first
+                // bit the selector is 1 so no multiplication needed
+                let sb: LinearCombination = if i == 0 {
+                    b.variable.into()
+                } else {
+                    cs.multiply(s.clone(), b.variable.into()).2.into()
+                };
+                *s = s.clone() - sb.clone();
+                high.push(sb);
+            }
+            selectors.append(&mut high);
+        }
+
+        // Child j is the node on the path when the position is j, sibling j-1 when the position
+        // is before j and sibling j when the position is after j.
+        let mut children = vec![];
+        // Sum of selectors of the positions before j, i.e. 1 iff the position is before j
+        let mut before: LinearCombination = LinearCombination::default();
+        for j in 0..*arity {
+            let (_, _, at) = cs.multiply(selectors[j].clone(), cur.clone());
+            let mut child: LinearCombination = at.into();
+            if j > 0 {
+                let (_, _, o) = cs.multiply(before.clone(), siblings[j - 1].into());
+                child = child + o;
+            }
+            if j < arity - 1 {
+                let after = Variable::One() - selectors[j].clone() - before.clone();
+                let (_, _, o) = cs.multiply(after, siblings[j].into());
+                child = child + o;
+            }
+            children.push(child);
+            before = before + selectors[j].clone();
+        }
+
+        cur = params.hash_constraints(cs, children, sbox_type)?;
+    }
+    Ok(cur)
+}
+
+/// Constraints for proving that `leaf` is in the `MixedArityMerkleTree` with the given `arities`
+/// and `root`. The leaf, its path and directions stay hidden.
+pub fn poseidon_mixed_arity_membership_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    leaf: Variable,
+    path: &[Vec<Variable>],
+    directions: &[Vec<AllocatedQuantity>],
+    arities: &[usize],
+    root: &FieldElement,
+    params: &MixedArityParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    let computed_root = poseidon_mixed_arity_path_constraints(
+        cs,
+        leaf.into(),
+        path,
+        directions,
+        arities,
+        params,
+        sbox_type,
+    )?;
+    constrain_lc_with_scalar::<CS>(cs, computed_root, root);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::gadgets::helper_constraints::mimc::mimc;
+    use crate::r1cs::gadgets::helper_constraints::poseidon::Poseidon_hash_n;
+    use crate::r1cs::gadgets::merkle_tree_hash::Mimc2;
+    use crate::r1cs::gadgets::poseidon_commitment::poseidon_commit;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    /// Returns all levels of the tree, leaves first and root last
+    fn build_tree(
+        leaves: Vec<FieldElement>,
+        params: &PoseidonParams,
+        sbox: &SboxType,
+    ) -> Vec<Vec<FieldElement>> {
+        let mut levels = vec![leaves];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|c| Poseidon_hash_2(c.to_vec(), params, sbox).unwrap())
+                .collect();
+            levels.push(next);
+        }
+        levels
+    }
+
+    fn get_path(levels: &[Vec<FieldElement>], mut index: usize) -> (Vec<FieldElement>, Vec<bool>) {
+        let directions = index_bits(index, levels.len() - 1).unwrap();
+        let mut path = vec![];
+        for level in &levels[..levels.len() - 1] {
+            path.push(level[index ^ 1].clone());
+            index >>= 1;
+        }
+        (path, directions)
+    }
+
+    #[test]
+    fn test_poseidon_kv_leaf() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let key = FieldElement::random();
+        let value = FieldElement::random();
+        let leaf = poseidon_kv_leaf(key.clone(), value.clone(), &params, sbox_type).unwrap();
+
+        // Deterministic
+        assert_eq!(
+            leaf,
+            poseidon_kv_leaf(key.clone(), value.clone(), &params, sbox_type).unwrap()
+        );
+        // Swapping key and value gives a different leaf
+        assert_ne!(
+            leaf,
+            poseidon_kv_leaf(value.clone(), key.clone(), &params, sbox_type).unwrap()
+        );
+        // Different from the internal node with the same children
+        assert_ne!(
+            leaf,
+            Poseidon_hash_2(vec![key.clone(), value.clone()], &params, sbox_type).unwrap()
+        );
+
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let mut prover_transcript = Transcript::new(b"KVLeaf");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        let (_, key_var) = prover.commit(key.clone(), FieldElement::random());
+        let (_, value_var) = prover.commit(value.clone(), FieldElement::random());
+        let leaf_lc = poseidon_kv_leaf_gadget(
+            &mut prover,
+            key_var.into(),
+            value_var.into(),
+            &params,
+            sbox_type,
+        )
+        .unwrap();
+        assert_eq!(prover.evaluate_lc(&leaf_lc).unwrap(), leaf);
+
+        // Only width 3 is supported
+        let params_5 = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        assert!(poseidon_kv_leaf(key, value, &params_5, sbox_type).is_err());
+        let num_multipliers = prover.num_multipliers();
+        assert!(poseidon_kv_leaf_gadget(
+            &mut prover,
+            key_var.into(),
+            value_var.into(),
+            &params_5,
+            sbox_type,
+        )
+        .is_err());
+        assert_eq!(prover.num_multipliers(), num_multipliers);
+    }
+
+    #[test]
+    fn test_merkle_proof_serialization() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox = SboxType::Quint;
+        let leaves = (0..8).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let levels = build_tree(leaves.clone(), &params, &sbox);
+        let root = levels.last().unwrap()[0].clone();
+
+        for index in 0..8 {
+            let (siblings, _) = get_path(&levels, index);
+            let proof = MerkleProof {
+                siblings,
+                index: index as u64,
+            };
+            let bytes = proof.to_bytes();
+            assert_eq!(bytes.len(), 9 + 3 * MODBYTES);
+            let decoded = MerkleProof::from_bytes(&bytes).unwrap();
+            assert_eq!(decoded, proof);
+            assert_eq!(decoded.root(&leaves[index], &params, &sbox).unwrap(), root);
+        }
+    }
+
+    #[test]
+    fn test_merkle_proof_malformed() {
+        let proof = MerkleProof {
+            siblings: (0..3).map(|_| FieldElement::random()).collect(),
+            index: 5,
+        };
+        let bytes = proof.to_bytes();
+        assert_eq!(proof.directions().unwrap(), vec![true, false, true]);
+
+        // Index too large for the siblings when built directly
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let wide = MerkleProof {
+            siblings: proof.siblings.clone(),
+            index: 8,
+        };
+        assert!(wide.directions().is_err());
+        assert!(wide
+            .root(&FieldElement::random(), &params, &SboxType::Quint)
+            .is_err());
+
+        assert!(MerkleProof::from_bytes(&[]).is_err());
+        // Unknown version
+        let mut b = bytes.clone();
+        b[0] = 2;
+        assert!(MerkleProof::from_bytes(&b).is_err());
+        // Truncated index and truncated sibling
+        assert!(MerkleProof::from_bytes(&bytes[..5]).is_err());
+        assert!(MerkleProof::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        // Index needs more bits than the number of siblings
+        let mut b = bytes.clone();
+        b[8] = 8;
+        assert!(MerkleProof::from_bytes(&b).is_err());
+        let mut b = bytes.clone();
+        b[1] = 0x80;
+        assert!(MerkleProof::from_bytes(&b).is_err());
+        // No siblings is only valid for index 0
+        assert!(MerkleProof::from_bytes(&bytes[..9]).is_err());
+        let mut b = bytes[..9].to_vec();
+        b[8] = 0;
+        assert!(MerkleProof::from_bytes(&b).unwrap().siblings.is_empty());
+    }
+
+    #[test]
+    fn test_index_bits() {
+        assert_eq!(index_bits(0, 3).unwrap(), vec![false, false, false]);
+        assert_eq!(index_bits(6, 3).unwrap(), vec![false, true, true]);
+        assert_eq!(index_bits(7, 3).unwrap(), vec![true, true, true]);
+        assert_eq!(
+            index_bits(1, 5).unwrap(),
+            vec![true, false, false, false, false]
+        );
+        assert_eq!(index_bits(0, 0).unwrap(), Vec::<bool>::new());
+        assert_eq!(index_bits(usize::max_value(), 70).unwrap().len(), 70);
+
+        assert!(index_bits(8, 3).is_err());
+        assert!(index_bits(1, 0).is_err());
+        assert!(index_bits(1 << 20, 20).is_err());
+    }
+
+    fn check_path_via_trait<H: TwoToOneHash>(
+        hasher: &H,
+        leaf: &FieldElement,
+        path: &[FieldElement],
+        directions: &[bool],
+        root: &FieldElement,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"TwoToOneHashPath");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let (leaf_comm, leaf_var) = prover.commit(leaf.clone(), FieldElement::random());
+            let mut comms = vec![leaf_comm];
+            let mut path_vars = vec![];
+            for node in path {
+                let (c, v) = prover.commit(node.clone(), FieldElement::random());
+                comms.push(c);
+                path_vars.push(v);
+            }
+            let mut dirs = vec![];
+            for d in directions {
+                let d = FieldElement::from(*d as u64);
+                let (c, v) = prover.commit(d.clone(), FieldElement::random());
+                comms.push(c);
+                dirs.push(AllocatedQuantity {
+                    variable: v,
+                    assignment: Some(d),
+                });
+            }
+            let r =
+                merkle_path_constraints(&mut prover, hasher, leaf_var.into(), &path_vars, &dirs)
+                    .unwrap();
+            constrain_lc_with_scalar(&mut prover, r, root);
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"TwoToOneHashPath");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars = commitments
+            .into_iter()
+            .map(|c| verifier.commit(c))
+            .collect::<Vec<_>>();
+        let leaf_var = vars.remove(0);
+        let dirs = vars
+            .split_off(path.len())
+            .into_iter()
+            .map(|v| AllocatedQuantity {
+                variable: v,
+                assignment: None,
+            })
+            .collect::<Vec<_>>();
+        let r =
+            merkle_path_constraints(&mut verifier, hasher, leaf_var.into(), &vars, &dirs).unwrap();
+        constrain_lc_with_scalar(&mut verifier, r, root);
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_two_to_one_hash_tree() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+        let hasher = PoseidonHash2 {
+            params: &params,
+            sbox: sbox_type,
+        };
+
+        // Build a tree of 8 leaves only through the trait
+        let leaves = (0..8).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let mut levels = vec![leaves.clone()];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|c| hasher.hash(&c[0], &c[1]).unwrap())
+                .collect();
+            levels.push(next);
+        }
+        let root = levels.last().unwrap()[0].clone();
+        assert_eq!(levels, build_tree(leaves.clone(), &params, sbox_type));
+
+        let mut tree = PoseidonMerkleTree::new(3, &params, sbox_type).unwrap();
+        for l in &leaves {
+            tree.append(l.clone()).unwrap();
+        }
+        assert_eq!(tree.root(), root);
+
+        for i in 0..leaves.len() {
+            let (path, directions) = get_path(&levels, i);
+            assert_eq!(
+                merkle_root(&hasher, &leaves[i], &path, &directions).unwrap(),
+                root
+            );
+            assert_eq!(
+                poseidon_merkle_root(&leaves[i], &path, &directions, &params, sbox_type).unwrap(),
+                root
+            );
+        }
+
+        let (path, directions) = get_path(&levels, 5);
+        assert!(check_path_via_trait(
+            &hasher,
+            &leaves[5],
+            &path,
+            &directions,
+            &root
+        ));
+        assert!(!check_path_via_trait(
+            &hasher,
+            &leaves[4],
+            &path,
+            &directions,
+            &root
+        ));
+
+        // Same tree with MiMC for the nodes
+        let constants = (0..322).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let mimc_hasher = Mimc2 {
+            constants: &constants,
+        };
+        let mut mimc_levels = vec![leaves.clone()];
+        while mimc_levels.last().unwrap().len() > 1 {
+            let next = mimc_levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|c| mimc(&c[0], &c[1], &constants))
+                .collect();
+            mimc_levels.push(next);
+        }
+        let mimc_root = mimc_levels.last().unwrap()[0].clone();
+        assert_ne!(mimc_root, root);
+        let (path, directions) = get_path(&mimc_levels, 5);
+        assert_eq!(
+            merkle_root(&mimc_hasher, &leaves[5], &path, &directions).unwrap(),
+            mimc_root
+        );
+        assert!(check_path_via_trait(
+            &mimc_hasher,
+            &leaves[5],
+            &path,
+            &directions,
+            &mimc_root
+        ));
+
+        // Params of other width error instead of panicking
+        let params_5 = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        let hasher_5 = PoseidonHash2 {
+            params: &params_5,
+            sbox: sbox_type,
+        };
+        assert!(hasher_5.hash(&leaves[0], &leaves[1]).is_err());
+        assert!(
+            poseidon_merkle_root(&leaves[0], &path, &directions, &params_5, sbox_type).is_err()
+        );
+
+        // Path and directions of different lengths error instead of panicking
+        assert!(
+            poseidon_merkle_root(&leaves[0], &path, &directions[1..], &params, sbox_type).is_err()
+        );
+        assert!(merkle_root(&mimc_hasher, &leaves[0], &path[1..], &directions).is_err());
+    }
+
+    fn check_sorted_nonmembership(query: u64, left_index: usize, expect_success: bool) {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+        let n = 64;
+
+        let G: G1Vector = get_generators("G", 4096).into();
+        let H: G1Vector = get_generators("H", 4096).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let leaves = (1..=8)
+            .map(|i| FieldElement::from(i * 10u64))
+            .collect::<Vec<_>>();
+        let levels = build_tree(leaves.clone(), &params, sbox_type);
+        let root = levels.last().unwrap()[0].clone();
+
+        let (left_path, left_directions) = get_path(&levels, left_index);
+        let (right_path, _) = get_path(&levels, left_index + 1);
+        assert_eq!(
+            poseidon_merkle_root(
+                &leaves[left_index],
+                &left_path,
+                &left_directions,
+                &params,
+                sbox_type
+            )
+            .unwrap(),
+            root
+        );
+
+        let query = FieldElement::from(query);
+        let left_neighbor = leaves[left_index].clone();
+        let right_neighbor = leaves[left_index + 1].clone();
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"SortedNonMembership");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocate = |prover: &mut Prover, v: FieldElement| {
+                let (com, var) = prover.commit(v.clone(), FieldElement::random());
+                comms.push(com);
+                AllocatedQuantity {
+                    variable: var,
+                    assignment: Some(v),
+                }
+            };
+
+            let q = allocate(&mut prover, query);
+            let l = allocate(&mut prover, left_neighbor);
+            let r = allocate(&mut prover, right_neighbor);
+            let lp = left_path
+                .iter()
+                .map(|v| allocate(&mut prover, v.clone()).variable)
+                .collect::<Vec<_>>();
+            let rp = right_path
+                .iter()
+                .map(|v| allocate(&mut prover, v.clone()).variable)
+                .collect::<Vec<_>>();
+            let dirs = left_directions
+                .iter()
+                .map(|d| allocate(&mut prover, FieldElement::from(*d as u64)))
+                .collect::<Vec<_>>();
+
+            poseidon_sorted_nonmembership_gadget(
+                &mut prover,
+                q,
+                l,
+                r,
+                &lp,
+                &rp,
+                &dirs,
+                &root,
+                &params,
+                sbox_type,
+                n,
+            )
+            .unwrap();
+
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"SortedNonMembership");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars = commitments
+            .into_iter()
+            .map(|c| verifier.commit(c))
+            .collect::<Vec<_>>();
+        let mut next = || AllocatedQuantity {
+            variable: vars.remove(0),
+            assignment: None,
+        };
+        let q = next();
+        let l = next();
+        let r = next();
+        let lp = (0..3).map(|_| next().variable).collect::<Vec<_>>();
+        let rp = (0..3).map(|_| next().variable).collect::<Vec<_>>();
+        let dirs = (0..3).map(|_| next()).collect::<Vec<_>>();
+
+        poseidon_sorted_nonmembership_gadget(
+            &mut verifier,
+            q,
+            l,
+            r,
+            &lp,
+            &rp,
+            &dirs,
+            &root,
+            &params,
+            sbox_type,
+            n,
+        )
+        .unwrap();
+
+        assert_eq!(
+            verifier.verify(&proof, &g, &h, &G, &H).is_ok(),
+            expect_success
+        );
+    }
+
+    #[test]
+    fn test_poseidon_sorted_nonmembership() {
+        // 35 lies between leaves 30 and 40 at indices 2 and 3
+        check_sorted_nonmembership(35, 2, true);
+        // 45 lies between leaves 40 and 50 at indices 3 and 4, paths differ at all levels
+        check_sorted_nonmembership(45, 3, true);
+        // 30 is a leaf so it can't lie strictly between its neighbors
+        check_sorted_nonmembership(30, 2, false);
+        check_sorted_nonmembership(40, 2, false);
+    }
+
+    fn check_sorted_insert(
+        leaves: &[FieldElement],
+        new_value: u64,
+        left_index: usize,
+        tamper_new_root: bool,
+    ) -> bool {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+        let n = 64;
+
+        let G: G1Vector = get_generators("G", 8192).into();
+        let H: G1Vector = get_generators("H", 8192).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let new_value = FieldElement::from(new_value);
+        let levels = build_tree(leaves.to_vec(), &params, sbox_type);
+        let old_root = levels.last().unwrap()[0].clone();
+        let mut new_leaves = leaves.to_vec();
+        new_leaves[left_index + 1] = new_value.clone();
+        let mut new_root = build_tree(new_leaves, &params, sbox_type).last().unwrap()[0].clone();
+        if tamper_new_root {
+            new_root = FieldElement::random();
+        }
+
+        let (left_path, left_directions) = get_path(&levels, left_index);
+        let (right_path, _) = get_path(&levels, left_index + 2);
+        let depth = left_path.len();
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"SortedInsert");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocate = |prover: &mut Prover, v: FieldElement| {
+                let (com, var) = prover.commit(v.clone(), FieldElement::random());
+                comms.push(com);
+                AllocatedQuantity {
+                    variable: var,
+                    assignment: Some(v),
+                }
+            };
+
+            let v = allocate(&mut prover, new_value);
+            let l = allocate(&mut prover, leaves[left_index].clone());
+            let r = allocate(&mut prover, leaves[left_index + 2].clone());
+            let lp = left_path
+                .iter()
+                .map(|v| allocate(&mut prover, v.clone()).variable)
+                .collect::<Vec<_>>();
+            let rp = right_path
+                .iter()
+                .map(|v| allocate(&mut prover, v.clone()).variable)
+                .collect::<Vec<_>>();
+            let dirs = left_directions
+                .iter()
+                .map(|d| allocate(&mut prover, FieldElement::from(*d as u64)))
+                .collect::<Vec<_>>();
+
+            poseidon_sorted_insert_gadget(
+                &mut prover,
+                v,
+                l,
+                r,
+                &lp,
+                &rp,
+                &dirs,
+                &old_root,
+                &new_root,
+                &params,
+                sbox_type,
+                n,
+            )
+            .unwrap();
+
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"SortedInsert");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars = commitments
+            .into_iter()
+            .map(|c| verifier.commit(c))
+            .collect::<Vec<_>>();
+        let mut next = || AllocatedQuantity {
+            variable: vars.remove(0),
+            assignment: None,
+        };
+        let v = next();
+        let l = next();
+        let r = next();
+        let lp = (0..depth).map(|_| next().variable).collect::<Vec<_>>();
+        let rp = (0..depth).map(|_| next().variable).collect::<Vec<_>>();
+        let dirs = (0..depth).map(|_| next()).collect::<Vec<_>>();
+
+        poseidon_sorted_insert_gadget(
+            &mut verifier,
+            v,
+            l,
+            r,
+            &lp,
+            &rp,
+            &dirs,
+            &old_root,
+            &new_root,
+            &params,
+            sbox_type,
+            n,
+        )
+        .unwrap();
+
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_poseidon_sorted_insert() {
+        // Empty leaves at indices 2 and 5
+        let leaves = vec![10, 20, 0, 40, 50, 0, 70, 80]
+            .into_iter()
+            .map(|i| FieldElement::from(i as u64))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            sorted_insert_position(&leaves, &FieldElement::from(30u64)),
+            Some(2)
+        );
+        assert_eq!(
+            sorted_insert_position(&leaves, &FieldElement::from(60u64)),
+            Some(5)
+        );
+        assert_eq!(
+            sorted_insert_position(&leaves, &FieldElement::from(45u64)),
+            None
+        );
+        assert_eq!(
+            sorted_insert_position(&leaves, &FieldElement::from(40u64)),
+            None
+        );
+
+        // Empty leaf shares its parent with the right neighbor
+        assert!(check_sorted_insert(&leaves, 30, 1, false));
+        // Empty leaf shares its parent with the left neighbor
+        assert!(check_sorted_insert(&leaves, 60, 4, false));
+
+        // Breaks the order
+        assert!(!check_sorted_insert(&leaves, 45, 1, false));
+        assert!(!check_sorted_insert(&leaves, 20, 1, false));
+        // New root is not of the tree with the value inserted
+        assert!(!check_sorted_insert(&leaves, 30, 1, true));
+        // Leaf between the neighbors is not empty
+        assert!(!check_sorted_insert(&leaves, 45, 2, false));
+    }
+
+    fn check_record_membership(
+        record: &[FieldElement],
+        index: usize,
+        leaves: Vec<FieldElement>,
+        params_leaf: &PoseidonParams,
+        params_tree: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let levels = build_tree(leaves, params_tree, sbox_type);
+        let root = levels.last().unwrap()[0].clone();
+        let (path, directions) = get_path(&levels, index);
+        let depth = path.len();
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"RecordMembership");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocate = |prover: &mut Prover, v: FieldElement| {
+                let (com, var) = prover.commit(v.clone(), FieldElement::random());
+                comms.push(com);
+                AllocatedQuantity {
+                    variable: var,
+                    assignment: Some(v),
+                }
+            };
+            let fields = record
+                .iter()
+                .map(|f| allocate(&mut prover, f.clone()).variable)
+                .collect::<Vec<_>>();
+            let p = path
+                .iter()
+                .map(|v| allocate(&mut prover, v.clone()).variable)
+                .collect::<Vec<_>>();
+            let dirs = directions
+                .iter()
+                .map(|d| allocate(&mut prover, FieldElement::from(*d as u64)))
+                .collect::<Vec<_>>();
+
+            poseidon_record_membership_gadget(
+                &mut prover,
+                fields,
+                &p,
+                &dirs,
+                &root,
+                params_leaf,
+                params_tree,
+                sbox_type,
+            )
+            .unwrap();
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"RecordMembership");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars = commitments
+            .into_iter()
+            .map(|c| verifier.commit(c))
+            .collect::<Vec<_>>();
+        let mut next = || AllocatedQuantity {
+            variable: vars.remove(0),
+            assignment: None,
+        };
+        let fields = (0..record.len())
+            .map(|_| next().variable)
+            .collect::<Vec<_>>();
+        let p = (0..depth).map(|_| next().variable).collect::<Vec<_>>();
+        let dirs = (0..depth).map(|_| next()).collect::<Vec<_>>();
+
+        poseidon_record_membership_gadget(
+            &mut verifier,
+            fields,
+            &p,
+            &dirs,
+            &root,
+            params_leaf,
+            params_tree,
+            sbox_type,
+        )
+        .unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_poseidon_record_membership() {
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds_leaf, partial_rounds_tree) = (4, 4, 56, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds_leaf, partial_rounds_tree) = (4, 4, 56, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds_leaf, partial_rounds_tree) = (4, 4, 56, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds_leaf, partial_rounds_tree) = (4, 4, 56, 55);
+
+        // Records have 3 fields and are hashed with width 5, the tree uses width 3
+        let params_leaf = PoseidonParams::new(5, full_b, full_e, partial_rounds_leaf).unwrap();
+        let params_tree = PoseidonParams::new(3, full_b, full_e, partial_rounds_tree).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let records = (0..4)
+            .map(|_| (0..3).map(|_| FieldElement::random()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let leaves = records
+            .iter()
+            .map(|r| Poseidon_hash_n(r.clone(), &params_leaf, sbox_type).unwrap())
+            .collect::<Vec<_>>();
+
+        assert!(check_record_membership(
+            &records[2],
+            2,
+            leaves.clone(),
+            &params_leaf,
+            &params_tree,
+            sbox_type
+        ));
+
+        // A record with one field changed is not in the tree
+        let mut modified = records[2].clone();
+        modified[1] = FieldElement::random();
+        assert!(!check_record_membership(
+            &modified,
+            2,
+            leaves,
+            &params_leaf,
+            &params_tree,
+            sbox_type
+        ));
+    }
+
+    fn check_merkle_update(
+        old_leaf: &FieldElement,
+        new_leaf: &FieldElement,
+        path: &[FieldElement],
+        directions: &[bool],
+        old_root: &FieldElement,
+        new_root: &FieldElement,
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let depth = path.len();
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"MerkleUpdate");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let mut comms = vec![];
+            let mut commit = |v: FieldElement| {
+                let (c, var) = prover.commit(v.clone(), FieldElement::random());
+                comms.push(c);
+                AllocatedQuantity {
+                    variable: var,
+                    assignment: Some(v),
+                }
+            };
+            let old = commit(old_leaf.clone());
+            let new = commit(new_leaf.clone());
+            let p = path.iter().map(|n| commit(n.clone())).collect::<Vec<_>>();
+            let dirs = directions
+                .iter()
+                .map(|d| {
+                    commit(if *d {
+                        FieldElement::one()
+                    } else {
+                        FieldElement::zero()
+                    })
+                })
+                .collect::<Vec<_>>();
+            merkle_update_gadget(
+                &mut prover,
+                old.variable,
+                new.variable,
+                p,
+                &dirs,
+                old_root,
+                new_root,
+                params,
+                sbox_type,
+            )
+            .unwrap();
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"MerkleUpdate");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars = commitments
+            .into_iter()
+            .map(|c| verifier.commit(c))
+            .collect::<Vec<_>>();
+        let mut next = || AllocatedQuantity {
+            variable: vars.remove(0),
+            assignment: None,
+        };
+        let old = next().variable;
+        let new = next().variable;
+        let p = (0..depth).map(|_| next()).collect::<Vec<_>>();
+        let dirs = (0..depth).map(|_| next()).collect::<Vec<_>>();
+        merkle_update_gadget(
+            &mut verifier,
+            old,
+            new,
+            p,
+            &dirs,
+            old_root,
+            new_root,
+            params,
+            sbox_type,
+        )
+        .unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_merkle_update() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let mut leaves = (0..8).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let levels = build_tree(leaves.clone(), &params, sbox_type);
+        let old_root = levels.last().unwrap()[0].clone();
+        let (path, directions) = get_path(&levels, 6);
+
+        let old_leaf = leaves[6].clone();
+        let new_leaf = FieldElement::random();
+        leaves[6] = new_leaf.clone();
+        let new_root = build_tree(leaves, &params, sbox_type).last().unwrap()[0].clone();
+
+        assert!(check_merkle_update(
+            &old_leaf,
+            &new_leaf,
+            &path,
+            &directions,
+            &old_root,
+            &new_root,
+            &params,
+            sbox_type
+        ));
+
+        // Siblings that do not reproduce the old root
+        let mut wrong_path = path.clone();
+        wrong_path[1] = FieldElement::random();
+        let wrong_new_root =
+            poseidon_merkle_root(&new_leaf, &wrong_path, &directions, &params, sbox_type).unwrap();
+        assert!(!check_merkle_update(
+            &old_leaf,
+            &new_leaf,
+            &wrong_path,
+            &directions,
+            &old_root,
+            &wrong_new_root,
+            &params,
+            sbox_type
+        ));
+    }
+
+    fn check_reveal_leaf(
+        leaf: &FieldElement,
+        public_value: &FieldElement,
+        path: &[FieldElement],
+        directions: &[bool],
+        root: &FieldElement,
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 1024).into();
+        let H: G1Vector = get_generators("H", 1024).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let depth = path.len();
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"RevealLeaf");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let mut comms = vec![];
+            let mut commit = |v: FieldElement| {
+                let (c, var) = prover.commit(v.clone(), FieldElement::random());
+                comms.push(c);
+                AllocatedQuantity {
+                    variable: var,
+                    assignment: Some(v),
+                }
+            };
+            let l = commit(leaf.clone()).variable;
+            let p = path
+                .iter()
+                .map(|n| commit(n.clone()).variable)
+                .collect::<Vec<_>>();
+            let dirs = directions
+                .iter()
+                .map(|d| {
+                    commit(if *d {
+                        FieldElement::one()
+                    } else {
+                        FieldElement::zero()
+                    })
+                })
+                .collect::<Vec<_>>();
+            poseidon_reveal_leaf_gadget(
+                &mut prover,
+                l,
+                public_value.clone(),
+                &p,
+                &dirs,
+                root,
+                params,
+                sbox_type,
+            )
+            .unwrap();
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"RevealLeaf");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars = commitments
+            .into_iter()
+            .map(|c| verifier.commit(c))
+            .collect::<Vec<_>>();
+        let mut next = || AllocatedQuantity {
+            variable: vars.remove(0),
+            assignment: None,
+        };
+        let l = next().variable;
+        let p = (0..depth).map(|_| next().variable).collect::<Vec<_>>();
+        let dirs = (0..depth).map(|_| next()).collect::<Vec<_>>();
+        poseidon_reveal_leaf_gadget(
+            &mut verifier,
+            l,
+            public_value.clone(),
+            &p,
+            &dirs,
+            root,
+            params,
+            sbox_type,
+        )
+        .unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_poseidon_reveal_leaf() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let leaves = (0..8).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let levels = build_tree(leaves.clone(), &params, sbox_type);
+        let root = levels.last().unwrap()[0].clone();
+        let (path, directions) = get_path(&levels, 3);
+
+        assert!(check_reveal_leaf(
+            &leaves[3],
+            &leaves[3],
+            &path,
+            &directions,
+            &root,
+            &params,
+            sbox_type
+        ));
+        // Valid path but the revealed value is not the leaf
+        assert!(!check_reveal_leaf(
+            &leaves[3],
+            &leaves[4],
+            &path,
+            &directions,
+            &root,
+            &params,
+            sbox_type
+        ));
+    }
+
+    fn check_committed_leaf_membership(
+        value: &FieldElement,
+        randomness: &FieldElement,
+        index: usize,
+        leaves: Vec<FieldElement>,
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let levels = build_tree(leaves, params, sbox_type);
+        let root = levels.last().unwrap()[0].clone();
+        let (path, directions) = get_path(&levels, index);
+        let depth = path.len();
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"CommittedLeafMembership");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocate = |prover: &mut Prover, v: FieldElement| {
+                let (com, var) = prover.commit(v.clone(), FieldElement::random());
+                comms.push(com);
+                AllocatedQuantity {
+                    variable: var,
+                    assignment: Some(v),
+                }
+            };
+            let v = allocate(&mut prover, value.clone());
+            let r = allocate(&mut prover, randomness.clone());
+            let p = path
+                .iter()
+                .map(|n| allocate(&mut prover, n.clone()).variable)
+                .collect::<Vec<_>>();
+            let dirs = directions
+                .iter()
+                .map(|d| allocate(&mut prover, FieldElement::from(*d as u64)))
+                .collect::<Vec<_>>();
+
+            committed_leaf_membership_gadget(
+                &mut prover,
+                v,
+                r,
+                &p,
+                &dirs,
+                &root,
+                params,
+                sbox_type,
+            )
+            .unwrap();
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"CommittedLeafMembership");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars = commitments
+            .into_iter()
+            .map(|c| verifier.commit(c))
+            .collect::<Vec<_>>();
+        let mut next = || AllocatedQuantity {
+            variable: vars.remove(0),
+            assignment: None,
+        };
+        let v = next();
+        let r = next();
+        let p = (0..depth).map(|_| next().variable).collect::<Vec<_>>();
+        let dirs = (0..depth).map(|_| next()).collect::<Vec<_>>();
+
+        committed_leaf_membership_gadget(&mut verifier, v, r, &p, &dirs, &root, params, sbox_type)
+            .unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_committed_leaf_membership() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let value = FieldElement::random();
+        let randomness = FieldElement::random();
+        let mut leaves = (0..8).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        leaves[3] = poseidon_commit(value.clone(), randomness.clone(), &params, sbox_type).unwrap();
+
+        assert!(check_committed_leaf_membership(
+            &value,
+            &randomness,
+            3,
+            leaves.clone(),
+            &params,
+            sbox_type
+        ));
+
+        // Same value with different randomness gives a different leaf
+        assert!(!check_committed_leaf_membership(
+            &value,
+            &FieldElement::random(),
+            3,
+            leaves,
+            &params,
+            sbox_type
+        ));
+    }
+
+    fn check_indexed_membership(
+        leaf: &FieldElement,
+        index: u64,
+        path: &[FieldElement],
+        root: &FieldElement,
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 1024).into();
+        let H: G1Vector = get_generators("H", 1024).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let depth = path.len();
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"IndexedMembership");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let (com_leaf, var_leaf) = prover.commit(leaf.clone(), FieldElement::random());
+            let idx = FieldElement::from(index);
+            let (com_idx, var_idx) = prover.commit(idx.clone(), FieldElement::random());
+            let mut comms = vec![com_leaf, com_idx];
+            let mut p = vec![];
+            for n in path {
+                let (com, var) = prover.commit(n.clone(), FieldElement::random());
+                comms.push(com);
+                p.push(var);
+            }
+            poseidon_indexed_membership_gadget(
+                &mut prover,
+                var_leaf,
+                AllocatedQuantity {
+                    variable: var_idx,
+                    assignment: Some(idx),
+                },
+                depth,
+                &p,
+                root,
+                params,
+                sbox_type,
+            )
+            .unwrap();
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"IndexedMembership");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars = commitments
+            .into_iter()
+            .map(|c| verifier.commit(c))
+            .collect::<Vec<_>>();
+        let var_leaf = vars.remove(0);
+        let var_idx = vars.remove(0);
+        poseidon_indexed_membership_gadget(
+            &mut verifier,
+            var_leaf,
+            AllocatedQuantity {
+                variable: var_idx,
+                assignment: None,
+            },
+            depth,
+            &vars,
+            root,
+            params,
+            sbox_type,
+        )
+        .unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_poseidon_indexed_membership() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let leaves = (0..8).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let levels = build_tree(leaves.clone(), &params, sbox_type);
+        let root = levels.last().unwrap()[0].clone();
+
+        for &i in [0usize, 5, 7].iter() {
+            let (path, _) = get_path(&levels, i);
+            assert!(check_indexed_membership(
+                &leaves[i], i as u64, &path, &root, &params, sbox_type
+            ));
+        }
+
+        let (path, _) = get_path(&levels, 5);
+        // Wrong index for the path
+        assert!(!check_indexed_membership(
+            &leaves[5], 4, &path, &root, &params, sbox_type
+        ));
+        // Out of range index whose low bits are the correct index
+        assert!(!check_indexed_membership(
+            &leaves[5],
+            5 + 8,
+            &path,
+            &root,
+            &params,
+            sbox_type
+        ));
+
+        // Path not matching the depth
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let mut prover_transcript = Transcript::new(b"IndexedMembership");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        let (_, var_leaf) = prover.commit(leaves[5].clone(), FieldElement::random());
+        let (_, var_idx) = prover.commit(FieldElement::from(5u64), FieldElement::random());
+        assert!(poseidon_indexed_membership_gadget(
+            &mut prover,
+            var_leaf,
+            AllocatedQuantity {
+                variable: var_idx,
+                assignment: Some(FieldElement::from(5u64)),
+            },
+            4,
+            &[var_leaf, var_leaf, var_leaf],
+            &root,
+            &params,
+            sbox_type,
+        )
+        .is_err());
+    }
+
+    /// Returns whether the proof verified and the number of multipliers
+    fn check_merkle_multiproof(
+        tree: &PoseidonMerkleTree,
+        indices: &[u64],
+        leaves: &[FieldElement],
+    ) -> (bool, usize) {
+        let G: G1Vector = get_generators("G", 4096).into();
+        let H: G1Vector = get_generators("H", 4096).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let root = tree.root();
+        let multiproof = tree.multiproof_for(indices).unwrap();
+
+        let (proof, commitments, num_multipliers) = {
+            let mut prover_transcript = Transcript::new(b"MerkleMultiproof");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let mut comms = vec![];
+            let mut var_leaves = vec![];
+            for leaf in leaves {
+                let (c, v) = prover.commit(leaf.clone(), FieldElement::random());
+                comms.push(c);
+                var_leaves.push(v);
+            }
+            let mut var_siblings = vec![];
+            for level_siblings in &multiproof.siblings {
+                let mut vars = vec![];
+                for s in level_siblings {
+                    let (c, v) = prover.commit(s.clone(), FieldElement::random());
+                    comms.push(c);
+                    vars.push(v);
+                }
+                var_siblings.push(vars);
+            }
+            poseidon_merkle_multiproof_gadget(
+                &mut prover,
+                var_leaves,
+                &multiproof.indices,
+                &var_siblings,
+                &root,
+                tree.params,
+                tree.sbox,
+            )
+            .unwrap();
+            let num_multipliers = prover.num_multipliers();
+            (prover.prove(&G, &H).unwrap(), comms, num_multipliers)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"MerkleMultiproof");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut commitments = commitments.into_iter();
+        let var_leaves = (0..leaves.len())
+            .map(|_| verifier.commit(commitments.next().unwrap()))
+            .collect::<Vec<_>>();
+        let var_siblings = multiproof
+            .siblings
+            .iter()
+            .map(|level_siblings| {
+                level_siblings
+                    .iter()
+                    .map(|_| verifier.commit(commitments.next().unwrap()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        poseidon_merkle_multiproof_gadget(
+            &mut verifier,
+            var_leaves,
+            &multiproof.indices,
+            &var_siblings,
+            &root,
+            tree.params,
+            tree.sbox,
+        )
+        .unwrap();
+        (
+            verifier.verify(&proof, &g, &h, &G, &H).is_ok(),
+            num_multipliers,
+        )
+    }
+
+    #[test]
+    fn test_poseidon_merkle_multiproof() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+        let depth = 3;
+
+        let mut tree = PoseidonMerkleTree::new_retaining(depth, &params, sbox_type).unwrap();
+        let leaves = (0..8).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        for l in &leaves {
+            tree.append(l.clone()).unwrap();
+        }
+        let root = tree.root();
+
+        // Leaves 2 and 3 share their parent, only the nodes at positions 0 of level 1 and 1 of
+        // level 2 are needed and 3 hashes instead of 6 for 2 separate proofs
+        let multiproof = tree.multiproof_for(&[3, 2]).unwrap();
+        assert_eq!(multiproof.indices, vec![2, 3]);
+        assert_eq!(multiproof.siblings[0].len(), 0);
+        assert_eq!(multiproof.siblings[1].len(), 1);
+        assert_eq!(multiproof.siblings[2].len(), 1);
+        assert_eq!(multiproof.num_hashes(), 3);
+        let proven = vec![leaves[2].clone(), leaves[3].clone()];
+        assert_eq!(multiproof.root(&proven, &params, sbox_type).unwrap(), root);
+        let paths = multiproof.paths(&proven, &params, sbox_type).unwrap();
+        assert_eq!(paths[0], tree.proof_for(2).unwrap());
+        assert_eq!(paths[1], tree.proof_for(3).unwrap());
+        assert_ne!(
+            multiproof
+                .root(&[leaves[2].clone(), leaves[4].clone()], &params, sbox_type)
+                .unwrap(),
+            root
+        );
+        assert!(multiproof.root(&leaves[..1], &params, sbox_type).is_err());
+
+        // Leaves in different halves share only the root
+        let multiproof = tree.multiproof_for(&[1, 6]).unwrap();
+        assert_eq!(multiproof.num_hashes(), 5);
+        assert_eq!(
+            multiproof
+                .root(&[leaves[1].clone(), leaves[6].clone()], &params, sbox_type)
+                .unwrap(),
+            root
+        );
+        assert!(tree.multiproof_for(&[]).is_err());
+        assert!(tree.multiproof_for(&[8]).is_err());
+
+        // In the circuit, each node is hashed once so the cost is that of `num_hashes` hashes
+        let per_hash_cost = {
+            let mut prover_transcript = Transcript::new(b"MerkleMultiproof");
+            let g = G1::from_msg_hash("g".as_bytes());
+            let h = G1::from_msg_hash("h".as_bytes());
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let (_, l) = prover.commit(FieldElement::random(), FieldElement::random());
+            let (_, r) = prover.commit(FieldElement::random(), FieldElement::random());
+            PoseidonHash2 {
+                params: &params,
+                sbox: sbox_type,
+            }
+            .hash_constraints(&mut prover, l.into(), r.into())
+            .unwrap();
+            prover.num_multipliers()
+        };
+        let (verified, single) = check_merkle_multiproof(&tree, &[2], &leaves[2..3]);
+        assert!(verified);
+        assert_eq!(single, depth * per_hash_cost);
+        let (verified, both) = check_merkle_multiproof(&tree, &[2, 3], &leaves[2..4]);
+        assert!(verified);
+        assert_eq!(
+            both,
+            tree.multiproof_for(&[2, 3]).unwrap().num_hashes() * per_hash_cost
+        );
+        assert!(both < 2 * single);
+        let (verified, apart) =
+            check_merkle_multiproof(&tree, &[1, 6], &[leaves[1].clone(), leaves[6].clone()]);
+        assert!(verified);
+        assert_eq!(
+            apart,
+            tree.multiproof_for(&[1, 6]).unwrap().num_hashes() * per_hash_cost
+        );
+
+        // Wrong leaf
+        let (verified, _) =
+            check_merkle_multiproof(&tree, &[2, 3], &[leaves[2].clone(), leaves[4].clone()]);
+        assert!(!verified);
+    }
+
+    #[test]
+    fn test_poseidon_merkle_tree_proof_for() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox = SboxType::Quint;
+        let depth = 7;
+
+        let mut tree = PoseidonMerkleTree::new_retaining(depth, &params, &sbox).unwrap();
+        let mut frontier_only = PoseidonMerkleTree::new(depth, &params, &sbox).unwrap();
+        let leaves = (0..100).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        for (i, l) in leaves.iter().enumerate() {
+            assert_eq!(tree.append(l.clone()).unwrap(), i as u64);
+            frontier_only.append(l.clone()).unwrap();
+        }
+        let root = tree.root();
+        assert_eq!(frontier_only.root(), root);
+
+        // Same as the root of the whole tree with the remaining leaves empty
+        let mut padded = leaves.clone();
+        padded.resize(1 << depth, FieldElement::zero());
+        assert_eq!(build_tree(padded, &params, &sbox).last().unwrap()[0], root);
+
+        for &i in [0, 50, 99].iter() {
+            let proof = tree.proof_for(i as u64).unwrap();
+            assert_eq!(proof.siblings.len(), depth);
+            assert_eq!(proof.root(&leaves[i], &params, &sbox).unwrap(), root);
+        }
+        assert!(tree.proof_for(100).is_err());
+        assert!(frontier_only.proof_for(0).is_err());
+
+        // Filling the tree
+        let mut tree = PoseidonMerkleTree::new_retaining(2, &params, &sbox).unwrap();
+        let leaves = (0..4).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        for l in &leaves {
+            tree.append(l.clone()).unwrap();
+        }
+        assert!(tree.append(FieldElement::random()).is_err());
+        let root = build_tree(leaves.clone(), &params, &sbox).last().unwrap()[0].clone();
+        assert_eq!(tree.root(), root);
+        for i in 0..4 {
+            let proof = tree.proof_for(i as u64).unwrap();
+            assert_eq!(proof.root(&leaves[i], &params, &sbox).unwrap(), root);
+        }
+    }
+
+    fn check_mixed_arity_membership(
+        tree: &MixedArityMerkleTree,
+        leaf: &FieldElement,
+        proof: &MixedArityMerkleProof,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let root = tree.root();
+        let directions = proof.directions().unwrap();
+
+        let (snark_proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"MixedArityMembership");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocate = |prover: &mut Prover, v: FieldElement| {
+                let (com, var) = prover.commit(v.clone(), FieldElement::random());
+                comms.push(com);
+                AllocatedQuantity {
+                    variable: var,
+                    assignment: Some(v),
+                }
+            };
+            let l = allocate(&mut prover, leaf.clone()).variable;
+            let p = proof
+                .siblings
+                .iter()
+                .map(|s| {
+                    s.iter()
+                        .map(|n| allocate(&mut prover, n.clone()).variable)
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            let dirs = directions
+                .iter()
+                .map(|d| {
+                    d.iter()
+                        .map(|b| allocate(&mut prover, FieldElement::from(*b as u64)))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+
+            poseidon_mixed_arity_membership_gadget(
+                &mut prover,
+                l,
+                &p,
+                &dirs,
+                &tree.arities,
+                &root,
+                &tree.params,
+                tree.sbox,
+            )
+            .unwrap();
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"MixedArityMembership");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars = commitments
+            .into_iter()
+            .map(|c| verifier.commit(c))
+            .collect::<Vec<_>>();
+        let mut next = || AllocatedQuantity {
+            variable: vars.remove(0),
+            assignment: None,
+        };
+        let l = next().variable;
+        let p = tree
+            .arities
+            .iter()
+            .map(|a| (1..*a).map(|_| next().variable).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let dirs = tree
+            .arities
+            .iter()
+            .map(|a| (0..a.trailing_zeros()).map(|_| next()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        poseidon_mixed_arity_membership_gadget(
+            &mut verifier,
+            l,
+            &p,
+            &dirs,
+            &tree.arities,
+            &root,
+            &tree.params,
+            tree.sbox,
+        )
+        .unwrap();
+        verifier.verify(&snark_proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_structure_commitment() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox = SboxType::Quint;
+        let depth = 4;
+
+        let leaves = (0..5).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let mut tree = PoseidonMerkleTree::new(depth, &params, &sbox).unwrap();
+        let mut padded = PoseidonMerkleTree::new(depth, &params, &sbox).unwrap();
+        for l in &leaves {
+            tree.append(l.clone()).unwrap();
+            padded.append(l.clone()).unwrap();
+        }
+        assert_eq!(tree.structure_commitment(), padded.structure_commitment());
+        assert_ne!(tree.structure_commitment(), tree.root());
+
+        // Appending an empty leaf keeps the root but not the structure commitment
+        padded.append(FieldElement::zero()).unwrap();
+        assert_eq!(tree.root(), padded.root());
+        assert_ne!(tree.num_leaves(), padded.num_leaves());
+        assert_ne!(tree.structure_commitment(), padded.structure_commitment());
+
+        // Different depth
+        let mut deeper = PoseidonMerkleTree::new(depth + 1, &params, &sbox).unwrap();
+        for l in &leaves {
+            deeper.append(l.clone()).unwrap();
+        }
+        assert_ne!(tree.structure_commitment(), deeper.structure_commitment());
+    }
+
+    #[test]
+    fn test_mixed_arity_membership() {
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds_3, partial_rounds_5) = (4, 4, 55, 56);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds_3, partial_rounds_5) = (4, 4, 55, 56);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds_3, partial_rounds_5) = (4, 4, 55, 56);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds_3, partial_rounds_5) = (4, 4, 55, 56);
+
+        let params_3 = PoseidonParams::new(3, full_b, full_e, partial_rounds_3).unwrap();
+        let params_5 = PoseidonParams::new(5, full_b, full_e, partial_rounds_5).unwrap();
+        let sbox = &SboxType::Quint;
+        let params = MixedArityParams {
+            params_2: Some(&params_3),
+            params_4: Some(&params_5),
+            params_8: None,
+        };
+
+        // Arity 4 at the bottom and 2 at the top, the last 2 leaves are empty
+        let leaves = (0..6).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let tree =
+            MixedArityMerkleTree::new(leaves.clone(), vec![4, 2], params.clone(), sbox).unwrap();
+        assert_eq!(tree.num_leaves(), 8);
+        let left = Poseidon_hash_4(leaves[..4].to_vec(), &params_5, sbox).unwrap();
+        let right = Poseidon_hash_4(
+            vec![
+                leaves[4].clone(),
+                leaves[5].clone(),
+                FieldElement::zero(),
+                FieldElement::zero(),
+            ],
+            &params_5,
+            sbox,
+        )
+        .unwrap();
+        let root = Poseidon_hash_2(vec![left.clone(), right], &params_3, sbox).unwrap();
+        assert_eq!(tree.root(), root);
+
+        let proof = tree.proof_for(5).unwrap();
+        assert_eq!(proof.positions, vec![1, 1]);
+        assert_eq!(proof.siblings[1], vec![left]);
+        assert_eq!(
+            proof.directions().unwrap(),
+            vec![vec![true, false], vec![true]]
+        );
+        for i in 0..8 {
+            let proof = tree.proof_for(i).unwrap();
+            let leaf = tree.levels[0][i].clone();
+            assert_eq!(proof.root(&leaf, &params, sbox).unwrap(), root);
+        }
+        assert!(tree.proof_for(8).is_err());
+
+        for &i in [0, 2, 5, 7].iter() {
+            let proof = tree.proof_for(i).unwrap();
+            assert!(check_mixed_arity_membership(
+                &tree,
+                &tree.levels[0][i],
+                &proof
+            ));
+        }
+        // Leaf at a different position than the path's fails
+        let proof = tree.proof_for(2).unwrap();
+        assert!(!check_mixed_arity_membership(&tree, &leaves[3], &proof));
+        assert!(!check_mixed_arity_membership(
+            &tree,
+            &FieldElement::random(),
+            &proof
+        ));
+
+        // Unsupported arity, missing params and too many leaves
+        assert!(
+            MixedArityMerkleTree::new(leaves.clone(), vec![3, 2], params.clone(), sbox).is_err()
+        );
+        assert!(MixedArityMerkleTree::new(leaves.clone(), vec![8], params.clone(), sbox).is_err());
+        assert!(MixedArityMerkleTree::new(leaves, vec![2, 2], params, sbox).is_err());
+    }
+}
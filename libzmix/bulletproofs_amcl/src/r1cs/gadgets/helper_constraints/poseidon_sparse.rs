@@ -0,0 +1,656 @@
+use crate::errors::{BulletproofError, BulletproofErrorKind, R1CSError};
+use crate::r1cs::{ConstraintSystem, LinearCombination};
+use amcl_wrapper::field_elem::FieldElement;
+use std::ops::Deref;
+
+use super::poseidon::{PoseidonParams, SboxType};
+
+/* Partial rounds with sparse linear layers. Described in appendix B of the Poseidon
+paper https://eprint.iacr.org/2019/458. The description below uses the column convention, i.e. the
+linear layer is `x -> A.x` where `A` is the transpose of `PoseidonParams::MDS_matrix` and the
+Sbox of partial rounds is applied to index `s = width - 1`.
+
+1. Round constants: A partial round is `x -> A.S(x + c)`. Moving the constant of round r+1 before
+the linear layer of round r gives the vector `e = A^-1.c_{r+1}` added after the Sbox of round r.
+The entries of `e` other than `s` commute with the Sbox so they are added to the constant of round
+r and only `e[s]` remains as a scalar added after the Sbox. Going backwards from the last partial
+round, only the first partial round keeps a full constant vector, every round adds a scalar after
+its Sbox and the last partial round adds nothing.
+
+2. Linear layers: A dense matrix `M` is factored as `M = M''.M'` where `M'` is identity on index
+`s` and equal to `M` on the other indices and `M''` is sparse, i.e. identity except row and
+column `s`. `M'` commutes with the partial Sbox so it is moved into the linear layer of the
+previous round which is factored again. The `M'` left after the first partial round is merged
+into the linear layer of the last full round before the partial rounds and applied on the first
+partial round's constant.
+
+Each sparse matrix has `3*width - 2` non-zero entries rather than `width^2` so the linear layer of
+a partial round needs 25 rather than 81 multiplications for width 9 and the linear combinations
+in the circuit are correspondingly smaller. The number of multiplication gates is unchanged.
+*/
+
+// Sparse matrix which is identity except row and column `s = width - 1`.
+#[derive(Clone, Debug)]
+pub struct SparseMatrix {
+    // Entry at row s and column s
+    pub diag: FieldElement,
+    // Row s without the column s
+    pub row: Vec<FieldElement>,
+    // Column s without the row s
+    pub col: Vec<FieldElement>,
+}
+
+impl SparseMatrix {
+    /// Multiply the matrix with the column vector `x`
+    fn apply(&self, x: &[FieldElement]) -> Vec<FieldElement> {
+        let s = x.len() - 1;
+        let mut out = Vec::with_capacity(x.len());
+        for i in 0..s {
+            out.push(&self.col[i] * &x[s] + &x[i]);
+        }
+        let mut last = &self.diag * &x[s];
+        for i in 0..s {
+            last += &self.row[i] * &x[i];
+        }
+        out.push(last);
+        out
+    }
+
+    /// Multiply the matrix with the column vector `x` in the circuit
+    fn apply_lc(&self, x: &[LinearCombination]) -> Vec<LinearCombination> {
+        let s = x.len() - 1;
+        let mut out = Vec::with_capacity(x.len());
+        for i in 0..s {
+            out.push((&self.col[i] * x[s].clone() + x[i].clone()).simplify());
+        }
+        let mut last = &self.diag * x[s].clone();
+        for i in 0..s {
+            last += &self.row[i] * x[i].clone();
+        }
+        out.push(last.simplify());
+        out
+    }
+}
+
+/// Precomputed constants and matrices for evaluating the partial rounds with sparse linear
+/// layers. Created from the `PoseidonParams` it is used with.
+#[derive(Clone, Debug)]
+pub struct OptimizedPoseidonParams {
+    // Linear layer of the last full round before the partial rounds in column convention
+    pub last_full_round_matrix: Vec<Vec<FieldElement>>,
+    // Constants added to the state before the first partial round
+    pub first_partial_round_keys: Vec<FieldElement>,
+    // Constants added to index `s` after the Sbox in each partial round
+    pub partial_round_keys: Vec<FieldElement>,
+    // Linear layer of each partial round
+    pub sparse_matrices: Vec<SparseMatrix>,
+}
+
+impl OptimizedPoseidonParams {
+    pub fn new(params: &PoseidonParams) -> Result<Self, BulletproofError> {
+        let (sparse_matrices, last_full_round_matrix) = Self::compute_sparse_matrices(params)?;
+
+        let width = params.width;
+        let s = width - 1;
+        let a = transpose(&params.MDS_matrix);
+        // Invertible as checked by `compute_sparse_matrices`
+        let a_inv = invert_matrix(&a).unwrap();
+
+        let round_keys = params.round_keys_by_round();
+        let partial_keys = &round_keys
+            [params.full_rounds_beginning..params.full_rounds_beginning + params.partial_rounds];
+        let num_partial = partial_keys.len();
+
+        // Move round constants backwards, `acc` is the constant vector of the current round.
+        let mut partial_round_keys = vec![FieldElement::zero(); num_partial];
+        let mut acc = vec![FieldElement::zero(); width];
+        for r in (0..num_partial).rev() {
+            for i in 0..width {
+                acc[i] += &partial_keys[r][i];
+            }
+            if r == 0 {
+                break;
+            }
+            let mut e = mat_vec_mul(&a_inv, &acc);
+            partial_round_keys[r - 1] = e[s].clone();
+            e[s] = FieldElement::zero();
+            acc = e;
+        }
+
+        // The last full round matrix is `M'.A` where `M'` is left over from the first partial
+        // round
+        let left_over = if num_partial > 0 {
+            mat_mul(&last_full_round_matrix, &a_inv)
+        } else {
+            identity(width)
+        };
+        let first_partial_round_keys = mat_vec_mul(&left_over, &acc);
+
+        Ok(Self {
+            last_full_round_matrix,
+            first_partial_round_keys,
+            partial_round_keys,
+            sparse_matrices,
+        })
+    }
+
+    /// Factor the linear layers of the partial rounds into sparse matrices, one per partial
+    /// round, and return them with the dense matrix replacing the linear layer of the last full
+    /// round before the partial rounds. Errors if the Sbox of partial rounds is not only at the
+    /// last position, there is no full round before the partial rounds or the MDS matrix or its
+    /// submatrices needed are not invertible.
+    pub fn compute_sparse_matrices(
+        params: &PoseidonParams,
+    ) -> Result<(Vec<SparseMatrix>, Vec<Vec<FieldElement>>), BulletproofError> {
+        if params.width < 2 {
+            return Err(BulletproofErrorKind::R1CSError {
+                msg: format!("Width should be at least 2 but is {}", params.width),
+            }
+            .into());
+        }
+        // The factorization needs the Sbox of partial rounds on the last position only
+        if !params.has_default_partial_sbox_positions() {
+            return Err(BulletproofErrorKind::R1CSError {
+                msg: format!(
+                    "Sparse partial rounds need the Sbox only at position {} but found {:?}",
+                    params.width - 1,
+                    params.partial_sbox_positions
+                ),
+            }
+            .into());
+        }
+        // The matrix left over after the partial rounds is merged into the previous full round
+        if params.full_rounds_beginning == 0 {
+            return Err(BulletproofErrorKind::R1CSError {
+                msg: String::from("Need at least 1 full round before the partial rounds"),
+            }
+            .into());
+        }
+
+        let a = transpose(&params.MDS_matrix);
+        let singular = || {
+            BulletproofError::from(BulletproofErrorKind::R1CSError {
+                msg: String::from("MDS matrix or its submatrix is not invertible"),
+            })
+        };
+        invert_matrix(&a).ok_or_else(singular)?;
+
+        // Factor the linear layers backwards, `cur` is the linear layer of the current round.
+        let mut sparse_matrices = vec![];
+        let mut cur = a.clone();
+        for _ in 0..params.partial_rounds {
+            let (sparse, dense) = factor(&cur).ok_or_else(singular)?;
+            sparse_matrices.push(sparse);
+            cur = mat_mul(&dense, &a);
+        }
+        sparse_matrices.reverse();
+        Ok((sparse_matrices, cur))
+    }
+}
+
+/// `OptimizedPoseidonParams` restricted to width 9
+#[derive(Clone, Debug)]
+pub struct PoseidonW9SparseParams(OptimizedPoseidonParams);
+
+impl PoseidonW9SparseParams {
+    pub fn new(params: &PoseidonParams) -> Result<Self, BulletproofError> {
+        if params.width != 9 {
+            return Err(BulletproofErrorKind::UnacceptableWidthForPoseidon {
+                width: params.width,
+                acceptable: vec![9],
+            }
+            .into());
+        }
+        Ok(Self(OptimizedPoseidonParams::new(params)?))
+    }
+}
+
+impl Deref for PoseidonW9SparseParams {
+    type Target = OptimizedPoseidonParams;
+
+    fn deref(&self) -> &OptimizedPoseidonParams {
+        &self.0
+    }
+}
+
+/// Same as `Poseidon_permutation_sparse` but only for width 9
+pub fn Poseidon_permutation_w9_sparse(
+    input: &[FieldElement],
+    params: &PoseidonParams,
+    sparse_params: &PoseidonW9SparseParams,
+    sbox: &SboxType,
+) -> Vec<FieldElement> {
+    assert_eq!(params.width, 9);
+    Poseidon_permutation_sparse(input, params, sparse_params, sbox)
+}
+
+/// Same as `Poseidon_permutation` but evaluating the partial rounds with sparse linear layers.
+/// `sparse_params` must be created from `params`.
+pub fn Poseidon_permutation_sparse(
+    input: &[FieldElement],
+    params: &PoseidonParams,
+    sparse_params: &OptimizedPoseidonParams,
+    sbox: &SboxType,
+) -> Vec<FieldElement> {
+    let width = params.width;
+    assert_eq!(input.len(), width);
+
+    let full_rounds_beginning = params.full_rounds_beginning;
+    let partial_rounds_end = full_rounds_beginning + params.partial_rounds;
+    let mds = transpose(&params.MDS_matrix);
+
+    let mut state = input.to_vec();
+    let full_round =
+        |state: &mut Vec<FieldElement>, keys: &[FieldElement], matrix: &Vec<Vec<FieldElement>>| {
+            for i in 0..width {
+                state[i] += &keys[i];
+                state[i] = sbox.apply_sbox(&state[i]);
+            }
+            *state = mat_vec_mul(matrix, state);
+        };
+
+    let round_keys = params.round_keys_by_round();
+    for (r, keys) in round_keys[..full_rounds_beginning].iter().enumerate() {
+        if r == full_rounds_beginning - 1 {
+            full_round(&mut state, keys, &sparse_params.last_full_round_matrix);
+        } else {
+            full_round(&mut state, keys, &mds);
+        }
+    }
+
+    for i in 0..width {
+        state[i] += &sparse_params.first_partial_round_keys[i];
+    }
+    for (key, matrix) in sparse_params
+        .partial_round_keys
+        .iter()
+        .zip(sparse_params.sparse_matrices.iter())
+    {
+        state[width - 1] = sbox.apply_sbox(&state[width - 1]);
+        state[width - 1] += key;
+        state = matrix.apply(&state);
+    }
+
+    for keys in &round_keys[partial_rounds_end..partial_rounds_end + params.full_rounds_end] {
+        full_round(&mut state, keys, &mds);
+    }
+    state
+}
+
+/// Same as `Poseidon_permutation_sparse_constraints` but only for width 9
+pub fn Poseidon_permutation_w9_sparse_constraints<CS: ConstraintSystem>(
+    cs: &mut CS,
+    input: Vec<LinearCombination>,
+    params: &PoseidonParams,
+    sparse_params: &PoseidonW9SparseParams,
+    sbox_type: &SboxType,
+) -> Result<Vec<LinearCombination>, R1CSError> {
+    assert_eq!(params.width, 9);
+    Poseidon_permutation_sparse_constraints(cs, input, params, sparse_params, sbox_type)
+}
+
+/// Enforces constraints of `Poseidon_permutation_sparse`. Uses the same number of
+/// multiplication gates as `Poseidon_permutation_constraints` but smaller linear combinations.
+pub fn Poseidon_permutation_sparse_constraints<CS: ConstraintSystem>(
+    cs: &mut CS,
+    input: Vec<LinearCombination>,
+    params: &PoseidonParams,
+    sparse_params: &OptimizedPoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<Vec<LinearCombination>, R1CSError> {
+    let width = params.width;
+    assert_eq!(input.len(), width);
+
+    let full_rounds_beginning = params.full_rounds_beginning;
+    let partial_rounds_end = full_rounds_beginning + params.partial_rounds;
+    let mds = transpose(&params.MDS_matrix);
+
+    fn full_round<CS: ConstraintSystem>(
+        cs: &mut CS,
+        state: Vec<LinearCombination>,
+        keys: &[FieldElement],
+        matrix: &[Vec<FieldElement>],
+        sbox_type: &SboxType,
+    ) -> Result<Vec<LinearCombination>, R1CSError> {
+        let mut sbox_outputs = vec![];
+        for (s, k) in state.into_iter().zip(keys.iter()) {
+            sbox_outputs.push(sbox_type.synthesize_sbox(cs, s, k.clone())?);
+        }
+        Ok(matrix
+            .iter()
+            .map(|row| {
+                let mut lc = LinearCombination::default();
+                for (m, v) in row.iter().zip(sbox_outputs.iter()) {
+                    lc += m * LinearCombination::from(*v);
+                }
+                lc
+            })
+            .collect())
+    }
+
+    let mut state = input;
+    let round_keys = params.round_keys_by_round();
+    for (r, keys) in round_keys[..full_rounds_beginning].iter().enumerate() {
+        let matrix = if r == full_rounds_beginning - 1 {
+            &sparse_params.last_full_round_matrix
+        } else {
+            &mds
+        };
+        state = full_round(cs, state, keys, matrix, sbox_type)?;
+    }
+
+    for i in 0..width {
+        state[i] =
+            (state[i].clone() + sparse_params.first_partial_round_keys[i].clone()).simplify();
+    }
+    for (key, matrix) in sparse_params
+        .partial_round_keys
+        .iter()
+        .zip(sparse_params.sparse_matrices.iter())
+    {
+        let sbox_out =
+            sbox_type.synthesize_sbox(cs, state[width - 1].clone(), FieldElement::zero())?;
+        state[width - 1] = sbox_out + key.clone();
+        state = matrix.apply_lc(&state);
+    }
+
+    for keys in &round_keys[partial_rounds_end..partial_rounds_end + params.full_rounds_end] {
+        state = full_round(cs, state, keys, &mds, sbox_type)?;
+    }
+    Ok(state)
+}
+
+/// Factor `m` as `m = sparse.dense` where `dense` is identity on index `s = width - 1` and same
+/// as `m` elsewhere. Returns None if `m` without row and column `s` is not invertible.
+fn factor(m: &[Vec<FieldElement>]) -> Option<(SparseMatrix, Vec<Vec<FieldElement>>)> {
+    let width = m.len();
+    let s = width - 1;
+    let m_hat = m[..s]
+        .iter()
+        .map(|row| row[..s].to_vec())
+        .collect::<Vec<_>>();
+    let m_hat_inv = invert_matrix(&m_hat)?;
+
+    // row = m[s][..s].m_hat^-1
+    let row = (0..s)
+        .map(|j| {
+            let mut sum = FieldElement::zero();
+            for k in 0..s {
+                sum += &m[s][k] * &m_hat_inv[k][j];
+            }
+            sum
+        })
+        .collect::<Vec<_>>();
+    let col = (0..s).map(|i| m[i][s].clone()).collect::<Vec<_>>();
+
+    let mut dense = identity(width);
+    for i in 0..s {
+        dense[i][..s].clone_from_slice(&m_hat[i]);
+    }
+
+    Some((
+        SparseMatrix {
+            diag: m[s][s].clone(),
+            row,
+            col,
+        },
+        dense,
+    ))
+}
+
+fn identity(n: usize) -> Vec<Vec<FieldElement>> {
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| {
+                    if i == j {
+                        FieldElement::one()
+                    } else {
+                        FieldElement::zero()
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn transpose(m: &[Vec<FieldElement>]) -> Vec<Vec<FieldElement>> {
+    (0..m[0].len())
+        .map(|j| m.iter().map(|row| row[j].clone()).collect())
+        .collect()
+}
+
+fn mat_mul(a: &[Vec<FieldElement>], b: &[Vec<FieldElement>]) -> Vec<Vec<FieldElement>> {
+    a.iter()
+        .map(|row| {
+            (0..b[0].len())
+                .map(|j| {
+                    let mut sum = FieldElement::zero();
+                    for k in 0..row.len() {
+                        sum += &row[k] * &b[k][j];
+                    }
+                    sum
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn mat_vec_mul(m: &[Vec<FieldElement>], v: &[FieldElement]) -> Vec<FieldElement> {
+    m.iter()
+        .map(|row| {
+            let mut sum = FieldElement::zero();
+            for (a, b) in row.iter().zip(v.iter()) {
+                sum += a * b;
+            }
+            sum
+        })
+        .collect()
+}
+
+/// Invert a square matrix using Gauss-Jordan elimination. Returns None if the matrix is singular.
+fn invert_matrix(m: &[Vec<FieldElement>]) -> Option<Vec<Vec<FieldElement>>> {
+    let n = m.len();
+    let mut a = m.to_vec();
+    let mut inv = identity(n);
+    for col in 0..n {
+        let pivot = (col..n).find(|&r| !a[r][col].is_zero())?;
+        a.swap(pivot, col);
+        inv.swap(pivot, col);
+
+        let p_inv = a[col][col].inverse();
+        for c in 0..n {
+            a[col][c] = &a[col][c] * &p_inv;
+            inv[col][c] = &inv[col][c] * &p_inv;
+        }
+        for r in 0..n {
+            if r == col || a[r][col].is_zero() {
+                continue;
+            }
+            let factor = a[r][col].clone();
+            for c in 0..n {
+                let t = &factor * &a[col][c];
+                a[r][c] -= t;
+                let t = &factor * &inv[col][c];
+                inv[r][c] -= t;
+            }
+        }
+    }
+    Some(inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::gadgets::helper_constraints::poseidon::{
+        Poseidon_permutation, Poseidon_permutation_constraints,
+    };
+    use crate::r1cs::Prover;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::G1;
+    use merlin::Transcript;
+
+    // Width 9 constants are not defined for secp256k1
+    #[cfg(any(feature = "bls381", feature = "bn254", feature = "ed25519"))]
+    #[test]
+    fn test_sparse_w9_matches_dense() {
+        let width = 9;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 57);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 57);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 57);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sparse_params = PoseidonW9SparseParams::new(&params).unwrap();
+        assert_eq!(sparse_params.sparse_matrices.len(), partial_rounds);
+        // No constant after the Sbox of the last partial round
+        assert!(sparse_params.partial_round_keys[partial_rounds - 1].is_zero());
+
+        // Sbox on more than the last position of partial rounds cannot be made sparse
+        let mut two_sboxes = params.clone();
+        two_sboxes.partial_sbox_positions = vec![0, width - 1];
+        assert!(PoseidonW9SparseParams::new(&two_sboxes).is_err());
+
+        let mut sboxes = vec![SboxType::Cube, SboxType::Quint];
+        #[cfg(feature = "inverse_sbox")]
+        sboxes.push(SboxType::Inverse);
+        for sbox_type in &sboxes {
+            for _ in 0..100 {
+                let input = (0..width)
+                    .map(|_| FieldElement::random())
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    Poseidon_permutation_w9_sparse(&input, &params, &sparse_params, sbox_type),
+                    Poseidon_permutation(&input, &params, sbox_type)
+                );
+            }
+        }
+
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let sbox_type = &SboxType::Quint;
+        let input = (0..width)
+            .map(|_| FieldElement::random())
+            .collect::<Vec<_>>();
+        let expected = Poseidon_permutation(&input, &params, sbox_type);
+
+        let mut dense_transcript = Transcript::new(b"PoseidonW9Dense");
+        let mut dense_prover = Prover::new(&g, &h, &mut dense_transcript);
+        let mut sparse_transcript = Transcript::new(b"PoseidonW9Sparse");
+        let mut sparse_prover = Prover::new(&g, &h, &mut sparse_transcript);
+        let mut dense_vars = vec![];
+        let mut sparse_vars = vec![];
+        for i in &input {
+            dense_vars.push(
+                dense_prover
+                    .commit(i.clone(), FieldElement::random())
+                    .1
+                    .into(),
+            );
+            sparse_vars.push(
+                sparse_prover
+                    .commit(i.clone(), FieldElement::random())
+                    .1
+                    .into(),
+            );
+        }
+        Poseidon_permutation_constraints(&mut dense_prover, dense_vars, &params, sbox_type)
+            .unwrap();
+        let out = Poseidon_permutation_w9_sparse_constraints(
+            &mut sparse_prover,
+            sparse_vars,
+            &params,
+            &sparse_params,
+            sbox_type,
+        )
+        .unwrap();
+        for i in 0..width {
+            assert_eq!(sparse_prover.evaluate_lc(&out[i]).unwrap(), expected[i]);
+        }
+        assert_eq!(
+            sparse_prover.num_multipliers(),
+            dense_prover.num_multipliers()
+        );
+    }
+
+    #[test]
+    fn test_optimized_w5_matches_dense() {
+        let width = 5;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let (sparse_matrices, dense) =
+            OptimizedPoseidonParams::compute_sparse_matrices(&params).unwrap();
+        assert_eq!(sparse_matrices.len(), partial_rounds);
+        for m in &sparse_matrices {
+            assert_eq!(m.row.len(), width - 1);
+            assert_eq!(m.col.len(), width - 1);
+        }
+        assert_eq!(dense.len(), width);
+        assert!(dense.iter().all(|row| row.len() == width));
+
+        let optimized = OptimizedPoseidonParams::new(&params).unwrap();
+        assert_eq!(optimized.last_full_round_matrix, dense);
+
+        let sbox_type = &SboxType::Quint;
+        for _ in 0..100 {
+            let input = (0..width)
+                .map(|_| FieldElement::random())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                Poseidon_permutation_sparse(&input, &params, &optimized, sbox_type),
+                Poseidon_permutation(&input, &params, sbox_type)
+            );
+        }
+
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let input = (0..width)
+            .map(|_| FieldElement::random())
+            .collect::<Vec<_>>();
+        let expected = Poseidon_permutation(&input, &params, sbox_type);
+        let mut transcript = Transcript::new(b"PoseidonW5Sparse");
+        let mut prover = Prover::new(&g, &h, &mut transcript);
+        let vars = input
+            .iter()
+            .map(|i| prover.commit(i.clone(), FieldElement::random()).1.into())
+            .collect();
+        let out = Poseidon_permutation_sparse_constraints(
+            &mut prover,
+            vars,
+            &params,
+            &optimized,
+            sbox_type,
+        )
+        .unwrap();
+        for i in 0..width {
+            assert_eq!(prover.evaluate_lc(&out[i]).unwrap(), expected[i]);
+        }
+
+        // No full round before the partial rounds
+        let no_full = PoseidonParams::new(width, 0, 8, partial_rounds).unwrap();
+        assert!(OptimizedPoseidonParams::compute_sparse_matrices(&no_full).is_err());
+    }
+
+    #[test]
+    fn test_sparse_w9_rejects_other_widths() {
+        let params = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        assert!(PoseidonW9SparseParams::new(&params).is_err());
+    }
+}
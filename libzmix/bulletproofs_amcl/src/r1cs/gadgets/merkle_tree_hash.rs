@@ -1,8 +1,9 @@
 use crate::errors::{BulletproofError, BulletproofErrorKind, R1CSError, R1CSErrorKind};
-use crate::r1cs::gadgets::helper_constraints::mimc::mimc;
+use crate::r1cs::gadgets::helper_constraints::mimc::{enforce_mimc_2_inputs, mimc};
 use crate::r1cs::gadgets::helper_constraints::poseidon::{
-    PoseidonParams, Poseidon_hash_2, Poseidon_hash_4, Poseidon_hash_4_constraints, Poseidon_hash_8,
-    Poseidon_hash_8_constraints, SboxType,
+    PoseidonParams, Poseidon_hash_2, Poseidon_hash_2_constraints, Poseidon_hash_4,
+    Poseidon_hash_4_constraints, Poseidon_hash_8, Poseidon_hash_8_constraints, SboxType,
+    CAP_CONST_W_3,
 };
 use crate::r1cs::{ConstraintSystem, LinearCombination, Prover, Variable, Verifier};
 use amcl_wrapper::commitment::commit_to_field_element;
@@ -60,6 +61,25 @@ pub trait Arity8MerkleTreeHash {
     fn hash(&self, inputs: Vec<FieldElement>) -> Result<FieldElement, BulletproofError>;
 }
 
+/// Compression function of 2 nodes into their parent for binary merkle trees, with the
+/// constraints for it, so that the tree gadgets need not be tied to a hash function. Unlike
+/// `Arity4MerkleTreeHashConstraints`, there is no setup, any constant the hash needs is used as
+/// a constant in the constraints and not committed. Implemented by `PoseidonHash2` and `Mimc2`.
+pub trait TwoToOneHash {
+    fn hash(
+        &self,
+        left: &FieldElement,
+        right: &FieldElement,
+    ) -> Result<FieldElement, BulletproofError>;
+
+    fn hash_constraints<CS: ConstraintSystem>(
+        &self,
+        cs: &mut CS,
+        left: LinearCombination,
+        right: LinearCombination,
+    ) -> Result<LinearCombination, R1CSError>;
+}
+
 pub trait Arity4MerkleTreeHashConstraints {
     /// This is for hash function specific setup. Like Poseidon needs a variable allocated for
     /// capacity constant. Done for the prover and must be done once and only once
@@ -108,6 +128,7 @@ pub struct Mimc2<'a> {
     pub constants: &'a [FieldElement],
 }
 
+/// Poseidon 2:1 hash with capacity constant `CAP_CONST_W_3`, needs params of width 3
 pub struct PoseidonHash2<'a> {
     pub params: &'a PoseidonParams,
     pub sbox: &'a SboxType,
@@ -136,13 +157,73 @@ impl<'a> Arity2MerkleTreeHash for Mimc2<'a> {
     }
 }
 
+impl<'a> TwoToOneHash for Mimc2<'a> {
+    fn hash(
+        &self,
+        left: &FieldElement,
+        right: &FieldElement,
+    ) -> Result<FieldElement, BulletproofError> {
+        Ok(mimc(left, right, self.constants))
+    }
+
+    fn hash_constraints<CS: ConstraintSystem>(
+        &self,
+        cs: &mut CS,
+        left: LinearCombination,
+        right: LinearCombination,
+    ) -> Result<LinearCombination, R1CSError> {
+        enforce_mimc_2_inputs::<CS>(cs, left, right, self.constants)
+    }
+}
+
 impl<'a> Arity2MerkleTreeHash for PoseidonHash2<'a> {
     fn hash(&self, inputs: Vec<FieldElement>) -> Result<FieldElement, BulletproofError> {
         Self::is_num_inputs_correct(&inputs)?;
+        if self.params.width != 3 {
+            return Err(BulletproofErrorKind::IncorrectWidthForPoseidon {
+                width: self.params.width,
+                expected: 3,
+            }
+            .into());
+        }
         Poseidon_hash_2(inputs, &self.params, &self.sbox)
     }
 }
 
+impl<'a> TwoToOneHash for PoseidonHash2<'a> {
+    fn hash(
+        &self,
+        left: &FieldElement,
+        right: &FieldElement,
+    ) -> Result<FieldElement, BulletproofError> {
+        Arity2MerkleTreeHash::hash(self, vec![left.clone(), right.clone()])
+    }
+
+    fn hash_constraints<CS: ConstraintSystem>(
+        &self,
+        cs: &mut CS,
+        left: LinearCombination,
+        right: LinearCombination,
+    ) -> Result<LinearCombination, R1CSError> {
+        if self.params.width != 3 {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Poseidon 2:1 hash needs params of width 3 but found {}",
+                    self.params.width
+                ),
+            }
+            .into());
+        }
+        Poseidon_hash_2_constraints::<CS>(
+            cs,
+            vec![left, right],
+            FieldElement::from(CAP_CONST_W_3).into(),
+            self.params,
+            self.sbox,
+        )
+    }
+}
+
 impl<'a> Arity4MerkleTreeHash for PoseidonHash4<'a> {
     fn hash(&self, inputs: Vec<FieldElement>) -> Result<FieldElement, BulletproofError> {
         Self::is_num_inputs_correct(&inputs)?;
@@ -6,11 +6,23 @@ extern crate rand;
 pub mod bound_check;
 // TODO: Uncomment
 //pub mod hamming_distance;
+pub mod hash_at_index;
 pub mod helper_constraints;
 pub mod mimc;
 pub mod non_zero;
+pub mod poseidon_bits;
+pub mod poseidon_collision;
+pub mod poseidon_commitment;
 pub mod poseidon_constants;
+pub mod poseidon_derivation;
 pub mod poseidon_hash;
+pub mod poseidon_hash_chain;
+pub mod poseidon_linear_relation;
+pub mod poseidon_mac;
+pub mod poseidon_nullifier;
+pub mod poseidon_poly_eval;
+pub mod poseidon_shuffle;
+pub mod ring_membership;
 // TODO: Uncomment
 //pub mod randomizer;
 pub mod merkle_tree_hash;
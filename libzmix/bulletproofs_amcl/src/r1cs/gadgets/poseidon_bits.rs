@@ -0,0 +1,923 @@
+use super::helper_constraints::bit::canonical_bits_gadget;
+use super::helper_constraints::poseidon::{
+    PoseidonParams, Poseidon_hash_2, Poseidon_hash_2_constraints, Poseidon_hash_n,
+    Poseidon_hash_n_constraints, SboxType, CAP_CONST_W_3,
+};
+use super::helper_constraints::positive_no::positive_no_gadget;
+use super::helper_constraints::{allocate_lc_as_quantity, constrain_lc_with_scalar};
+use crate::errors::{BulletproofError, R1CSError, R1CSErrorKind};
+use crate::r1cs::linear_combination::AllocatedQuantity;
+use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
+use amcl_wrapper::constants::CurveOrder;
+use amcl_wrapper::field_elem::FieldElement;
+
+/// Returns the least significant bit of `Poseidon_hash_2(a, b)`, true when the bit is 1.
+pub fn poseidon_hash_parity(
+    a: FieldElement,
+    b: FieldElement,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<bool, BulletproofError> {
+    let hash = Poseidon_hash_2(vec![a, b], params, sbox)?;
+    Ok(hash.is_odd())
+}
+
+/// Enforces constraints for `poseidon_hash_parity` and returns the variable for the least
+/// significant bit of the hash. The whole hash is decomposed into bits with a unique
+/// decomposition so that the prover cannot choose the parity.
+pub fn poseidon_hash_parity_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    a: Variable,
+    b: Variable,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<Variable, R1CSError> {
+    let hash = Poseidon_hash_2_constraints::<CS>(
+        cs,
+        vec![a.into(), b.into()],
+        FieldElement::from(CAP_CONST_W_3).into(),
+        params,
+        sbox_type,
+    )?;
+    let bits = canonical_bits_gadget(cs, hash)?;
+    Ok(bits[0].variable)
+}
+
+/// Split `Poseidon_hash_n(inputs)` into `num_chunks` challenges of `bits_per_chunk` bits each.
+/// Chunk `i` is made of bits `i*bits_per_chunk` to `(i+1)*bits_per_chunk - 1` of the hash, least
+/// significant bit first. The chunks can use at most as many bits as the curve order.
+pub fn poseidon_challenge_split(
+    inputs: Vec<FieldElement>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+    num_chunks: usize,
+    bits_per_chunk: usize,
+) -> Result<Vec<FieldElement>, BulletproofError> {
+    check_chunk_sizes(num_chunks, bits_per_chunk)?;
+    let hash = Poseidon_hash_n(inputs, params, sbox)?;
+    Ok(split_into_chunks(&hash, num_chunks, bits_per_chunk))
+}
+
+/// Enforces constraints for `poseidon_challenge_split` and returns the chunks. The hash is
+/// decomposed into bits with a unique decomposition so the prover cannot choose the chunks.
+pub fn poseidon_challenge_split_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<LinearCombination>,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    num_chunks: usize,
+    bits_per_chunk: usize,
+) -> Result<Vec<LinearCombination>, R1CSError> {
+    check_chunk_sizes(num_chunks, bits_per_chunk)?;
+    let hash = Poseidon_hash_n_constraints::<CS>(cs, inputs, params, sbox_type)?;
+    let bits = canonical_bits_gadget(cs, hash)?;
+    Ok(bits_to_chunks(&bits, bits_per_chunk)
+        .into_iter()
+        .take(num_chunks)
+        .collect())
+}
+
+/// Enforces constraints for `Poseidon_hash_n(inputs)` and returns the hash split into `num_limbs`
+/// limbs of `limb_bits` bits each, least significant limb first, i.e. hash is
+/// `Sum(limbs[i] * 2^(i*limb_bits))` without any carry. The limbs are made of the bits of the
+/// hash's unique decomposition so each limb is range checked and the prover cannot choose a
+/// different decomposition, e.g. of `hash + order`. The limbs should have at least as many bits
+/// as the curve order, limbs beyond those bits are 0.
+pub fn poseidon_hash_to_limbs_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<LinearCombination>,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    limb_bits: usize,
+    num_limbs: usize,
+) -> Result<Vec<LinearCombination>, R1CSError> {
+    check_limb_sizes(num_limbs, limb_bits)?;
+    let hash = Poseidon_hash_n_constraints::<CS>(cs, inputs, params, sbox_type)?;
+    let bits = canonical_bits_gadget(cs, hash)?;
+    let mut limbs = bits_to_chunks(&bits, limb_bits);
+    limbs.resize(num_limbs, LinearCombination::default());
+    Ok(limbs)
+}
+
+/// Pack `Poseidon_hash_n(inputs)` into `num_packs` elements of `bits_per_pack` bits each, least
+/// significant pack first, for exposing the hash to a circuit over a smaller field. The packs
+/// should have at least as many bits as the curve order.
+pub fn poseidon_hash_packed_output(
+    inputs: Vec<FieldElement>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+    num_packs: usize,
+    bits_per_pack: usize,
+) -> Result<Vec<FieldElement>, BulletproofError> {
+    check_limb_sizes(num_packs, bits_per_pack)?;
+    let hash = Poseidon_hash_n(inputs, params, sbox)?;
+    Ok(split_into_chunks(&hash, num_packs, bits_per_pack))
+}
+
+/// Constraints for proving that `Poseidon_hash_n(inputs)` is the concatenation of the bits of
+/// public `packed_outputs` of `bits_per_pack` bits each, least significant pack first, as
+/// computed by `poseidon_hash_packed_output`. Used when the hash is a public input of a circuit
+/// over a different field. Each pack is range checked by `poseidon_hash_to_limbs_gadget`.
+pub fn poseidon_hash_packed_output_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<LinearCombination>,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    packed_outputs: Vec<FieldElement>,
+    bits_per_pack: usize,
+) -> Result<(), R1CSError> {
+    let packs = poseidon_hash_to_limbs_gadget(
+        cs,
+        inputs,
+        params,
+        sbox_type,
+        bits_per_pack,
+        packed_outputs.len(),
+    )?;
+    for (lc, p) in packs.into_iter().zip(packed_outputs.iter()) {
+        constrain_lc_with_scalar::<CS>(cs, lc, p);
+    }
+    Ok(())
+}
+
+/// Returns the number of set bits of `Poseidon_hash_n(inputs)`.
+pub fn poseidon_hash_hamming_weight(
+    inputs: Vec<FieldElement>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<usize, BulletproofError> {
+    let hash = Poseidon_hash_n(inputs, params, sbox)?;
+    Ok((0..CurveOrder.nbits())
+        .filter(|i| hash.shift_right(*i).is_odd())
+        .count())
+}
+
+/// Enforces that `Poseidon_hash_n(inputs)` has at least `min_weight` set bits. The hash is
+/// decomposed into bits with a unique decomposition and `popcount - min_weight` is range checked
+/// to be non-negative, i.e. in `[0, 2^n)` where `n` is the number of bits needed for the count.
+pub fn poseidon_hash_min_hamming_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<LinearCombination>,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    min_weight: usize,
+) -> Result<(), R1CSError> {
+    let num_bits = CurveOrder.nbits();
+    if min_weight > num_bits {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Minimum weight {} is more than the {} bits of the hash",
+                min_weight, num_bits
+            ),
+        }
+        .into());
+    }
+    let hash = Poseidon_hash_n_constraints::<CS>(cs, inputs, params, sbox_type)?;
+    let bits = canonical_bits_gadget(cs, hash)?;
+    let popcount: LinearCombination = bits
+        .iter()
+        .map(|b| (b.variable, FieldElement::one()))
+        .collect::<Vec<_>>()
+        .iter()
+        .collect();
+    let diff = allocate_lc_as_quantity(cs, popcount - FieldElement::from(min_weight as u64))?;
+    // Bits needed to represent any count from 0 to `num_bits`
+    let count_bits = 64 - (num_bits as u64).leading_zeros() as usize;
+    positive_no_gadget(cs, diff, count_bits)
+}
+
+/// Returns true if `Poseidon_hash_n(inputs)` has its top `leading_zeros` bits as 0, i.e. the
+/// hash is less than `2^(n - leading_zeros)` where `n` is the number of bits of the curve order.
+/// Used for proof of work where `inputs` contain a nonce.
+pub fn poseidon_pow_check(
+    inputs: Vec<FieldElement>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+    leading_zeros: usize,
+) -> Result<bool, BulletproofError> {
+    check_leading_zeros(leading_zeros)?;
+    let hash = Poseidon_hash_n(inputs, params, sbox)?;
+    Ok(hash
+        .shift_right(CurveOrder.nbits() - leading_zeros)
+        .is_zero())
+}
+
+/// Enforces constraints for `poseidon_pow_check`. The hash is decomposed into bits with a unique
+/// decomposition and its top `leading_zeros` bits are constrained to be 0.
+pub fn poseidon_pow_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<LinearCombination>,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    leading_zeros: usize,
+) -> Result<(), R1CSError> {
+    check_leading_zeros(leading_zeros)?;
+    let hash = Poseidon_hash_n_constraints::<CS>(cs, inputs, params, sbox_type)?;
+    let bits = canonical_bits_gadget(cs, hash)?;
+    for bit in &bits[bits.len() - leading_zeros..] {
+        cs.constrain(bit.variable.into());
+    }
+    Ok(())
+}
+
+/// Enforces that `Poseidon_hash_n` of `flags` followed by `other_inputs` is `image` and that
+/// `flags` is a bit-packed flag set of at most `max_flag_bits` flags, i.e. its bits beyond
+/// `max_flag_bits` are 0, by range checking it to `[0, 2^max_flag_bits)`.
+pub fn poseidon_hash_with_flags_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    flags: Variable,
+    other_inputs: Vec<Variable>,
+    max_flag_bits: usize,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    image: &FieldElement,
+) -> Result<(), R1CSError> {
+    // Range check needs fewer bits than the field to be meaningful
+    if max_flag_bits == 0 || max_flag_bits >= CurveOrder.nbits() {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Flags should have 1 to {} bits but found {}",
+                CurveOrder.nbits() - 1,
+                max_flag_bits
+            ),
+        }
+        .into());
+    }
+    let flags_quantity = allocate_lc_as_quantity(cs, flags.into())?;
+    positive_no_gadget(cs, flags_quantity, max_flag_bits)?;
+
+    let mut inputs: Vec<LinearCombination> = vec![flags.into()];
+    inputs.extend(other_inputs.into_iter().map(|v| v.into()));
+    let hash = Poseidon_hash_n_constraints::<CS>(cs, inputs, params, sbox_type)?;
+    constrain_lc_with_scalar::<CS>(cs, hash, image);
+    Ok(())
+}
+
+/// Group the bits, least significant first, into chunks of `chunk_size` bits and return each
+/// chunk as the linear combination `Sum(bits[i] * 2^i)` of its bits. The last chunk can be smaller.
+fn bits_to_chunks(bits: &[AllocatedQuantity], chunk_size: usize) -> Vec<LinearCombination> {
+    bits.chunks(chunk_size)
+        .map(|chunk| {
+            let mut exp_2 = FieldElement::one();
+            let mut terms = vec![];
+            for bit in chunk {
+                terms.push((bit.variable, exp_2.clone()));
+                exp_2 = &exp_2 + &exp_2;
+            }
+            terms.iter().collect()
+        })
+        .collect()
+}
+
+/// Split `elem` into `num_chunks` chunks of `bits_per_chunk` bits each, least significant first.
+fn split_into_chunks(
+    elem: &FieldElement,
+    num_chunks: usize,
+    bits_per_chunk: usize,
+) -> Vec<FieldElement> {
+    let mut chunks = vec![];
+    for i in 0..num_chunks {
+        let shifted = elem.shift_right(i * bits_per_chunk);
+        let mut chunk = FieldElement::zero();
+        let mut exp_2 = FieldElement::one();
+        for j in 0..bits_per_chunk {
+            if shifted.shift_right(j).is_odd() {
+                chunk += &exp_2;
+            }
+            exp_2 = &exp_2 + &exp_2;
+        }
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Limbs, unlike chunks, need to hold all bits of the hash
+fn check_limb_sizes(num_limbs: usize, limb_bits: usize) -> Result<(), R1CSError> {
+    if limb_bits == 0 || num_limbs * limb_bits < CurveOrder.nbits() {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "{} limbs of {} bits cannot hold the hash of {} bits",
+                num_limbs,
+                limb_bits,
+                CurveOrder.nbits()
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn check_chunk_sizes(num_chunks: usize, bits_per_chunk: usize) -> Result<(), R1CSError> {
+    if bits_per_chunk == 0 || num_chunks * bits_per_chunk > CurveOrder.nbits() {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Cannot split hash into {} chunks of {} bits, hash has {} bits",
+                num_chunks,
+                bits_per_chunk,
+                CurveOrder.nbits()
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn check_leading_zeros(leading_zeros: usize) -> Result<(), R1CSError> {
+    if leading_zeros > CurveOrder.nbits() {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Cannot require {} leading zeros, hash has {} bits",
+                leading_zeros,
+                CurveOrder.nbits()
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::gadgets::helper_constraints::constrain_lc_with_scalar;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    #[test]
+    fn test_poseidon_hash_parity() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        for _ in 0..4 {
+            let a = FieldElement::random();
+            let b = FieldElement::random();
+            let parity = poseidon_hash_parity(a.clone(), b.clone(), &params, sbox_type).unwrap();
+            let expected = if parity {
+                FieldElement::one()
+            } else {
+                FieldElement::zero()
+            };
+
+            let (proof, commitments) = {
+                let mut prover_transcript = Transcript::new(b"HashParity");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let (com_a, var_a) = prover.commit(a, FieldElement::random());
+                let (com_b, var_b) = prover.commit(b, FieldElement::random());
+                let bit =
+                    poseidon_hash_parity_gadget(&mut prover, var_a, var_b, &params, sbox_type)
+                        .unwrap();
+                assert_eq!(prover.evaluate_lc(&bit.into()).unwrap(), expected);
+                constrain_lc_with_scalar(&mut prover, bit.into(), &expected);
+                (prover.prove(&G, &H).unwrap(), vec![com_a, com_b])
+            };
+
+            // Verifier checks the parity matches and fails when its flipped
+            for (p, result) in vec![
+                (expected.clone(), true),
+                (FieldElement::one() - expected, false),
+            ] {
+                let mut verifier_transcript = Transcript::new(b"HashParity");
+                let mut verifier = Verifier::new(&mut verifier_transcript);
+                let var_a = verifier.commit(commitments[0].clone());
+                let var_b = verifier.commit(commitments[1].clone());
+                let bit =
+                    poseidon_hash_parity_gadget(&mut verifier, var_a, var_b, &params, sbox_type)
+                        .unwrap();
+                constrain_lc_with_scalar(&mut verifier, bit.into(), &p);
+                assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_poseidon_challenge_split() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let inputs = vec![FieldElement::random(), FieldElement::random()];
+        let hash = Poseidon_hash_n(inputs.clone(), &params, sbox_type).unwrap();
+        let (num_chunks, bits_per_chunk) = (5, 50);
+        let chunks = poseidon_challenge_split(
+            inputs.clone(),
+            &params,
+            sbox_type,
+            num_chunks,
+            bits_per_chunk,
+        )
+        .unwrap();
+
+        // The chunks reassemble into the hash along with the bits not covered by the chunks
+        let mut reassembled = FieldElement::zero();
+        let mut shift = FieldElement::one();
+        for c in &chunks {
+            reassembled += c * &shift;
+            shift = &shift * &FieldElement::from(1u64 << bits_per_chunk);
+        }
+        reassembled += hash.shift_right(num_chunks * bits_per_chunk) * &shift;
+        assert_eq!(reassembled, hash);
+
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let mut prover_transcript = Transcript::new(b"ChallengeSplit");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        let vars = inputs
+            .iter()
+            .map(|i| prover.commit(i.clone(), FieldElement::random()).1.into())
+            .collect::<Vec<_>>();
+        let chunk_lcs = poseidon_challenge_split_gadget(
+            &mut prover,
+            vars,
+            &params,
+            sbox_type,
+            num_chunks,
+            bits_per_chunk,
+        )
+        .unwrap();
+        assert_eq!(chunk_lcs.len(), num_chunks);
+        for (lc, c) in chunk_lcs.iter().zip(chunks.iter()) {
+            assert_eq!(&prover.evaluate_lc(lc).unwrap(), c);
+        }
+
+        // Too many bits
+        assert!(poseidon_challenge_split(inputs.clone(), &params, sbox_type, 4, 64).is_err());
+        assert!(poseidon_challenge_split(inputs, &params, sbox_type, 2, 0).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_hash_to_limbs() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let inputs = vec![FieldElement::random(), FieldElement::random()];
+        let hash = Poseidon_hash_n(inputs.clone(), &params, sbox_type).unwrap();
+        let limb_bits = 64;
+        let num_limbs = (CurveOrder.nbits() + limb_bits - 1) / limb_bits;
+        let base = FieldElement::from(2u64).pow(&FieldElement::from(limb_bits as u64));
+
+        // Too few bits
+        let mut prover_transcript = Transcript::new(b"HashToLimbs");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        assert!(poseidon_hash_to_limbs_gadget(
+            &mut prover,
+            vec![FieldElement::one().into(), FieldElement::one().into()],
+            &params,
+            sbox_type,
+            limb_bits,
+            num_limbs - 1
+        )
+        .is_err());
+
+        let (proof, commitments, limbs) = {
+            let mut prover_transcript = Transcript::new(b"HashToLimbs");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let (comms, vars): (Vec<_>, Vec<_>) = inputs
+                .iter()
+                .map(|i| prover.commit(i.clone(), FieldElement::random()))
+                .unzip();
+            let limb_lcs = poseidon_hash_to_limbs_gadget(
+                &mut prover,
+                vars.into_iter().map(|v| v.into()).collect(),
+                &params,
+                sbox_type,
+                limb_bits,
+                num_limbs,
+            )
+            .unwrap();
+            assert_eq!(limb_lcs.len(), num_limbs);
+            let limbs = limb_lcs
+                .iter()
+                .map(|l| prover.evaluate_lc(l).unwrap())
+                .collect::<Vec<_>>();
+            for (lc, l) in limb_lcs.into_iter().zip(limbs.iter()) {
+                constrain_lc_with_scalar(&mut prover, lc, l);
+            }
+            (prover.prove(&G, &H).unwrap(), comms, limbs)
+        };
+
+        // Limbs reconstruct to the hash
+        let mut reconstructed = FieldElement::zero();
+        for l in limbs.iter().rev() {
+            reconstructed = &reconstructed * &base + l;
+        }
+        assert_eq!(reconstructed, hash);
+
+        // Limbs with the lowest one over the range reconstruct to the same hash but are rejected
+        let mut over_range = limbs.clone();
+        over_range[0] = &over_range[0] + &base;
+        over_range[1] = &over_range[1] - &FieldElement::one();
+
+        for (claimed, expected) in vec![(limbs, true), (over_range, false)] {
+            let mut verifier_transcript = Transcript::new(b"HashToLimbs");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let vars = commitments
+                .iter()
+                .map(|c| verifier.commit(c.clone()).into())
+                .collect();
+            let limb_lcs = poseidon_hash_to_limbs_gadget(
+                &mut verifier,
+                vars,
+                &params,
+                sbox_type,
+                limb_bits,
+                num_limbs,
+            )
+            .unwrap();
+            for (lc, l) in limb_lcs.into_iter().zip(claimed.iter()) {
+                constrain_lc_with_scalar(&mut verifier, lc, l);
+            }
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_packed_output() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        // Pack the hash into 2 elements of 128 bits
+        let bits_per_pack = 128;
+        let inputs = vec![FieldElement::random(), FieldElement::random()];
+        assert!(
+            poseidon_hash_packed_output(inputs.clone(), &params, sbox_type, 1, bits_per_pack)
+                .is_err()
+        );
+        let packs =
+            poseidon_hash_packed_output(inputs.clone(), &params, sbox_type, 2, bits_per_pack)
+                .unwrap();
+        let hash = Poseidon_hash_n(inputs.clone(), &params, sbox_type).unwrap();
+        let base = FieldElement::from(2u64).pow(&FieldElement::from(bits_per_pack as u64));
+        assert_eq!(&packs[1] * &base + &packs[0], hash);
+
+        // Packs reconstructing to the hash but with the lower one over 128 bits
+        let mut over_range = packs.clone();
+        over_range[0] = &over_range[0] + &base;
+        over_range[1] = &over_range[1] - &FieldElement::one();
+        let mut swapped = packs.clone();
+        swapped.swap(0, 1);
+
+        for (claimed, expected) in vec![(packs, true), (over_range, false), (swapped, false)] {
+            let (proof, commitments) = {
+                let mut prover_transcript = Transcript::new(b"HashPackedOutput");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let (comms, vars): (Vec<_>, Vec<_>) = inputs
+                    .iter()
+                    .map(|i| prover.commit(i.clone(), FieldElement::random()))
+                    .unzip();
+                poseidon_hash_packed_output_gadget(
+                    &mut prover,
+                    vars.into_iter().map(|v| v.into()).collect(),
+                    &params,
+                    sbox_type,
+                    claimed.clone(),
+                    bits_per_pack,
+                )
+                .unwrap();
+                (prover.prove(&G, &H).unwrap(), comms)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"HashPackedOutput");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let vars = commitments
+                .into_iter()
+                .map(|c| verifier.commit(c).into())
+                .collect();
+            poseidon_hash_packed_output_gadget(
+                &mut verifier,
+                vars,
+                &params,
+                sbox_type,
+                claimed,
+                bits_per_pack,
+            )
+            .unwrap();
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_min_hamming() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let inputs = vec![FieldElement::from(7u64), FieldElement::from(11u64)];
+        let weight = poseidon_hash_hamming_weight(inputs.clone(), &params, sbox_type).unwrap();
+        let hash = Poseidon_hash_n(inputs.clone(), &params, sbox_type).unwrap();
+        let mut expected_weight = 0;
+        for b in hash.to_bytes() {
+            expected_weight += b.count_ones() as usize;
+        }
+        assert_eq!(weight, expected_weight);
+
+        {
+            let mut prover_transcript = Transcript::new(b"HashMinHamming");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            assert!(poseidon_hash_min_hamming_gadget(
+                &mut prover,
+                inputs.iter().map(|i| i.clone().into()).collect(),
+                &params,
+                sbox_type,
+                CurveOrder.nbits() + 1,
+            )
+            .is_err());
+        }
+
+        // Threshold met exactly passes, one more than the weight fails
+        for (min_weight, expected) in vec![(weight / 2, true), (weight, true), (weight + 1, false)]
+        {
+            let (proof, commitments) = {
+                let mut prover_transcript = Transcript::new(b"HashMinHamming");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let (comms, vars): (Vec<_>, Vec<_>) = inputs
+                    .iter()
+                    .map(|i| prover.commit(i.clone(), FieldElement::random()))
+                    .unzip();
+                poseidon_hash_min_hamming_gadget(
+                    &mut prover,
+                    vars.into_iter().map(|v| v.into()).collect(),
+                    &params,
+                    sbox_type,
+                    min_weight,
+                )
+                .unwrap();
+                (prover.prove(&G, &H).unwrap(), comms)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"HashMinHamming");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let vars = commitments
+                .into_iter()
+                .map(|c| verifier.commit(c).into())
+                .collect();
+            poseidon_hash_min_hamming_gadget(&mut verifier, vars, &params, sbox_type, min_weight)
+                .unwrap();
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+
+    #[test]
+    fn test_poseidon_pow() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let leading_zeros = 4;
+        let data = FieldElement::random();
+        assert!(poseidon_pow_check(
+            vec![data.clone()],
+            &params,
+            sbox_type,
+            CurveOrder.nbits() + 1
+        )
+        .is_err());
+
+        // Search for a nonce satisfying the work and one not satisfying it
+        let mut good_nonce = None;
+        let mut bad_nonce = None;
+        for i in 0..1000u64 {
+            let nonce = FieldElement::from(i);
+            let inputs = vec![data.clone(), nonce.clone()];
+            if poseidon_pow_check(inputs, &params, sbox_type, leading_zeros).unwrap() {
+                good_nonce.get_or_insert(nonce);
+            } else {
+                bad_nonce.get_or_insert(nonce);
+            }
+            if good_nonce.is_some() && bad_nonce.is_some() {
+                break;
+            }
+        }
+
+        for (nonce, expected) in vec![(good_nonce.unwrap(), true), (bad_nonce.unwrap(), false)] {
+            let inputs = vec![data.clone(), nonce];
+            let (proof, commitments) = {
+                let mut prover_transcript = Transcript::new(b"HashPow");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let (comms, vars): (Vec<_>, Vec<_>) = inputs
+                    .iter()
+                    .map(|i| prover.commit(i.clone(), FieldElement::random()))
+                    .unzip();
+                poseidon_pow_gadget(
+                    &mut prover,
+                    vars.into_iter().map(|v| v.into()).collect(),
+                    &params,
+                    sbox_type,
+                    leading_zeros,
+                )
+                .unwrap();
+                (prover.prove(&G, &H).unwrap(), comms)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"HashPow");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let vars = commitments
+                .into_iter()
+                .map(|c| verifier.commit(c).into())
+                .collect();
+            poseidon_pow_gadget(&mut verifier, vars, &params, sbox_type, leading_zeros).unwrap();
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_with_flags() {
+        let width = 5;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let max_flag_bits = 8;
+        let others = vec![FieldElement::random(), FieldElement::random()];
+
+        {
+            let mut prover_transcript = Transcript::new(b"HashWithFlags");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let (_, flags) = prover.commit(FieldElement::one(), FieldElement::random());
+            for bits in vec![0, CurveOrder.nbits()] {
+                assert!(poseidon_hash_with_flags_gadget(
+                    &mut prover,
+                    flags,
+                    vec![],
+                    bits,
+                    &params,
+                    sbox_type,
+                    &FieldElement::one(),
+                )
+                .is_err());
+            }
+        }
+
+        // All 8 flags set fits, a flag at position 8 overflows
+        for (flags, expected) in vec![(0b1010_0101u64, true), (255, true), (256, false)] {
+            let mut inputs = vec![FieldElement::from(flags)];
+            inputs.extend(others.iter().cloned());
+            let image = Poseidon_hash_n(inputs.clone(), &params, sbox_type).unwrap();
+
+            let (proof, commitments) = {
+                let mut prover_transcript = Transcript::new(b"HashWithFlags");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let (comms, mut vars): (Vec<_>, Vec<_>) = inputs
+                    .iter()
+                    .map(|i| prover.commit(i.clone(), FieldElement::random()))
+                    .unzip();
+                let flags_var = vars.remove(0);
+                poseidon_hash_with_flags_gadget(
+                    &mut prover,
+                    flags_var,
+                    vars,
+                    max_flag_bits,
+                    &params,
+                    sbox_type,
+                    &image,
+                )
+                .unwrap();
+                (prover.prove(&G, &H).unwrap(), comms)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"HashWithFlags");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let mut vars = commitments
+                .into_iter()
+                .map(|c| verifier.commit(c))
+                .collect::<Vec<_>>();
+            let flags_var = vars.remove(0);
+            poseidon_hash_with_flags_gadget(
+                &mut verifier,
+                flags_var,
+                vars,
+                max_flag_bits,
+                &params,
+                sbox_type,
+                &image,
+            )
+            .unwrap();
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use super::helper_constraints::poseidon::{PoseidonParams, Poseidon_hash_n_constraints, SboxType};
+use crate::errors::{R1CSError, R1CSErrorKind};
+use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
+use amcl_wrapper::field_elem::FieldElement;
+
+/// Constraints for proving knowledge of 2 different inputs `a` and `b` with the same
+/// `Poseidon_hash_n`, i.e. a collision. Meant for security testing of reduced round params,
+/// with secure params no witness should exist.
+/// `a != b` is enforced by proving `Sum(inv_i * (a_i - b_i)) = 1` for some `inv_i` chosen by the
+/// prover. When `a == b`, the sum is 0 for any `inv_i`, else prover sets `inv_i` to the inverse of
+/// `a_i - b_i` for one `i` where they differ and 0 for others.
+pub fn poseidon_collision_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    a: Vec<Variable>,
+    b: Vec<Variable>,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    if a.len() != b.len() {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Inputs should be of same length but are {} and {}",
+                a.len(),
+                b.len()
+            ),
+        }
+        .into());
+    }
+
+    let diffs = a
+        .iter()
+        .zip(b.iter())
+        .map(|(x, y)| *x - *y)
+        .collect::<Vec<LinearCombination>>();
+
+    // Prover picks the inverse of the first non-zero difference
+    let mut found = false;
+    let mut sum = LinearCombination::default();
+    for d in &diffs {
+        let inv = cs.evaluate_lc(d).map(|v| {
+            if found || v.is_zero() {
+                FieldElement::zero()
+            } else {
+                found = true;
+                v.inverse()
+            }
+        });
+        let inv_var = cs.allocate(inv)?;
+        let (_, _, o) = cs.multiply(d.clone(), inv_var.into());
+        sum = sum + o;
+    }
+    cs.constrain(sum - FieldElement::one());
+
+    let hash_a = Poseidon_hash_n_constraints::<CS>(
+        cs,
+        a.into_iter().map(|v| v.into()).collect(),
+        params,
+        sbox_type,
+    )?;
+    let hash_b = Poseidon_hash_n_constraints::<CS>(
+        cs,
+        b.into_iter().map(|v| v.into()).collect(),
+        params,
+        sbox_type,
+    )?;
+    cs.constrain(hash_a - hash_b);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    fn check_collision(
+        a: Vec<FieldElement>,
+        b: Vec<FieldElement>,
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 512).into();
+        let H: G1Vector = get_generators("H", 512).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let n = a.len();
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"PoseidonCollision");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let mut comms = vec![];
+            let mut vars_a = vec![];
+            let mut vars_b = vec![];
+            for (x, y) in a.into_iter().zip(b.into_iter()) {
+                let (c, v) = prover.commit(x, FieldElement::random());
+                comms.push(c);
+                vars_a.push(v);
+                let (c, v) = prover.commit(y, FieldElement::random());
+                comms.push(c);
+                vars_b.push(v);
+            }
+            poseidon_collision_gadget(&mut prover, vars_a, vars_b, params, sbox_type).unwrap();
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"PoseidonCollision");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars_a = vec![];
+        let mut vars_b = vec![];
+        for i in 0..n {
+            vars_a.push(verifier.commit(commitments[2 * i].clone()));
+            vars_b.push(verifier.commit(commitments[2 * i + 1].clone()));
+        }
+        poseidon_collision_gadget(&mut verifier, vars_a, vars_b, params, sbox_type).unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_poseidon_collision() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        // Trivial witness with same inputs has the same hash but is rejected
+        let a = vec![FieldElement::random(), FieldElement::random()];
+        assert!(!check_collision(a.clone(), a.clone(), &params, sbox_type));
+
+        // Different inputs without a collision
+        let b = vec![a[0].clone(), FieldElement::random()];
+        assert!(!check_collision(a, b, &params, sbox_type));
+    }
+}
@@ -0,0 +1,679 @@
+use super::helper_constraints::poseidon::{
+    domain_tag, PoseidonParams, Poseidon_hash_2_constraints, Poseidon_hash_n,
+    Poseidon_hash_n_constraints, Poseidon_permutation_owned, SboxType, DOMAIN_LABEL_COMMITMENT,
+};
+use super::helper_constraints::positive_no::positive_no_gadget;
+use super::helper_constraints::{allocate_lc_as_quantity, constrain_lc_with_scalar};
+use crate::errors::{R1CSError, R1CSErrorKind};
+use crate::r1cs::linear_combination::AllocatedQuantity;
+use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
+use amcl_wrapper::field_elem::FieldElement;
+
+// Commitments to a value here are `poseidon_commit(value, salt)`, the Poseidon 2:1 hash of the
+// value and the salt with the domain tag of `DOMAIN_LABEL_COMMITMENT` as capacity constant. The
+// value occupies the first slot and the salt the second. The tag keeps commitments apart from
+// other 2:1 hashes like merkle tree nodes so a commitment can be used as a leaf. Need params of
+// width 3.
+
+fn check_commitment_width(params: &PoseidonParams) -> Result<(), R1CSError> {
+    if params.width != 3 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Commitment needs params of width 3 but found {}",
+                params.width
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Commit to `value` with `salt`
+pub fn poseidon_commit(
+    value: FieldElement,
+    salt: FieldElement,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, R1CSError> {
+    check_commitment_width(params)?;
+    let input = vec![domain_tag(DOMAIN_LABEL_COMMITMENT), value, salt];
+    Ok(Poseidon_permutation_owned(input, params, sbox).remove(1))
+}
+
+/// Enforces the constraints of `poseidon_commit` and returns the commitment
+pub fn poseidon_commit_constraints<CS: ConstraintSystem>(
+    cs: &mut CS,
+    value: LinearCombination,
+    salt: LinearCombination,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<LinearCombination, R1CSError> {
+    check_commitment_width(params)?;
+    Poseidon_hash_2_constraints::<CS>(
+        cs,
+        vec![value, salt],
+        domain_tag(DOMAIN_LABEL_COMMITMENT).into(),
+        params,
+        sbox_type,
+    )
+}
+
+/// Constraints for proving that `c1` and `c2` are commitments to the same hidden `value` with
+/// public salts `salt1` and `salt2` respectively, i.e. `c1 = poseidon_commit(value, salt1)` and
+/// `c2 = poseidon_commit(value, salt2)`.
+pub fn poseidon_same_value_diff_salt_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    value: Variable,
+    salt1: FieldElement,
+    salt2: FieldElement,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    c1: &FieldElement,
+    c2: &FieldElement,
+) -> Result<(), R1CSError> {
+    for (salt, c) in vec![(salt1, c1), (salt2, c2)] {
+        let hash = poseidon_commit_constraints(cs, value.into(), salt.into(), params, sbox_type)?;
+        constrain_lc_with_scalar::<CS>(cs, hash, c);
+    }
+    Ok(())
+}
+
+/// Constraints for proving that public commitments `c1 = poseidon_commit(v1, r1)` and
+/// `c2 = poseidon_commit(v2, r2)` open to hidden values summing to the public `total`, i.e.
+/// `v1 + v2 = total`, e.g. for proving balance preservation. The sum is over the field so the
+/// values should be range checked separately if wrap around matters.
+pub fn poseidon_sum_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v1: Variable,
+    r1: Variable,
+    v2: Variable,
+    r2: Variable,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    c1: &FieldElement,
+    c2: &FieldElement,
+    total: FieldElement,
+) -> Result<(), R1CSError> {
+    for (value, salt, c) in vec![(v1, r1, c1), (v2, r2, c2)] {
+        let hash = poseidon_commit_constraints(cs, value.into(), salt.into(), params, sbox_type)?;
+        constrain_lc_with_scalar::<CS>(cs, hash, c);
+    }
+    constrain_lc_with_scalar::<CS>(cs, v1 + v2, &total);
+    Ok(())
+}
+
+/// Constraints for proving that `commitment` is `poseidon_commit(value, timestamp)` for hidden
+/// `value` and hidden `timestamp` where `min_ts <= timestamp <= max_ts`. The timestamp takes the
+/// place of the salt. As in `bound_check_gadget`, `timestamp - min_ts` and `max_ts - timestamp` are
+/// proved to be in `[0, 2^64)`.
+pub fn poseidon_timebound_commit_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    value: Variable,
+    timestamp: AllocatedQuantity,
+    min_ts: u64,
+    max_ts: u64,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    commitment: &FieldElement,
+) -> Result<(), R1CSError> {
+    if min_ts > max_ts {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Window start {} is greater than window end {}",
+                min_ts, max_ts
+            ),
+        }
+        .into());
+    }
+
+    // timestamp - min_ts
+    let above_min = allocate_lc_as_quantity(
+        cs,
+        timestamp.variable - LinearCombination::from(FieldElement::from(min_ts)),
+    )?;
+    positive_no_gadget(cs, above_min, 64)?;
+    // max_ts - timestamp
+    let below_max = allocate_lc_as_quantity(
+        cs,
+        LinearCombination::from(FieldElement::from(max_ts)) - timestamp.variable,
+    )?;
+    positive_no_gadget(cs, below_max, 64)?;
+
+    let hash = poseidon_commit_constraints(
+        cs,
+        value.into(),
+        timestamp.variable.into(),
+        params,
+        sbox_type,
+    )?;
+    constrain_lc_with_scalar::<CS>(cs, hash, commitment);
+    Ok(())
+}
+
+// Credential commitments are `Poseidon_hash_n(id_hash, attr_1, attr_2, ...)`, the hash of the
+// identity occupies the first slot and the attributes the following slots in order. The params
+// should be of a width whose rate fits the identity and all attributes.
+
+fn check_credential_size(num_attributes: usize, params: &PoseidonParams) -> Result<(), R1CSError> {
+    if num_attributes + 1 > params.width - 1 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Params of width {} can commit to at most {} attributes but {} given",
+                params.width,
+                params.width - 2,
+                num_attributes
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Commitment to a credential with identity hash `id_hash` and the given `attributes`
+pub fn poseidon_credential_commitment(
+    id_hash: FieldElement,
+    attributes: Vec<FieldElement>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, R1CSError> {
+    check_credential_size(attributes.len(), params)?;
+    let mut inputs = vec![id_hash];
+    inputs.extend(attributes);
+    Poseidon_hash_n(inputs, params, sbox).map_err(|e| {
+        R1CSErrorKind::GadgetError {
+            description: e.to_string(),
+        }
+        .into()
+    })
+}
+
+/// Constraints for proving that `commitment` is the credential commitment of hidden `id_hash`
+/// and hidden `attributes`, as computed by `poseidon_credential_commitment`. Errors if the
+/// identity and attributes do not fit in the rate of `params`.
+pub fn poseidon_credential_commit_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    id_hash: Variable,
+    attributes: Vec<Variable>,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    commitment: &FieldElement,
+) -> Result<(), R1CSError> {
+    check_credential_size(attributes.len(), params)?;
+    let mut inputs: Vec<LinearCombination> = vec![id_hash.into()];
+    inputs.extend(attributes.into_iter().map(|a| a.into()));
+    let hash = Poseidon_hash_n_constraints::<CS>(cs, inputs, params, sbox_type)?;
+    constrain_lc_with_scalar::<CS>(cs, hash, commitment);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::gadgets::helper_constraints::poseidon::Poseidon_hash_2;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    #[test]
+    fn test_poseidon_commit() {
+        let params = PoseidonParams::new(3, 4, 4, 55).unwrap();
+        let sbox_type = &SboxType::Quint;
+        let value = FieldElement::random();
+        let salt = FieldElement::random();
+
+        // Domain separated from the plain 2:1 hash
+        let c = poseidon_commit(value.clone(), salt.clone(), &params, sbox_type).unwrap();
+        assert_ne!(
+            c,
+            Poseidon_hash_2(vec![value.clone(), salt.clone()], &params, sbox_type).unwrap()
+        );
+        assert_ne!(
+            c,
+            poseidon_commit(salt.clone(), value.clone(), &params, sbox_type).unwrap()
+        );
+
+        // Only width 3 is supported
+        let params_5 = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        assert!(poseidon_commit(value.clone(), salt.clone(), &params_5, sbox_type).is_err());
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let mut prover_transcript = Transcript::new(b"PoseidonCommit");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        let (_, var_v) = prover.commit(value, FieldElement::random());
+        let (_, var_s) = prover.commit(salt, FieldElement::random());
+        assert!(poseidon_commit_constraints(
+            &mut prover,
+            var_v.into(),
+            var_s.into(),
+            &params_5,
+            sbox_type
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_poseidon_same_value_diff_salt() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 1024).into();
+        let H: G1Vector = get_generators("H", 1024).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let value = FieldElement::random();
+        let salt1 = FieldElement::random();
+        let salt2 = FieldElement::random();
+        let c1 = poseidon_commit(value.clone(), salt1.clone(), &params, sbox_type).unwrap();
+
+        // The second commitment is to the same value in first case and to a different value in
+        // the second case
+        let c2_same = poseidon_commit(value.clone(), salt2.clone(), &params, sbox_type).unwrap();
+        let c2_diff =
+            poseidon_commit(FieldElement::random(), salt2.clone(), &params, sbox_type).unwrap();
+
+        for (c2, expected) in vec![(c2_same, true), (c2_diff, false)] {
+            let (proof, commitment) = {
+                let mut prover_transcript = Transcript::new(b"SameValueDiffSalt");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let (com, var) = prover.commit(value.clone(), FieldElement::random());
+                poseidon_same_value_diff_salt_gadget(
+                    &mut prover,
+                    var,
+                    salt1.clone(),
+                    salt2.clone(),
+                    &params,
+                    sbox_type,
+                    &c1,
+                    &c2,
+                )
+                .unwrap();
+                (prover.prove(&G, &H).unwrap(), com)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"SameValueDiffSalt");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let var = verifier.commit(commitment);
+            poseidon_same_value_diff_salt_gadget(
+                &mut verifier,
+                var,
+                salt1.clone(),
+                salt2.clone(),
+                &params,
+                sbox_type,
+                &c1,
+                &c2,
+            )
+            .unwrap();
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+
+    #[test]
+    fn test_poseidon_sum() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 1024).into();
+        let H: G1Vector = get_generators("H", 1024).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let v1 = FieldElement::from(70u64);
+        let v2 = FieldElement::from(30u64);
+        let r1 = FieldElement::random();
+        let r2 = FieldElement::random();
+        let c1 = poseidon_commit(v1.clone(), r1.clone(), &params, sbox_type).unwrap();
+        let c2 = poseidon_commit(v2.clone(), r2.clone(), &params, sbox_type).unwrap();
+
+        for (total, expected) in vec![(100u64, true), (101u64, false)] {
+            let total = FieldElement::from(total);
+            let (proof, commitments) = {
+                let mut prover_transcript = Transcript::new(b"PoseidonSum");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let (comms, vars): (Vec<_>, Vec<_>) = vec![&v1, &r1, &v2, &r2]
+                    .into_iter()
+                    .map(|v| prover.commit(v.clone(), FieldElement::random()))
+                    .unzip();
+                poseidon_sum_gadget(
+                    &mut prover,
+                    vars[0],
+                    vars[1],
+                    vars[2],
+                    vars[3],
+                    &params,
+                    sbox_type,
+                    &c1,
+                    &c2,
+                    total.clone(),
+                )
+                .unwrap();
+                (prover.prove(&G, &H).unwrap(), comms)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"PoseidonSum");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let vars: Vec<_> = commitments
+                .into_iter()
+                .map(|c| verifier.commit(c))
+                .collect();
+            poseidon_sum_gadget(
+                &mut verifier,
+                vars[0],
+                vars[1],
+                vars[2],
+                vars[3],
+                &params,
+                sbox_type,
+                &c1,
+                &c2,
+                total,
+            )
+            .unwrap();
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+
+    fn check_timebound_commit(
+        value: &FieldElement,
+        timestamp: u64,
+        min_ts: u64,
+        max_ts: u64,
+        commitment: &FieldElement,
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 1024).into();
+        let H: G1Vector = get_generators("H", 1024).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"TimeboundCommit");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let (com_v, var_v) = prover.commit(value.clone(), FieldElement::random());
+            let ts = FieldElement::from(timestamp);
+            let (com_ts, var_ts) = prover.commit(ts.clone(), FieldElement::random());
+            let ts = AllocatedQuantity {
+                variable: var_ts,
+                assignment: Some(ts),
+            };
+            poseidon_timebound_commit_gadget(
+                &mut prover,
+                var_v,
+                ts,
+                min_ts,
+                max_ts,
+                params,
+                sbox_type,
+                commitment,
+            )
+            .unwrap();
+            (prover.prove(&G, &H).unwrap(), vec![com_v, com_ts])
+        };
+
+        let mut verifier_transcript = Transcript::new(b"TimeboundCommit");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let var_v = verifier.commit(commitments[0].clone());
+        let ts = AllocatedQuantity {
+            variable: verifier.commit(commitments[1].clone()),
+            assignment: None,
+        };
+        poseidon_timebound_commit_gadget(
+            &mut verifier,
+            var_v,
+            ts,
+            min_ts,
+            max_ts,
+            params,
+            sbox_type,
+            commitment,
+        )
+        .unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_poseidon_timebound_commit() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let (min_ts, max_ts) = (1_600_000_000u64, 1_600_086_400u64);
+        let value = FieldElement::random();
+        let commit = |ts: u64| {
+            poseidon_commit(value.clone(), FieldElement::from(ts), &params, sbox_type).unwrap()
+        };
+
+        // In the window, including both ends
+        for &ts in [min_ts, 1_600_050_000, max_ts].iter() {
+            assert!(check_timebound_commit(
+                &value,
+                ts,
+                min_ts,
+                max_ts,
+                &commit(ts),
+                &params,
+                sbox_type
+            ));
+        }
+
+        // Out of the window even though the commitment is correct
+        for &ts in [min_ts - 1, max_ts + 1].iter() {
+            assert!(!check_timebound_commit(
+                &value,
+                ts,
+                min_ts,
+                max_ts,
+                &commit(ts),
+                &params,
+                sbox_type
+            ));
+        }
+
+        // Invalid window
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let mut prover_transcript = Transcript::new(b"TimeboundCommit");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        let (_, var_v) = prover.commit(value.clone(), FieldElement::random());
+        let (_, var_ts) = prover.commit(FieldElement::from(min_ts), FieldElement::random());
+        assert!(poseidon_timebound_commit_gadget(
+            &mut prover,
+            var_v,
+            AllocatedQuantity {
+                variable: var_ts,
+                assignment: Some(FieldElement::from(min_ts)),
+            },
+            max_ts,
+            min_ts,
+            &params,
+            sbox_type,
+            &commit(min_ts),
+        )
+        .is_err());
+    }
+
+    fn check_credential_commit(
+        id_hash: &FieldElement,
+        attributes: &[FieldElement],
+        commitment: &FieldElement,
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 1024).into();
+        let H: G1Vector = get_generators("H", 1024).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"CredentialCommit");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let (com_id, var_id) = prover.commit(id_hash.clone(), FieldElement::random());
+            let mut comms = vec![com_id];
+            let mut vars = vec![];
+            for a in attributes {
+                let (com, var) = prover.commit(a.clone(), FieldElement::random());
+                comms.push(com);
+                vars.push(var);
+            }
+            poseidon_credential_commit_gadget(
+                &mut prover,
+                var_id,
+                vars,
+                params,
+                sbox_type,
+                commitment,
+            )
+            .unwrap();
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"CredentialCommit");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars = commitments
+            .into_iter()
+            .map(|c| verifier.commit(c))
+            .collect::<Vec<_>>();
+        let var_id = vars.remove(0);
+        poseidon_credential_commit_gadget(
+            &mut verifier,
+            var_id,
+            vars,
+            params,
+            sbox_type,
+            commitment,
+        )
+        .unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_poseidon_credential_commit() {
+        let width = 5;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let id_hash = FieldElement::random();
+        let attributes = vec![FieldElement::from(1990u64), FieldElement::from(42u64)];
+        let commitment =
+            poseidon_credential_commitment(id_hash.clone(), attributes.clone(), &params, sbox_type)
+                .unwrap();
+        assert_eq!(
+            commitment,
+            Poseidon_hash_n(
+                vec![
+                    id_hash.clone(),
+                    attributes[0].clone(),
+                    attributes[1].clone()
+                ],
+                &params,
+                sbox_type
+            )
+            .unwrap()
+        );
+
+        assert!(check_credential_commit(
+            &id_hash,
+            &attributes,
+            &commitment,
+            &params,
+            sbox_type
+        ));
+
+        // Tampered attribute
+        let mut tampered = attributes.clone();
+        tampered[1] = FieldElement::from(43u64);
+        assert!(!check_credential_commit(
+            &id_hash,
+            &tampered,
+            &commitment,
+            &params,
+            sbox_type
+        ));
+
+        // Width 5 fits the identity and at most 3 attributes
+        let too_many = (0..4).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        assert!(poseidon_credential_commitment(
+            id_hash.clone(),
+            too_many.clone(),
+            &params,
+            sbox_type
+        )
+        .is_err());
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let mut prover_transcript = Transcript::new(b"CredentialCommit");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        let (_, var_id) = prover.commit(id_hash, FieldElement::random());
+        let vars = too_many
+            .into_iter()
+            .map(|a| prover.commit(a, FieldElement::random()).1)
+            .collect::<Vec<_>>();
+        assert!(poseidon_credential_commit_gadget(
+            &mut prover,
+            var_id,
+            vars,
+            &params,
+            sbox_type,
+            &commitment
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use super::helper_constraints::allocate_lc_as_quantity;
+use super::helper_constraints::poseidon::{
+    domain_tag, PoseidonParams, Poseidon_hash_2_constraints, Poseidon_permutation_owned, SboxType,
+    DOMAIN_LABEL_DERIVE_CHILD,
+};
+use super::helper_constraints::poseidon_merkle_tree::committed_leaf_membership_gadget;
+use crate::errors::{R1CSError, R1CSErrorKind};
+use crate::r1cs::linear_combination::AllocatedQuantity;
+use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
+use amcl_wrapper::field_elem::FieldElement;
+
+// A parent secret derives a child secret for each index, the child being the Poseidon 2:1 hash of
+// the parent and the index with the domain tag of `DOMAIN_LABEL_DERIVE_CHILD` as capacity
+// constant. An issuer puts the commitment to the child, as computed by `poseidon_commit`,
+// in a merkle tree and the holder of the parent later proves that some child of theirs is
+// committed in the tree without revealing the parent, the index or the child.
+// All functions need params of width 3.
+
+fn check_derivation_width(params: &PoseidonParams) -> Result<(), R1CSError> {
+    if params.width != 3 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Derivation needs params of width 3 but found {}",
+                params.width
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Derive the child of `parent` at `index`
+pub fn poseidon_derive_child(
+    parent: FieldElement,
+    index: FieldElement,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, R1CSError> {
+    check_derivation_width(params)?;
+    let input = vec![domain_tag(DOMAIN_LABEL_DERIVE_CHILD), parent, index];
+    Ok(Poseidon_permutation_owned(input, params, sbox).remove(1))
+}
+
+/// Enforces the constraints of `poseidon_derive_child` and returns the child
+pub fn poseidon_derive_child_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    parent: LinearCombination,
+    index: LinearCombination,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<LinearCombination, R1CSError> {
+    check_derivation_width(params)?;
+    Poseidon_hash_2_constraints::<CS>(
+        cs,
+        vec![parent, index],
+        domain_tag(DOMAIN_LABEL_DERIVE_CHILD).into(),
+        params,
+        sbox_type,
+    )
+}
+
+/// Constraints for proving that the child of hidden `parent` at hidden `index`, committed with
+/// hidden `randomness`, is a leaf of the tree with the given `root`. Chains
+/// `poseidon_derive_child_gadget` with `committed_leaf_membership_gadget`, the child, its
+/// commitment, the path and directions `bits` stay hidden.
+pub fn poseidon_chained_derivation_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    parent: Variable,
+    index: Variable,
+    randomness: AllocatedQuantity,
+    path: &[Variable],
+    bits: &[AllocatedQuantity],
+    root: &FieldElement,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    let child = poseidon_derive_child_gadget(cs, parent.into(), index.into(), params, sbox_type)?
+        .simplify();
+    let child = allocate_lc_as_quantity(cs, child)?;
+    committed_leaf_membership_gadget(cs, child, randomness, path, bits, root, params, sbox_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::gadgets::helper_constraints::poseidon::Poseidon_hash_2;
+    use crate::r1cs::gadgets::helper_constraints::poseidon_merkle_tree::{
+        poseidon_merkle_root, PoseidonMerkleTree,
+    };
+    use crate::r1cs::gadgets::poseidon_commitment::poseidon_commit;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    fn check_chained_derivation(
+        parent: &FieldElement,
+        index: &FieldElement,
+        randomness: &FieldElement,
+        path: &[FieldElement],
+        directions: &[bool],
+        root: &FieldElement,
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let mut witness = vec![parent.clone(), index.clone(), randomness.clone()];
+        witness.extend(path.iter().cloned());
+        witness.extend(directions.iter().map(|d| FieldElement::from(*d as u64)));
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"ChainedDerivation");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let mut comms = vec![];
+            let mut vars = vec![];
+            for w in &witness {
+                let (c, v) = prover.commit(w.clone(), FieldElement::random());
+                comms.push(c);
+                vars.push(AllocatedQuantity {
+                    variable: v,
+                    assignment: Some(w.clone()),
+                });
+            }
+            let bits = vars.split_off(3 + path.len());
+            let path_vars = vars
+                .split_off(3)
+                .iter()
+                .map(|v| v.variable)
+                .collect::<Vec<_>>();
+            poseidon_chained_derivation_gadget(
+                &mut prover,
+                vars[0].variable,
+                vars[1].variable,
+                vars[2].clone(),
+                &path_vars,
+                &bits,
+                root,
+                params,
+                sbox_type,
+            )
+            .unwrap();
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"ChainedDerivation");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars = commitments
+            .into_iter()
+            .map(|c| AllocatedQuantity {
+                variable: verifier.commit(c),
+                assignment: None,
+            })
+            .collect::<Vec<_>>();
+        let bits = vars.split_off(3 + path.len());
+        let path_vars = vars
+            .split_off(3)
+            .iter()
+            .map(|v| v.variable)
+            .collect::<Vec<_>>();
+        poseidon_chained_derivation_gadget(
+            &mut verifier,
+            vars[0].variable,
+            vars[1].variable,
+            vars[2].clone(),
+            &path_vars,
+            &bits,
+            root,
+            params,
+            sbox_type,
+        )
+        .unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_poseidon_chained_derivation() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let parent = FieldElement::random();
+        let index = FieldElement::from(7u64);
+        let randomness = FieldElement::random();
+        let child =
+            poseidon_derive_child(parent.clone(), index.clone(), &params, sbox_type).unwrap();
+        assert_ne!(
+            child,
+            poseidon_derive_child(parent.clone(), FieldElement::from(8u64), &params, sbox_type)
+                .unwrap()
+        );
+        let params_5 = PoseidonParams::new(5, 4, 4, 56).unwrap();
+        assert!(
+            poseidon_derive_child(parent.clone(), index.clone(), &params_5, sbox_type).is_err()
+        );
+        // Domain separated from the plain 2:1 hash
+        assert_ne!(
+            child,
+            Poseidon_hash_2(vec![parent.clone(), index.clone()], &params, sbox_type).unwrap()
+        );
+
+        // Issue the commitment to the child as the leaf at index 5
+        let depth = 3;
+        let leaf_index = 5;
+        let mut tree = PoseidonMerkleTree::new_retaining(depth, &params, sbox_type).unwrap();
+        let issued = poseidon_commit(child, randomness.clone(), &params, sbox_type).unwrap();
+        for i in 0..(1 << depth) {
+            if i == leaf_index {
+                tree.append(issued.clone()).unwrap();
+            } else {
+                tree.append(FieldElement::random()).unwrap();
+            }
+        }
+        let root = tree.root();
+        let proof = tree.proof_for(leaf_index as u64).unwrap();
+        let directions = proof.directions().unwrap();
+        assert_eq!(
+            poseidon_merkle_root(&issued, &proof.siblings, &directions, &params, sbox_type)
+                .unwrap(),
+            root
+        );
+
+        let check = |parent: &FieldElement,
+                     index: &FieldElement,
+                     randomness: &FieldElement,
+                     path: &[FieldElement],
+                     root: &FieldElement| {
+            check_chained_derivation(
+                parent,
+                index,
+                randomness,
+                path,
+                &directions,
+                root,
+                &params,
+                sbox_type,
+            )
+        };
+
+        assert!(check(&parent, &index, &randomness, &proof.siblings, &root));
+
+        // Derivation link broken, a different parent or index
+        assert!(!check(
+            &FieldElement::random(),
+            &index,
+            &randomness,
+            &proof.siblings,
+            &root
+        ));
+        assert!(!check(
+            &parent,
+            &FieldElement::from(8u64),
+            &randomness,
+            &proof.siblings,
+            &root
+        ));
+        // Commitment link broken, a different randomness
+        assert!(!check(
+            &parent,
+            &index,
+            &FieldElement::random(),
+            &proof.siblings,
+            &root
+        ));
+        // Membership link broken, a different path or root
+        let mut wrong_path = proof.siblings.clone();
+        wrong_path[1] = FieldElement::random();
+        assert!(!check(&parent, &index, &randomness, &wrong_path, &root));
+        assert!(!check(
+            &parent,
+            &index,
+            &randomness,
+            &proof.siblings,
+            &FieldElement::random()
+        ));
+    }
+}
@@ -179,6 +179,120 @@ pub fn verify_proof_of_knowledge_of_preimage_of_Poseidon_2(
     verifier.verify(&proof, g, h, G, H)
 }
 
+/// Verifies several proofs of knowledge of preimage of Poseidon hash with 2 inputs and 1 output
+/// in a batch. `proofs[i]` is the proof and commitments for image `images[i]` as returned by
+/// `gen_proof_of_knowledge_of_preimage_of_Poseidon_2`. Returns `Ok(false)` if any proof is invalid.
+pub fn batch_verify_poseidon_hashes(
+    proofs: Vec<(R1CSProof, Vec<G1>)>,
+    images: &[FieldElement],
+    hash_params: &PoseidonParams,
+    sbox_type: &SboxType,
+    transcript_label: &'static [u8],
+    g: &G1,
+    h: &G1,
+    G: &G1Vector,
+    H: &G1Vector,
+) -> Result<bool, R1CSError> {
+    if proofs.len() != images.len() {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Number of proofs {} not equal to number of images {}",
+                proofs.len(),
+                images.len()
+            ),
+        }
+        .into());
+    }
+    for (i, (_, commitments)) in proofs.iter().enumerate() {
+        if commitments.len() != 2 {
+            return Err(R1CSErrorKind::GadgetError {
+                description: format!(
+                    "Expected 2 commitments to preimage for proof {} but found {}",
+                    i,
+                    commitments.len()
+                ),
+            }
+            .into());
+        }
+    }
+
+    let mut transcripts = (0..proofs.len())
+        .map(|_| Transcript::new(transcript_label))
+        .collect::<Vec<_>>();
+    let mut instances = vec![];
+    for ((transcript, (proof, commitments)), image) in
+        transcripts.iter_mut().zip(proofs.iter()).zip(images.iter())
+    {
+        let mut verifier = Verifier::new(transcript);
+        verify_knowledge_of_preimage_of_Poseidon_2(
+            image,
+            hash_params,
+            sbox_type,
+            commitments.clone(),
+            g,
+            h,
+            &mut verifier,
+        )?;
+        instances.push((verifier, proof));
+    }
+
+    match Verifier::batch_verify(instances, g, h, G, H) {
+        Ok(()) => Ok(true),
+        Err(e) => match e.kind() {
+            R1CSErrorKind::VerificationError => Ok(false),
+            _ => Err(e),
+        },
+    }
+}
+
+/// Verifies a proof of knowledge of preimage of Poseidon hash with 2 inputs and 1 output against
+/// the public `image`. `public_inputs` are the commitments to the preimage as returned by
+/// `gen_proof_of_knowledge_of_preimage_of_Poseidon_2`. Unlike
+/// `verify_proof_of_knowledge_of_preimage_of_Poseidon_2`, an invalid proof gives `Ok(false)` and
+/// only malformed inputs give an error.
+pub fn verify_hash_proof(
+    proof: &R1CSProof,
+    image: &FieldElement,
+    public_inputs: Vec<G1>,
+    hash_params: &PoseidonParams,
+    sbox_type: &SboxType,
+    transcript_label: &'static [u8],
+    g: &G1,
+    h: &G1,
+    G: &G1Vector,
+    H: &G1Vector,
+) -> Result<bool, R1CSError> {
+    if public_inputs.len() != 2 {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Expected 2 commitments to preimage but found {}",
+                public_inputs.len()
+            ),
+        }
+        .into());
+    }
+
+    let mut verifier_transcript = Transcript::new(transcript_label);
+    let mut verifier = Verifier::new(&mut verifier_transcript);
+
+    verify_knowledge_of_preimage_of_Poseidon_2(
+        image,
+        hash_params,
+        sbox_type,
+        public_inputs,
+        g,
+        h,
+        &mut verifier,
+    )?;
+    match verifier.verify(proof, g, h, G, H) {
+        Ok(()) => Ok(true),
+        Err(e) => match e.kind() {
+            R1CSErrorKind::VerificationError => Ok(false),
+            _ => Err(e),
+        },
+    }
+}
+
 /// Takes a Prover and enforces the constraints of Poseidon hash with 4 inputs and 1 output
 pub fn prove_knowledge_of_preimage_of_Poseidon_4<R: Rng + CryptoRng>(
     mut preimage: Vec<FieldElement>,
@@ -679,10 +793,229 @@ mod tests {
         let hash_params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
 
         check_hash_2(&hash_params, &SboxType::Cube);
+        #[cfg(feature = "inverse_sbox")]
         check_hash_2(&hash_params, &SboxType::Inverse);
         check_hash_2(&hash_params, &SboxType::Quint);
     }
 
+    #[test]
+    fn test_batch_verify_poseidon_hashes() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let hash_params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+        let mut rng = rand::thread_rng();
+
+        let G: G1Vector = get_generators("G", 1024).into();
+        let H: G1Vector = get_generators("H", 1024).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let label = b"PoseidonHash2:1Batch";
+
+        let mut proofs = vec![];
+        let mut images = vec![];
+        for _ in 0..4 {
+            let inputs = vec![FieldElement::random(), FieldElement::random()];
+            let image = Poseidon_hash_2(inputs.clone(), &hash_params, sbox_type).unwrap();
+            proofs.push(
+                gen_proof_of_knowledge_of_preimage_of_Poseidon_2(
+                    inputs,
+                    None,
+                    &image,
+                    &hash_params,
+                    sbox_type,
+                    Some(&mut rng),
+                    label,
+                    &g,
+                    &h,
+                    &G,
+                    &H,
+                )
+                .unwrap(),
+            );
+            images.push(image);
+        }
+
+        let start = Instant::now();
+        assert!(batch_verify_poseidon_hashes(
+            proofs.clone(),
+            &images,
+            &hash_params,
+            sbox_type,
+            label,
+            &g,
+            &h,
+            &G,
+            &H
+        )
+        .unwrap());
+        println!(
+            "Batch verification time for 4 proofs is: {:?}",
+            start.elapsed()
+        );
+
+        // Change the statement of one proof
+        images[2] = FieldElement::random();
+        assert!(!batch_verify_poseidon_hashes(
+            proofs.clone(),
+            &images,
+            &hash_params,
+            sbox_type,
+            label,
+            &g,
+            &h,
+            &G,
+            &H
+        )
+        .unwrap());
+
+        assert!(batch_verify_poseidon_hashes(
+            proofs.clone(),
+            &images[..3],
+            &hash_params,
+            sbox_type,
+            label,
+            &g,
+            &h,
+            &G,
+            &H
+        )
+        .is_err());
+
+        // Wrong number of commitments in one proof
+        let mut short_proofs = proofs.clone();
+        short_proofs[1].1.truncate(1);
+        assert!(batch_verify_poseidon_hashes(
+            short_proofs,
+            &images,
+            &hash_params,
+            sbox_type,
+            label,
+            &g,
+            &h,
+            &G,
+            &H
+        )
+        .is_err());
+
+        let mut long_proofs = proofs;
+        long_proofs[3].1.push(G1::random());
+        assert!(batch_verify_poseidon_hashes(
+            long_proofs,
+            &images,
+            &hash_params,
+            sbox_type,
+            label,
+            &g,
+            &h,
+            &G,
+            &H
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_verify_hash_proof() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let hash_params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+        let mut rng = rand::thread_rng();
+
+        let G: G1Vector = get_generators("G", 1024).into();
+        let H: G1Vector = get_generators("H", 1024).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let label = b"PoseidonHash2:1Verify";
+
+        let inputs = vec![FieldElement::random(), FieldElement::random()];
+        let image = Poseidon_hash_2(inputs.clone(), &hash_params, sbox_type).unwrap();
+        let (proof, comms) = gen_proof_of_knowledge_of_preimage_of_Poseidon_2(
+            inputs,
+            None,
+            &image,
+            &hash_params,
+            sbox_type,
+            Some(&mut rng),
+            label,
+            &g,
+            &h,
+            &G,
+            &H,
+        )
+        .unwrap();
+
+        assert!(verify_hash_proof(
+            &proof,
+            &image,
+            comms.clone(),
+            &hash_params,
+            sbox_type,
+            label,
+            &g,
+            &h,
+            &G,
+            &H
+        )
+        .unwrap());
+
+        // Mismatched image
+        assert!(!verify_hash_proof(
+            &proof,
+            &FieldElement::random(),
+            comms.clone(),
+            &hash_params,
+            sbox_type,
+            label,
+            &g,
+            &h,
+            &G,
+            &H
+        )
+        .unwrap());
+
+        // Wrong number of commitments
+        assert!(verify_hash_proof(
+            &proof,
+            &image,
+            comms[..1].to_vec(),
+            &hash_params,
+            sbox_type,
+            label,
+            &g,
+            &h,
+            &G,
+            &H
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_poseidon_hash_4() {
         let width = 5;
@@ -702,6 +1035,7 @@ mod tests {
         let hash_params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
 
         check_hash_4(&hash_params, &SboxType::Cube);
+        #[cfg(feature = "inverse_sbox")]
         check_hash_4(&hash_params, &SboxType::Inverse);
         check_hash_4(&hash_params, &SboxType::Quint);
     }
@@ -725,6 +1059,7 @@ mod tests {
         let hash_params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
 
         check_hash_8(&hash_params, &SboxType::Cube);
+        #[cfg(feature = "inverse_sbox")]
         check_hash_8(&hash_params, &SboxType::Inverse);
         check_hash_8(&hash_params, &SboxType::Quint);
     }
@@ -0,0 +1,221 @@
+use super::helper_constraints::constrain_lc_with_scalar;
+use super::helper_constraints::poseidon::{
+    PoseidonCircuitBudget, PoseidonParams, Poseidon_hash_1, Poseidon_hash_1_constraints, SboxType,
+    CAP_CONST_1_W_3,
+};
+use crate::errors::{BulletproofError, R1CSError};
+use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
+use amcl_wrapper::field_elem::FieldElement;
+
+// A hash chain of length `k` starting from `h_0` is `h_0, h_1, ..., h_k` where
+// `h_{i+1} = Poseidon_hash_1(h_i)`. Needs params of width 3.
+
+/// Apply `Poseidon_hash_1` `k` times on `h0` and return `h_k`
+pub fn poseidon_hash_chain(
+    h0: FieldElement,
+    k: usize,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<FieldElement, BulletproofError> {
+    let mut h = h0;
+    for _ in 0..k {
+        h = Poseidon_hash_1(h, params, sbox_type)?;
+    }
+    Ok(h)
+}
+
+/// Enforces constraints for `poseidon_hash_chain` and returns `h_k`
+pub fn poseidon_hash_chain_constraints<CS: ConstraintSystem>(
+    cs: &mut CS,
+    h0: LinearCombination,
+    k: usize,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<LinearCombination, R1CSError> {
+    let mut h = h0;
+    for _ in 0..k {
+        h = Poseidon_hash_1_constraints::<CS>(
+            cs,
+            h,
+            FieldElement::from(CAP_CONST_1_W_3).into(),
+            params,
+            sbox_type,
+        )?
+        .simplify();
+    }
+    Ok(h)
+}
+
+/// Same as `poseidon_hash_chain_constraints` but charges `budget` for each hash before adding its
+/// constraints, erroring once the budget is exceeded
+pub fn poseidon_hash_chain_constraints_with_budget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    h0: LinearCombination,
+    k: usize,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    budget: &mut PoseidonCircuitBudget,
+) -> Result<LinearCombination, R1CSError> {
+    let mut h = h0;
+    for _ in 0..k {
+        budget.charge_permutation(params, sbox_type)?;
+        h = Poseidon_hash_1_constraints::<CS>(
+            cs,
+            h,
+            FieldElement::from(CAP_CONST_1_W_3).into(),
+            params,
+            sbox_type,
+        )?
+        .simplify();
+    }
+    Ok(h)
+}
+
+/// Constraints for proving that public `hk` was derived from hidden `h0` by applying the hash
+/// chain `k` times, `k` being public. Used for proving that at least `k` steps of a chain have
+/// been taken, e.g. for rate limiting where the verifier has seen `hk` and the prover shows a
+/// preimage `k` steps back. Since the hash is one way, knowing `h0` implies the prover knows all
+/// intermediate values so knowing a preimage `k` steps back proves a chain of length at least `k`.
+pub fn poseidon_min_chain_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    h0: Variable,
+    k: usize,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    hk: &FieldElement,
+) -> Result<(), R1CSError> {
+    let out = poseidon_hash_chain_constraints(cs, h0.into(), k, params, sbox_type)?;
+    constrain_lc_with_scalar::<CS>(cs, out, hk);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::R1CSErrorKind;
+    use crate::r1cs::gadgets::helper_constraints::poseidon::Poseidon_permutation_gate_count;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    #[test]
+    fn test_poseidon_min_chain() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let k = 5;
+        let h0 = FieldElement::random();
+        let hk = poseidon_hash_chain(h0.clone(), k, &params, sbox_type).unwrap();
+        // One step less
+        let hk_1 = poseidon_hash_chain(h0.clone(), k - 1, &params, sbox_type).unwrap();
+
+        for (image, expected) in vec![(hk, true), (hk_1, false)] {
+            let (proof, commitment) = {
+                let mut prover_transcript = Transcript::new(b"PoseidonMinChain");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let (com, var) = prover.commit(h0.clone(), FieldElement::random());
+                poseidon_min_chain_gadget(&mut prover, var, k, &params, sbox_type, &image).unwrap();
+                (prover.prove(&G, &H).unwrap(), com)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"PoseidonMinChain");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let var = verifier.commit(commitment);
+            poseidon_min_chain_gadget(&mut verifier, var, k, &params, sbox_type, &image).unwrap();
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+
+    #[test]
+    fn test_hash_chain_with_budget() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+        let gates_per_hash = Poseidon_permutation_gate_count(&params, sbox_type);
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        // Budget for 3 hashes
+        let max = 3 * gates_per_hash;
+        let h0 = FieldElement::random();
+
+        let mut prover_transcript = Transcript::new(b"HashChainBudget");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        let var = prover.commit(h0.clone(), FieldElement::random()).1;
+        let mut budget = PoseidonCircuitBudget::new(max);
+        let out = poseidon_hash_chain_constraints_with_budget(
+            &mut prover,
+            var.into(),
+            3,
+            &params,
+            sbox_type,
+            &mut budget,
+        )
+        .unwrap();
+        assert_eq!(
+            prover.evaluate_lc(&out).unwrap(),
+            poseidon_hash_chain(h0.clone(), 3, &params, sbox_type).unwrap()
+        );
+        assert_eq!(budget.used_gates(), max);
+        assert_eq!(budget.remaining_gates(), 0);
+        assert_eq!(prover.num_multipliers(), max);
+
+        // A longer chain fails at the 4th hash without adding its constraints
+        let mut prover_transcript = Transcript::new(b"HashChainBudget");
+        let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+        let var = prover.commit(h0, FieldElement::random()).1;
+        let mut budget = PoseidonCircuitBudget::new(max);
+        let err = poseidon_hash_chain_constraints_with_budget(
+            &mut prover,
+            var.into(),
+            5,
+            &params,
+            sbox_type,
+            &mut budget,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.kind(),
+            R1CSErrorKind::GateBudgetExceeded {
+                current: 4 * gates_per_hash,
+                max
+            }
+        );
+        assert_eq!(budget.used_gates(), max);
+        assert_eq!(prover.num_multipliers(), max);
+    }
+}
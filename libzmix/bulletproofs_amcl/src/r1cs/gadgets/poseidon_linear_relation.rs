@@ -0,0 +1,141 @@
+use super::helper_constraints::constrain_lc_with_scalar;
+use super::helper_constraints::poseidon::{
+    PoseidonParams, Poseidon_hash_2_constraints, SboxType, CAP_CONST_W_3,
+};
+use crate::errors::R1CSError;
+use crate::r1cs::{ConstraintSystem, Variable};
+use amcl_wrapper::field_elem::FieldElement;
+
+/// Constraints for proving knowledge of hidden `x` and `y` such that
+/// `Poseidon_hash_2(x, y) = image` and `x + y = public_sum`.
+pub fn hash_with_linear_constraint_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    x: Variable,
+    y: Variable,
+    image: &FieldElement,
+    public_sum: &FieldElement,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    let hash = Poseidon_hash_2_constraints::<CS>(
+        cs,
+        vec![x.into(), y.into()],
+        FieldElement::from(CAP_CONST_W_3).into(),
+        params,
+        sbox_type,
+    )?;
+    constrain_lc_with_scalar::<CS>(cs, hash, image);
+    // x + y - public_sum = 0
+    constrain_lc_with_scalar::<CS>(cs, x + y, public_sum);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::gadgets::helper_constraints::poseidon::Poseidon_hash_2;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    fn check_hash_with_linear_constraint(
+        x: &FieldElement,
+        y: &FieldElement,
+        image: &FieldElement,
+        public_sum: &FieldElement,
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 512).into();
+        let H: G1Vector = get_generators("H", 512).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"HashWithLinearConstraint");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let (com_x, var_x) = prover.commit(x.clone(), FieldElement::random());
+            let (com_y, var_y) = prover.commit(y.clone(), FieldElement::random());
+            hash_with_linear_constraint_gadget(
+                &mut prover,
+                var_x,
+                var_y,
+                image,
+                public_sum,
+                params,
+                sbox_type,
+            )
+            .unwrap();
+            (prover.prove(&G, &H).unwrap(), vec![com_x, com_y])
+        };
+
+        let mut verifier_transcript = Transcript::new(b"HashWithLinearConstraint");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let var_x = verifier.commit(commitments[0].clone());
+        let var_y = verifier.commit(commitments[1].clone());
+        hash_with_linear_constraint_gadget(
+            &mut verifier,
+            var_x,
+            var_y,
+            image,
+            public_sum,
+            params,
+            sbox_type,
+        )
+        .unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_hash_with_linear_constraint() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let x = FieldElement::random();
+        let y = FieldElement::random();
+        let image = Poseidon_hash_2(vec![x.clone(), y.clone()], &params, sbox_type).unwrap();
+        let sum = &x + &y;
+
+        // Both the hash and the sum hold
+        assert!(check_hash_with_linear_constraint(
+            &x, &y, &image, &sum, &params, sbox_type
+        ));
+
+        // Hash holds but not the sum
+        assert!(!check_hash_with_linear_constraint(
+            &x,
+            &y,
+            &image,
+            &(&sum + &FieldElement::one()),
+            &params,
+            sbox_type
+        ));
+
+        // Sum holds but not the hash, the inputs are shifted keeping their sum
+        let d = FieldElement::random();
+        assert!(!check_hash_with_linear_constraint(
+            &(&x + &d),
+            &(&y - &d),
+            &image,
+            &sum,
+            &params,
+            sbox_type
+        ));
+    }
+}
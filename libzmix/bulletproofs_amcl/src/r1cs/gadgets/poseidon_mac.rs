@@ -0,0 +1,146 @@
+use super::helper_constraints::constrain_lc_with_scalar;
+use super::helper_constraints::poseidon::{
+    domain_tag, PoseidonParams, PoseidonSponge, PoseidonSpongeConstraints, SboxType,
+    DOMAIN_LABEL_MAC,
+};
+use crate::errors::R1CSError;
+use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
+use amcl_wrapper::field_elem::FieldElement;
+
+// MAC with a keyed sponge. A `PoseidonSponge` with capacity 1 absorbs the domain tag of
+// `DOMAIN_LABEL_MAC`, the key, the number of message elements and then the message elements, and
+// the tag is the first squeezed element. The sponge's padding already separates messages `m` and
+// `m || 0`, the message length is absorbed as well so the length is fixed before the message.
+
+/// Compute the MAC tag of `message` with `key`
+pub fn poseidon_mac(
+    key: &FieldElement,
+    message: &[FieldElement],
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, R1CSError> {
+    let mut sponge = PoseidonSponge::new(params, sbox, 1)?;
+    sponge.absorb(&[
+        domain_tag(DOMAIN_LABEL_MAC),
+        key.clone(),
+        FieldElement::from(message.len() as u64),
+    ]);
+    sponge.absorb(message);
+    Ok(sponge.squeeze(1).remove(0))
+}
+
+/// Check that `tag` is the MAC tag of `message` with `key`
+pub fn poseidon_mac_verify(
+    key: &FieldElement,
+    message: &[FieldElement],
+    tag: &FieldElement,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<bool, R1CSError> {
+    Ok(poseidon_mac(key, message, params, sbox)? == *tag)
+}
+
+/// Constraints for proving that public `tag` is the MAC tag of hidden `message` with hidden
+/// `key`. The absorption of `poseidon_mac` is done with `PoseidonSpongeConstraints`.
+pub fn poseidon_mac_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    key: Variable,
+    message: Vec<Variable>,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    tag: &FieldElement,
+) -> Result<(), R1CSError> {
+    let mut inputs: Vec<LinearCombination> = vec![
+        domain_tag(DOMAIN_LABEL_MAC).into(),
+        key.into(),
+        FieldElement::from(message.len() as u64).into(),
+    ];
+    inputs.extend(message.into_iter().map(|m| m.into()));
+
+    let mut sponge = PoseidonSpongeConstraints::new(params, sbox_type, 1)?;
+    sponge.absorb(cs, &inputs)?;
+    let out = sponge.squeeze(cs, 1)?.remove(0);
+    constrain_lc_with_scalar::<CS>(cs, out, tag);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    #[test]
+    fn test_poseidon_mac() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let key = FieldElement::random();
+        let wrong_key = FieldElement::random();
+        let message = (0..4).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let tag = poseidon_mac(&key, &message, &params, sbox_type).unwrap();
+        assert!(poseidon_mac_verify(&key, &message, &tag, &params, sbox_type).unwrap());
+        assert!(!poseidon_mac_verify(&wrong_key, &message, &tag, &params, sbox_type).unwrap());
+        assert_ne!(
+            poseidon_mac(&wrong_key, &message, &params, sbox_type).unwrap(),
+            tag
+        );
+
+        // Appending a zero changes the tag
+        let mut extended = message.clone();
+        extended.push(FieldElement::zero());
+        assert_ne!(
+            poseidon_mac(&key, &extended, &params, sbox_type).unwrap(),
+            tag
+        );
+
+        for (k, expected) in vec![(key, true), (wrong_key, false)] {
+            let (proof, commitments) = {
+                let mut prover_transcript = Transcript::new(b"PoseidonMac");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let (key_com, key_var) = prover.commit(k.clone(), FieldElement::random());
+                let mut comms = vec![key_com];
+                let mut vars = vec![];
+                for m in &message {
+                    let (c, v) = prover.commit(m.clone(), FieldElement::random());
+                    comms.push(c);
+                    vars.push(v);
+                }
+                poseidon_mac_gadget(&mut prover, key_var, vars, &params, sbox_type, &tag).unwrap();
+                (prover.prove(&G, &H).unwrap(), comms)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"PoseidonMac");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let key_var = verifier.commit(commitments[0].clone());
+            let vars = commitments[1..]
+                .iter()
+                .map(|c| verifier.commit(c.clone()))
+                .collect();
+            poseidon_mac_gadget(&mut verifier, key_var, vars, &params, sbox_type, &tag).unwrap();
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+}
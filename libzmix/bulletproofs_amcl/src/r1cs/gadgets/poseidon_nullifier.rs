@@ -0,0 +1,214 @@
+use super::helper_constraints::bit::canonical_bits_gadget;
+use super::helper_constraints::poseidon::{
+    PoseidonParams, Poseidon_hash_2, Poseidon_hash_2_constraints, SboxType, CAP_CONST_W_3,
+};
+use super::helper_constraints::sparse_merkle_tree_4_ary::vanilla_merkle_merkle_tree_4_verif_gadget;
+use super::helper_constraints::{allocate_lc_as_quantity, LeafValueType};
+use super::merkle_tree_hash::Arity4MerkleTreeHashConstraints;
+use crate::errors::{BulletproofError, R1CSError, R1CSErrorKind};
+use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
+use amcl_wrapper::constants::CurveOrder;
+use amcl_wrapper::field_elem::FieldElement;
+
+// The nullifier of a secret and an index is `Poseidon_hash_2(secret, index)`. The set of seen
+// nullifiers is a `VanillaSparseMerkleTree4` where each nullifier is stored at the slot given by
+// the least significant `2*depth` bits of the nullifier, i.e. the tree is updated with the
+// nullifier as both key and value. A nullifier is fresh when its slot is empty (0). A fresh
+// nullifier whose slot is taken by a different nullifier cannot be proved fresh.
+
+/// Nullifier of `secret` and `index`
+pub fn poseidon_nullifier(
+    secret: FieldElement,
+    index: FieldElement,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, BulletproofError> {
+    Poseidon_hash_2(vec![secret, index], params, sbox)
+}
+
+/// Constraints for proving that the nullifier of hidden `secret` and `index` is not in the
+/// nullifier set with root `nullifier_set_root`. `nonmembership_witness` are the proof nodes, 3 per
+/// level, of the empty leaf at the nullifier's slot as returned by `VanillaSparseMerkleTree4::get`
+/// so the depth of the tree is a third of their number. The nullifier is decomposed into bits
+/// with a unique decomposition and its slot is made of the low bits. `tree_hash` should already be
+/// set up. Returns the nullifier so the caller can reveal it.
+pub fn poseidon_fresh_nullifier_gadget<
+    CS: ConstraintSystem,
+    MTHC: Arity4MerkleTreeHashConstraints,
+>(
+    cs: &mut CS,
+    secret: Variable,
+    index: Variable,
+    nullifier_set_root: &FieldElement,
+    nonmembership_witness: Vec<Variable>,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    tree_hash: &mut MTHC,
+) -> Result<LinearCombination, R1CSError> {
+    let depth = nonmembership_witness.len() / 3;
+    if depth == 0 || nonmembership_witness.len() % 3 != 0 || 2 * depth > CurveOrder.nbits() {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Non-membership witness of {} nodes does not fit a 4-ary tree of depth at most {}",
+                nonmembership_witness.len(),
+                CurveOrder.nbits() / 2
+            ),
+        }
+        .into());
+    }
+
+    let nullifier = Poseidon_hash_2_constraints::<CS>(
+        cs,
+        vec![secret.into(), index.into()],
+        FieldElement::from(CAP_CONST_W_3).into(),
+        params,
+        sbox_type,
+    )?
+    .simplify();
+
+    // Slot is the low 2*depth bits of the nullifier
+    let bits = canonical_bits_gadget(cs, nullifier.clone())?;
+    let mut exp_2 = FieldElement::one();
+    let mut terms = vec![];
+    for b in &bits[..2 * depth] {
+        terms.push((b.variable, exp_2.clone()));
+        exp_2 = &exp_2 + &exp_2;
+    }
+    let slot = allocate_lc_as_quantity(cs, terms.iter().collect())?;
+
+    vanilla_merkle_merkle_tree_4_verif_gadget(
+        cs,
+        depth,
+        nullifier_set_root,
+        LeafValueType::Known(FieldElement::zero()),
+        slot,
+        nonmembership_witness,
+        tree_hash,
+    )?;
+    Ok(nullifier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::gadgets::helper_constraints::constrain_lc_with_scalar;
+    use crate::r1cs::gadgets::helper_constraints::poseidon::CAP_CONST_W_5;
+    use crate::r1cs::gadgets::helper_constraints::sparse_merkle_tree_4_ary::{
+        DbVal4ary, ProofNode4ary, VanillaSparseMerkleTree4,
+    };
+    use crate::r1cs::gadgets::merkle_tree_hash::{PoseidonHash4, PoseidonHashConstraints};
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use crate::utils::hash_db::InMemoryHashDb;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    #[test]
+    fn test_poseidon_fresh_nullifier() {
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds_3, partial_rounds_5) = (4, 4, 55, 56);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds_3, partial_rounds_5) = (4, 4, 55, 56);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds_3, partial_rounds_5) = (4, 4, 55, 56);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds_3, partial_rounds_5) = (4, 4, 55, 56);
+
+        let params = PoseidonParams::new(3, full_b, full_e, partial_rounds_3).unwrap();
+        let tree_params = PoseidonParams::new(5, full_b, full_e, partial_rounds_5).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 8192).into();
+        let H: G1Vector = get_generators("H", 8192).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        // Nullifier set with some spent nullifiers
+        let mut db = InMemoryHashDb::<DbVal4ary>::new();
+        let tree_hash = PoseidonHash4 {
+            params: &tree_params,
+            sbox: sbox_type,
+        };
+        let depth = 8;
+        let mut tree = VanillaSparseMerkleTree4::new(&tree_hash, depth, &mut db).unwrap();
+        let secret = FieldElement::random();
+        let spent_index = FieldElement::from(1u64);
+        let fresh_index = FieldElement::from(2u64);
+        for index in vec![&spent_index, &FieldElement::from(3u64)] {
+            let nf = poseidon_nullifier(secret.clone(), index.clone(), &params, sbox_type).unwrap();
+            tree.update(&nf, nf.clone(), &mut db).unwrap();
+        }
+
+        for (index, expected) in vec![(fresh_index, true), (spent_index, false)] {
+            let nullifier =
+                poseidon_nullifier(secret.clone(), index.clone(), &params, sbox_type).unwrap();
+            let mut proof_nodes = Some(Vec::<ProofNode4ary>::new());
+            let slot_value = tree.get(&nullifier, &mut proof_nodes, &db).unwrap();
+            assert_eq!(slot_value.is_zero(), expected);
+            let proof_nodes = proof_nodes.unwrap();
+
+            let (proof, commitments) = {
+                let mut prover_transcript = Transcript::new(b"FreshNullifier");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let mut hash_constraints =
+                    PoseidonHashConstraints::new(&tree_params, sbox_type, CAP_CONST_W_5);
+                hash_constraints.prover_setup(&mut prover).unwrap();
+                let mut comms = vec![];
+                let mut vars = vec![];
+                for v in vec![secret.clone(), index.clone()]
+                    .into_iter()
+                    .chain(proof_nodes.iter().flat_map(|p| p.to_vec()))
+                {
+                    let (c, var) = prover.commit(v, FieldElement::random());
+                    comms.push(c);
+                    vars.push(var);
+                }
+                let witness = vars.split_off(2);
+                let nf = poseidon_fresh_nullifier_gadget(
+                    &mut prover,
+                    vars[0],
+                    vars[1],
+                    &tree.root,
+                    witness,
+                    &params,
+                    sbox_type,
+                    &mut hash_constraints,
+                )
+                .unwrap();
+                assert_eq!(prover.evaluate_lc(&nf).unwrap(), nullifier);
+                constrain_lc_with_scalar(&mut prover, nf, &nullifier);
+                (prover.prove(&G, &H).unwrap(), comms)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"FreshNullifier");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let mut hash_constraints =
+                PoseidonHashConstraints::new(&tree_params, sbox_type, CAP_CONST_W_5);
+            hash_constraints
+                .verifier_setup(&mut verifier, Some(&g), Some(&h))
+                .unwrap();
+            let mut vars = commitments
+                .into_iter()
+                .map(|c| verifier.commit(c))
+                .collect::<Vec<_>>();
+            let witness = vars.split_off(2);
+            let nf = poseidon_fresh_nullifier_gadget(
+                &mut verifier,
+                vars[0],
+                vars[1],
+                &tree.root,
+                witness,
+                &params,
+                sbox_type,
+                &mut hash_constraints,
+            )
+            .unwrap();
+            constrain_lc_with_scalar(&mut verifier, nf, &nullifier);
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+}
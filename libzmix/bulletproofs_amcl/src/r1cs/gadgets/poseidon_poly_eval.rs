@@ -0,0 +1,127 @@
+use super::helper_constraints::constrain_lc_with_scalar;
+use super::helper_constraints::poseidon::{PoseidonParams, Poseidon_hash_n_constraints, SboxType};
+use crate::errors::R1CSError;
+use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
+use amcl_wrapper::field_elem::FieldElement;
+
+// A polynomial `p(x) = coeffs[0] + coeffs[1]*x + ... + coeffs[n-1]*x^{n-1}` is committed as
+// `Poseidon_hash_n(coeffs)` so polynomials of degree up to `width - 2` can be committed.
+
+/// Constraints for proving that the hidden coefficients hash to `commitment` and the polynomial
+/// evaluates to `y` at `z`. As `z` is public, the Horner evaluation is a linear combination of the
+/// coefficients and does not need any multiplication gates.
+pub fn poseidon_poly_eval_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    coeffs: Vec<Variable>,
+    z: FieldElement,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+    commitment: &FieldElement,
+    y: &FieldElement,
+) -> Result<(), R1CSError> {
+    // Horner evaluation, (...((c_{n-1}*z + c_{n-2})*z + c_{n-3})...)*z + c_0
+    let mut eval = LinearCombination::default();
+    for c in coeffs.iter().rev() {
+        eval = &z * eval + *c;
+    }
+    constrain_lc_with_scalar::<CS>(cs, eval, y);
+
+    let hash = Poseidon_hash_n_constraints::<CS>(
+        cs,
+        coeffs.into_iter().map(|c| c.into()).collect(),
+        params,
+        sbox_type,
+    )?;
+    constrain_lc_with_scalar::<CS>(cs, hash, commitment);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::gadgets::helper_constraints::poseidon::Poseidon_hash_n;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    #[test]
+    fn test_poseidon_poly_eval() {
+        let width = 5;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let G: G1Vector = get_generators("G", 1024).into();
+        let H: G1Vector = get_generators("H", 1024).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        // Degree 3 polynomial
+        let coeffs = (0..4).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let commitment = Poseidon_hash_n(coeffs.clone(), &params, sbox_type).unwrap();
+        let z = FieldElement::random();
+        let mut y = FieldElement::zero();
+        let mut z_i = FieldElement::one();
+        for c in &coeffs {
+            y += c * &z_i;
+            z_i = &z_i * &z;
+        }
+
+        for (eval, expected) in vec![(y.clone(), true), (y + FieldElement::one(), false)] {
+            let (proof, commitments) = {
+                let mut prover_transcript = Transcript::new(b"PoseidonPolyEval");
+                let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+                let mut comms = vec![];
+                let mut vars = vec![];
+                for c in &coeffs {
+                    let (com, var) = prover.commit(c.clone(), FieldElement::random());
+                    comms.push(com);
+                    vars.push(var);
+                }
+                poseidon_poly_eval_gadget(
+                    &mut prover,
+                    vars,
+                    z.clone(),
+                    &params,
+                    sbox_type,
+                    &commitment,
+                    &eval,
+                )
+                .unwrap();
+                (prover.prove(&G, &H).unwrap(), comms)
+            };
+
+            let mut verifier_transcript = Transcript::new(b"PoseidonPolyEval");
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let vars = commitments
+                .into_iter()
+                .map(|c| verifier.commit(c))
+                .collect();
+            poseidon_poly_eval_gadget(
+                &mut verifier,
+                vars,
+                z.clone(),
+                &params,
+                sbox_type,
+                &commitment,
+                &eval,
+            )
+            .unwrap();
+            assert_eq!(verifier.verify(&proof, &g, &h, &G, &H).is_ok(), expected);
+        }
+    }
+}
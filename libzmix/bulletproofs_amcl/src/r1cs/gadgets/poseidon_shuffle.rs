@@ -0,0 +1,194 @@
+use super::helper_constraints::poseidon::{
+    domain_tag, PoseidonParams, PoseidonSponge, PoseidonSpongeConstraints, SboxType,
+    DOMAIN_LABEL_SHUFFLE,
+};
+use crate::errors::{R1CSError, R1CSErrorKind};
+use crate::r1cs::{ConstraintSystem, LinearCombination, Variable};
+use amcl_wrapper::field_elem::FieldElement;
+
+/*
+Proves that vector `b` is a permutation of vector `a` without revealing the permutation. The
+polynomials `∏(X - a_i)` and `∏(X - b_i)` are equal iff `b` is a permutation of `a`, so it is
+sufficient to check them at a random point `x` (Schwartz-Zippel). `x` is derived with Poseidon
+from the elements of both vectors so the prover cannot choose the vectors after `x`. A
+`PoseidonSponge` with capacity 1 absorbs the domain tag of `DOMAIN_LABEL_SHUFFLE`, the number of
+elements, the elements of `a` and then the elements of `b`, and `x` is the first squeezed element.
+*/
+
+/// Compute the challenge `x` for the vectors `a` and `b`
+pub fn poseidon_shuffle_challenge(
+    a: &[FieldElement],
+    b: &[FieldElement],
+    params: &PoseidonParams,
+    sbox: &SboxType,
+) -> Result<FieldElement, R1CSError> {
+    check_lengths(a.len(), b.len())?;
+    let mut sponge = PoseidonSponge::new(params, sbox, 1)?;
+    sponge.absorb(&[
+        domain_tag(DOMAIN_LABEL_SHUFFLE),
+        FieldElement::from(a.len() as u64),
+    ]);
+    sponge.absorb(a);
+    sponge.absorb(b);
+    Ok(sponge.squeeze(1).remove(0))
+}
+
+/// Constraints for proving that hidden `b` is a permutation of hidden `a`. The challenge is
+/// computed in the circuit as in `poseidon_shuffle_challenge` and then
+/// `∏(x - a_i) == ∏(x - b_i)` is enforced with `2*(n-1)` multiplication gates.
+pub fn poseidon_shuffle_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    a: Vec<Variable>,
+    b: Vec<Variable>,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    check_lengths(a.len(), b.len())?;
+
+    let mut inputs: Vec<LinearCombination> = vec![
+        domain_tag(DOMAIN_LABEL_SHUFFLE).into(),
+        FieldElement::from(a.len() as u64).into(),
+    ];
+    inputs.extend(a.iter().chain(b.iter()).map(|v| (*v).into()));
+
+    let mut sponge = PoseidonSpongeConstraints::new(params, sbox_type, 1)?;
+    sponge.absorb(cs, &inputs)?;
+    let x = sponge.squeeze(cs, 1)?.remove(0);
+
+    let product_a = product_of_differences(cs, &x, &a);
+    let product_b = product_of_differences(cs, &x, &b);
+    cs.constrain(product_a - product_b);
+    Ok(())
+}
+
+/// Returns `∏(x - v_i)`
+fn product_of_differences<CS: ConstraintSystem>(
+    cs: &mut CS,
+    x: &LinearCombination,
+    vals: &[Variable],
+) -> LinearCombination {
+    let mut product = x.clone() - vals[0];
+    for v in &vals[1..] {
+        let (_, _, o) = cs.multiply(product, x.clone() - *v);
+        product = o.into();
+    }
+    product
+}
+
+fn check_lengths(len_a: usize, len_b: usize) -> Result<(), R1CSError> {
+    if len_a == 0 || len_a != len_b {
+        return Err(R1CSErrorKind::GadgetError {
+            description: format!(
+                "Vectors should be non-empty and of same length but are of length {} and {}",
+                len_a, len_b
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    fn check_shuffle(
+        a: Vec<FieldElement>,
+        b: Vec<FieldElement>,
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 2048).into();
+        let H: G1Vector = get_generators("H", 2048).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+        let n = a.len();
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"PoseidonShuffle");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let mut comms = vec![];
+            let mut vars = vec![];
+            for v in a.into_iter().chain(b.into_iter()) {
+                let (c, var) = prover.commit(v, FieldElement::random());
+                comms.push(c);
+                vars.push(var);
+            }
+            let vars_b = vars.split_off(n);
+            poseidon_shuffle_gadget(&mut prover, vars, vars_b, params, sbox_type).unwrap();
+            (prover.prove(&G, &H).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"PoseidonShuffle");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut vars = commitments
+            .into_iter()
+            .map(|c| verifier.commit(c))
+            .collect::<Vec<_>>();
+        let vars_b = vars.split_off(n);
+        poseidon_shuffle_gadget(&mut verifier, vars, vars_b, params, sbox_type).unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_poseidon_shuffle() {
+        let width = 5;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 56);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let a = (0..6).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let shuffled = vec![
+            a[3].clone(),
+            a[0].clone(),
+            a[5].clone(),
+            a[1].clone(),
+            a[4].clone(),
+            a[2].clone(),
+        ];
+        assert_ne!(
+            poseidon_shuffle_challenge(&a, &shuffled, &params, sbox_type).unwrap(),
+            poseidon_shuffle_challenge(&shuffled, &a, &params, sbox_type).unwrap()
+        );
+        assert!(poseidon_shuffle_challenge(&a, &a[1..], &params, sbox_type).is_err());
+
+        assert!(check_shuffle(
+            a.clone(),
+            shuffled.clone(),
+            &params,
+            sbox_type
+        ));
+
+        // One element replaced
+        let mut not_permutation = shuffled.clone();
+        not_permutation[2] = FieldElement::random();
+        assert!(!check_shuffle(
+            a.clone(),
+            not_permutation,
+            &params,
+            sbox_type
+        ));
+
+        // Same set of elements but with different multiplicities
+        let mut repeated = shuffled;
+        repeated[0] = repeated[1].clone();
+        assert!(!check_shuffle(a, repeated, &params, sbox_type));
+    }
+}
@@ -0,0 +1,205 @@
+use super::helper_constraints::allocate_lc_as_quantity;
+use super::helper_constraints::non_zero::is_nonzero_gadget;
+use super::helper_constraints::poseidon::{
+    PoseidonParams, Poseidon_hash_1_constraints, SboxType, CAP_CONST_1_W_3,
+};
+use crate::errors::R1CSError;
+use crate::r1cs::linear_combination::AllocatedQuantity;
+use crate::r1cs::{ConstraintSystem, LinearCombination};
+use amcl_wrapper::field_elem::FieldElement;
+
+/* This constraint system has linear (in ring size) cost like set membership and should only be
+used for small rings.
+*/
+
+/// Constraints for proving knowledge of the secret key of one of the public keys of the ring
+/// without revealing which. The public key is `Poseidon_hash_1(secret_key)`.
+/// The public key is derived in the circuit and then it is proved that the product of differences
+/// of the public key with each key of the ring is 0, i.e. the public key is one of the ring.
+pub fn ring_membership_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    secret_key: AllocatedQuantity,
+    public_keys: &[FieldElement],
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<(), R1CSError> {
+    let pk = Poseidon_hash_1_constraints::<CS>(
+        cs,
+        secret_key.variable.into(),
+        FieldElement::from(CAP_CONST_1_W_3).into(),
+        params,
+        sbox_type,
+    )?
+    .simplify();
+
+    // Accumulates product of `pk - public_keys[i]`
+    let mut product: LinearCombination = FieldElement::one().into();
+    for public_key in public_keys {
+        let (_, _, o) = cs.multiply(product, pk.clone() - public_key.clone());
+        product = o.into();
+    }
+
+    // Ensure the product is 0
+    cs.constrain(product);
+
+    Ok(())
+}
+
+/// Constraints for proving that `value` is none of the elements of `set`, the complement of set
+/// membership, e.g. for checking a committed value against a blacklist. Each difference
+/// `value - set[i]` is allocated and proved non-zero with `is_nonzero_gadget`.
+pub fn none_of_n_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    value: AllocatedQuantity,
+    set: &[FieldElement],
+) -> Result<(), R1CSError> {
+    for elem in set {
+        let diff =
+            allocate_lc_as_quantity(cs, LinearCombination::from(value.variable) - elem.clone())?;
+        // Prover assigns 0 as inverse of 0 which fails the constraints
+        let diff_inv = cs.allocate(diff.assignment.as_ref().map(|d| {
+            if d.is_zero() {
+                FieldElement::zero()
+            } else {
+                d.inverse()
+            }
+        }))?;
+        is_nonzero_gadget(cs, diff.variable, diff_inv)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs::gadgets::helper_constraints::poseidon::Poseidon_hash_1;
+    use crate::r1cs::{Prover, Verifier};
+    use crate::utils::get_generators;
+    use amcl_wrapper::group_elem::GroupElement;
+    use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+    use merlin::Transcript;
+
+    fn check_ring_membership(
+        secret_key: FieldElement,
+        public_keys: &[FieldElement],
+        params: &PoseidonParams,
+        sbox_type: &SboxType,
+    ) -> bool {
+        let G: G1Vector = get_generators("G", 256).into();
+        let H: G1Vector = get_generators("H", 256).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let (proof, commitment) = {
+            let mut prover_transcript = Transcript::new(b"RingMembership");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let (com, var) = prover.commit(secret_key.clone(), FieldElement::random());
+            let sk = AllocatedQuantity {
+                variable: var,
+                assignment: Some(secret_key),
+            };
+            ring_membership_gadget(&mut prover, sk, public_keys, params, sbox_type).unwrap();
+            (prover.prove(&G, &H).unwrap(), com)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"RingMembership");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let var = verifier.commit(commitment);
+        let sk = AllocatedQuantity {
+            variable: var,
+            assignment: None,
+        };
+        ring_membership_gadget(&mut verifier, sk, public_keys, params, sbox_type).unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    fn check_none_of_n(value: FieldElement, set: &[FieldElement]) -> bool {
+        let G: G1Vector = get_generators("G", 16).into();
+        let H: G1Vector = get_generators("H", 16).into();
+        let g = G1::from_msg_hash("g".as_bytes());
+        let h = G1::from_msg_hash("h".as_bytes());
+
+        let (proof, commitment) = {
+            let mut prover_transcript = Transcript::new(b"NoneOfN");
+            let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+            let (com, var) = prover.commit(value.clone(), FieldElement::random());
+            let v = AllocatedQuantity {
+                variable: var,
+                assignment: Some(value),
+            };
+            none_of_n_gadget(&mut prover, v, set).unwrap();
+            (prover.prove(&G, &H).unwrap(), com)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"NoneOfN");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let var = verifier.commit(commitment);
+        let v = AllocatedQuantity {
+            variable: var,
+            assignment: None,
+        };
+        none_of_n_gadget(&mut verifier, v, set).unwrap();
+        verifier.verify(&proof, &g, &h, &G, &H).is_ok()
+    }
+
+    #[test]
+    fn test_ring_membership() {
+        let width = 3;
+
+        #[cfg(feature = "bls381")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "bn254")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "secp256k1")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        #[cfg(feature = "ed25519")]
+        let (full_b, full_e, partial_rounds) = (4, 4, 55);
+
+        let params = PoseidonParams::new(width, full_b, full_e, partial_rounds).unwrap();
+        let sbox_type = &SboxType::Quint;
+
+        let secret_keys = (0..4).map(|_| FieldElement::random()).collect::<Vec<_>>();
+        let public_keys = secret_keys
+            .iter()
+            .map(|sk| Poseidon_hash_1(sk.clone(), &params, sbox_type).unwrap())
+            .collect::<Vec<_>>();
+
+        for sk in &secret_keys {
+            assert!(check_ring_membership(
+                sk.clone(),
+                &public_keys,
+                &params,
+                sbox_type
+            ));
+        }
+
+        // Secret key whose public key is not in the ring
+        assert!(!check_ring_membership(
+            FieldElement::random(),
+            &public_keys,
+            &params,
+            sbox_type
+        ));
+    }
+
+    #[test]
+    fn test_none_of_n() {
+        for set_size in vec![1, 3] {
+            let set = (0..set_size)
+                .map(|_| FieldElement::random())
+                .collect::<Vec<_>>();
+
+            // Value not in the set
+            assert!(check_none_of_n(FieldElement::random(), &set));
+
+            // Values in the set
+            for v in &set {
+                assert!(!check_none_of_n(v.clone(), &set));
+            }
+        }
+    }
+}
@@ -620,6 +620,12 @@ impl<'a, 'b> Prover<'a, 'b> {
     pub fn num_multipliers(&self) -> usize {
         self.a_O.len()
     }
+
+    /// Number of variables allocated so far, the left, right and output of each multiplier and
+    /// the committed variables
+    pub fn num_variables(&self) -> usize {
+        self.a_L.len() + self.a_R.len() + self.a_O.len() + self.v.len()
+    }
 }
 
 impl<'a, 'b> ConstraintSystem for Prover<'a, 'b> {
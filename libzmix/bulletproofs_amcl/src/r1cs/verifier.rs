@@ -272,13 +272,68 @@ impl<'a> Verifier<'a> {
 
     /// Consume this `Verifier` and attempt to verify the supplied `proof`.
     pub fn verify(
-        mut self,
+        self,
         proof: &R1CSProof,
         g: &G1,
         h: &G1,
         G: &G1Vector,
         H: &G1Vector,
     ) -> Result<(), R1CSError> {
+        let (scalars, points) = self.verification_scalars_and_points(proof, g, h, G, H)?;
+        let res = G1Vector::inner_product_var_time_with_ref_vecs(
+            points.iter().collect(),
+            scalars.iter().collect(),
+        )
+        .unwrap();
+        if !res.is_identity() {
+            return Err(R1CSErrorKind::VerificationError.into());
+        }
+
+        Ok(())
+    }
+
+    /// Verify several proofs, each with its own `Verifier`, using a single multi-scalar
+    /// multiplication. The check of each proof is scaled by a random weight before adding it to
+    /// the combined check so the batch verifies only if each proof verifies (except with
+    /// negligible probability). Does not say which proof failed, verify individually for that.
+    pub fn batch_verify(
+        instances: Vec<(Verifier, &R1CSProof)>,
+        g: &G1,
+        h: &G1,
+        G: &G1Vector,
+        H: &G1Vector,
+    ) -> Result<(), R1CSError> {
+        let mut all_scalars = vec![];
+        let mut all_points = vec![];
+        for (verifier, proof) in instances {
+            let (scalars, points) = verifier.verification_scalars_and_points(proof, g, h, G, H)?;
+            let weight = FieldElement::random();
+            all_scalars.extend(scalars.into_iter().map(|s| &s * &weight));
+            all_points.extend(points);
+        }
+
+        let res = G1Vector::inner_product_var_time_with_ref_vecs(
+            all_points.iter().collect(),
+            all_scalars.iter().collect(),
+        )
+        .unwrap();
+        if !res.is_identity() {
+            return Err(R1CSErrorKind::VerificationError.into());
+        }
+
+        Ok(())
+    }
+
+    /// Consume this `Verifier` and return the scalars and points whose inner product should be
+    /// the identity for the `proof` to be valid.
+    fn verification_scalars_and_points(
+        mut self,
+        proof: &R1CSProof,
+        g: &G1,
+        h: &G1,
+        G: &G1Vector,
+        H: &G1Vector,
+    ) -> Result<(Vec<FieldElement>, Vec<G1>), R1CSError> {
         // Commit a length _suffix_ for the number of high-level variables.
         // We cannot do this in advance because user can commit variables one-by-one,
         // but this suffix provides safe disambiguation because each variable
@@ -457,12 +512,10 @@ impl<'a> Verifier<'a> {
         arg2.extend(proof.ipp_proof.L.as_slice());
         arg2.extend(proof.ipp_proof.R.as_slice());
 
-        let res = G1Vector::inner_product_var_time_with_ref_vecs(arg2, arg1).unwrap();
-        if !res.is_identity() {
-            return Err(R1CSErrorKind::VerificationError.into());
-        }
-
-        Ok(())
+        Ok((
+            arg1.into_iter().cloned().collect(),
+            arg2.into_iter().cloned().collect(),
+        ))
     }
 }
 
@@ -1,6 +1,7 @@
 extern crate rand;
 
 pub mod hash_db;
+pub mod proving_time;
 pub mod vector_poly;
 
 use amcl_wrapper::field_elem::FieldElement;
@@ -0,0 +1,119 @@
+use crate::errors::BulletproofError;
+use crate::r1cs::gadgets::helper_constraints::poseidon::{
+    PoseidonParams, Poseidon_hash_2, SboxType,
+};
+use crate::r1cs::gadgets::poseidon_hash::{
+    prove_knowledge_of_preimage_of_Poseidon_2, verify_knowledge_of_preimage_of_Poseidon_2,
+};
+use crate::r1cs::{Prover, Verifier};
+use crate::utils::get_generators;
+use amcl_wrapper::field_elem::FieldElement;
+use amcl_wrapper::group_elem::GroupElement;
+use amcl_wrapper::group_elem_g1::{G1Vector, G1};
+use merlin::Transcript;
+use std::time::{Duration, Instant};
+
+/// Per gate (multiplier) cost of proving and verifying on a machine. Used to estimate the time
+/// for proving and verifying a circuit from its gate count without creating the proof.
+#[derive(Clone, Debug)]
+pub struct ProvingCalibration {
+    pub proving_time_per_gate: Duration,
+    pub verification_time_per_gate: Duration,
+}
+
+/// Estimate time to prove a circuit with `gate_count` gates
+pub fn estimate_proving_time(gate_count: usize, calibration: &ProvingCalibration) -> Duration {
+    scale_duration(&calibration.proving_time_per_gate, gate_count)
+}
+
+/// Estimate time to verify a proof of a circuit with `gate_count` gates
+pub fn estimate_verification_time(gate_count: usize, calibration: &ProvingCalibration) -> Duration {
+    scale_duration(&calibration.verification_time_per_gate, gate_count)
+}
+
+fn scale_duration(per_gate: &Duration, gate_count: usize) -> Duration {
+    let nanos = per_gate.as_nanos() * gate_count as u128;
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+/// Measure per gate cost by timing proving and verification of knowledge of preimage of
+/// Poseidon 2:1 hash with width 3 and quint sbox.
+pub fn calibrate() -> Result<ProvingCalibration, BulletproofError> {
+    let hash_params = PoseidonParams::new(3, 4, 4, 55)?;
+    let sbox_type = &SboxType::Quint;
+
+    let G: G1Vector = get_generators("G", 1024).into();
+    let H: G1Vector = get_generators("H", 1024).into();
+    let g = G1::from_msg_hash("g".as_bytes());
+    let h = G1::from_msg_hash("h".as_bytes());
+
+    let inputs = vec![FieldElement::random(), FieldElement::random()];
+    let image = Poseidon_hash_2(inputs.clone(), &hash_params, sbox_type)?;
+
+    let label = b"PoseidonCalibration";
+
+    let start = Instant::now();
+    let mut prover_transcript = Transcript::new(label);
+    let mut prover = Prover::new(&g, &h, &mut prover_transcript);
+    let commitments = prove_knowledge_of_preimage_of_Poseidon_2(
+        inputs,
+        None,
+        &image,
+        &hash_params,
+        sbox_type,
+        Some(&mut rand::thread_rng()),
+        &mut prover,
+    )?;
+    let gate_count = prover.num_multipliers() as u32;
+    let proof = prover.prove(&G, &H)?;
+    let proving_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut verifier_transcript = Transcript::new(label);
+    let mut verifier = Verifier::new(&mut verifier_transcript);
+    verify_knowledge_of_preimage_of_Poseidon_2(
+        &image,
+        &hash_params,
+        sbox_type,
+        commitments,
+        &g,
+        &h,
+        &mut verifier,
+    )?;
+    verifier.verify(&proof, &g, &h, &G, &H)?;
+    let verification_time = start.elapsed();
+
+    Ok(ProvingCalibration {
+        proving_time_per_gate: proving_time / gate_count,
+        verification_time_per_gate: verification_time / gate_count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_scales_linearly() {
+        let calibration = ProvingCalibration {
+            proving_time_per_gate: Duration::from_micros(150),
+            verification_time_per_gate: Duration::from_micros(20),
+        };
+        let base = estimate_proving_time(1000, &calibration);
+        assert_eq!(base, Duration::from_millis(150));
+        for &m in [2u32, 4, 8, 1000].iter() {
+            assert_eq!(
+                estimate_proving_time(1000 * m as usize, &calibration),
+                base * m
+            );
+            assert_eq!(
+                estimate_verification_time(1000 * m as usize, &calibration),
+                estimate_verification_time(1000, &calibration) * m
+            );
+        }
+        assert_eq!(estimate_proving_time(0, &calibration), Duration::new(0, 0));
+    }
+}